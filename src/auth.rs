@@ -0,0 +1,449 @@
+extern crate md5;
+extern crate rpassword;
+
+use log::debug;
+use rand::Rng;
+use reqwest::header::WWW_AUTHENTICATE;
+use reqwest::{Request, Response, StatusCode};
+use serde_json::value::Value;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use url::form_urlencoded;
+use url::Url;
+use yaml_rust::Yaml;
+
+use crate::error::{self, JoatError};
+use crate::jwt_bearer;
+use crate::netrc;
+use crate::redact;
+use crate::sigv4::{self, AwsCredentials};
+use crate::{template, Context};
+
+const AUTHORIZATION_HEADER: &str = "Authorization";
+
+pub enum Auth {
+    NoAuth,
+    Basic {
+        username: String,
+        password: String,
+    },
+    Digest {
+        username: String,
+        password: String,
+    },
+    Bearer {
+        token: String,
+    },
+    ApiKeyQuery {
+        name: String,
+        value: String,
+    },
+    AwsSigV4 {
+        credentials: AwsCredentials,
+        service: String,
+        region: String,
+    },
+}
+
+fn prompt_value(label: &str, hidden: bool) -> error::Result<String> {
+    let prompt = format!("{}: ", label);
+    if hidden {
+        return rpassword::read_password_from_tty(Some(&prompt))
+            .map_err(|e| JoatError::Io(format!("Could not read {} from tty: {}", label, e)));
+    }
+    eprint!("{}", prompt);
+    let mut value = String::new();
+    io::stderr()
+        .flush()
+        .and_then(|_| io::stdin().read_line(&mut value))
+        .map_err(|e| JoatError::Io(format!("Could not read {} from stdin: {}", label, e)))?;
+    Ok(value.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Renders an auth option template, prompting the user when it's missing or empty.
+fn get_credential(
+    auth_yaml: &Yaml,
+    key: &str,
+    hidden: bool,
+    context: &Context,
+) -> error::Result<String> {
+    if let Some(raw_value) = auth_yaml[key].as_str() {
+        let value =
+            template::get_compiled_template_str_with_context(&raw_value.to_string(), context)?;
+        if !value.is_empty() {
+            return Ok(value);
+        }
+    }
+    prompt_value(key, hidden)
+}
+
+fn get_optional_value(
+    auth_yaml: &Yaml,
+    key: &str,
+    context: &Context,
+) -> error::Result<Option<String>> {
+    let raw_value = match auth_yaml[key].as_str() {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let value = template::get_compiled_template_str_with_context(&raw_value.to_string(), context)?;
+    match value.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(value)),
+    }
+}
+
+fn get_auth_yaml<'a>(yaml: &'a Yaml, subcmd_yaml: &'a Yaml) -> &'a Yaml {
+    match subcmd_yaml["auth"].is_badvalue() {
+        true => &yaml["auth"],
+        false => &subcmd_yaml["auth"],
+    }
+}
+
+fn get_netrc_auth(endpoint: &str) -> error::Result<Option<Auth>> {
+    Ok(netrc::get_credentials(endpoint)?.map(|c| Auth::Basic {
+        username: c.login,
+        password: c.password,
+    }))
+}
+
+/// `netrc: true` looks up .netrc only for subcommands without any other auth.
+fn is_netrc_fallback_enabled(yaml: &Yaml, subcmd_yaml: &Yaml) -> bool {
+    if !yaml["oauth"].is_badvalue() {
+        return false;
+    }
+    subcmd_yaml["netrc"]
+        .as_bool()
+        .or(yaml["netrc"].as_bool())
+        .unwrap_or(false)
+}
+
+pub fn get_auth(
+    app_name: &str,
+    yaml: &Yaml,
+    subcmd_yaml: &Yaml,
+    endpoint: &str,
+    context: &Context,
+) -> error::Result<Auth> {
+    let auth_yaml = get_auth_yaml(yaml, subcmd_yaml);
+    if auth_yaml.is_badvalue() {
+        if is_netrc_fallback_enabled(yaml, subcmd_yaml) {
+            return Ok(get_netrc_auth(endpoint)?.unwrap_or(Auth::NoAuth));
+        }
+        return Ok(Auth::NoAuth);
+    }
+    let auth_type = match auth_yaml {
+        Yaml::String(s) => s.as_str(),
+        _ => auth_yaml["type"]
+            .as_str()
+            .ok_or(JoatError::Config(String::from(
+                "Auth type should be a string",
+            )))?,
+    };
+    let auth = match auth_type {
+        "none" => Auth::NoAuth,
+        "basic" => Auth::Basic {
+            username: get_credential(auth_yaml, "username", false, context)?,
+            password: get_credential(auth_yaml, "password", true, context)?,
+        },
+        "digest" => Auth::Digest {
+            username: get_credential(auth_yaml, "username", false, context)?,
+            password: get_credential(auth_yaml, "password", true, context)?,
+        },
+        "bearer" => Auth::Bearer {
+            token: get_credential(auth_yaml, "token", true, context)?,
+        },
+        "netrc" => get_netrc_auth(endpoint)?
+            .ok_or(JoatError::Auth(format!("No .netrc entry for {}", endpoint)))?,
+        "jwt_bearer" => Auth::Bearer {
+            token: jwt_bearer::get_token(app_name, &jwt_bearer::get_config(auth_yaml, context)?)?,
+        },
+        "api_key_query" => Auth::ApiKeyQuery {
+            name: auth_yaml["name"]
+                .as_str()
+                .ok_or(JoatError::Config(String::from(
+                    "Missing api key query param name",
+                )))?
+                .to_string(),
+            value: get_credential(auth_yaml, "value", true, context)?,
+        },
+        "aws_sigv4" => Auth::AwsSigV4 {
+            credentials: sigv4::get_credentials(get_optional_value(
+                auth_yaml, "profile", context,
+            )?)?,
+            service: get_optional_value(auth_yaml, "service", context)?.ok_or(
+                JoatError::Config(String::from("Missing service for aws_sigv4 auth")),
+            )?,
+            region: sigv4::get_region(get_optional_value(auth_yaml, "region", context)?)?,
+        },
+        t => return Err(JoatError::Config(format!("Unknown auth type {}", t))),
+    };
+    add_redacted_credentials(&auth);
+    Ok(auth)
+}
+
+fn add_redacted_credentials(auth: &Auth) {
+    match auth {
+        Auth::Basic { password, .. } | Auth::Digest { password, .. } => {
+            redact::add_secret(password)
+        }
+        Auth::Bearer { token } => redact::add_secret(token),
+        Auth::ApiKeyQuery { value, .. } => redact::add_secret(value),
+        Auth::AwsSigV4 { credentials, .. } => {
+            redact::add_secret(&credentials.secret_access_key);
+            if let Some(t) = &credentials.session_token {
+                redact::add_secret(t);
+            }
+        }
+        Auth::NoAuth => (),
+    }
+}
+
+fn get_basic_header(username: &str, password: &str) -> String {
+    let credentials = format!("{}:{}", username, password);
+    format!("Basic {}", base64::encode(credentials.as_bytes()))
+}
+
+pub fn add_auth_headers(auth: &Auth, headers: &mut HashMap<String, Value>) {
+    let header_value = match auth {
+        Auth::Basic { username, password } => get_basic_header(username, password),
+        Auth::Bearer { token } => format!("Bearer {}", token),
+        _ => return,
+    };
+    headers.insert(
+        AUTHORIZATION_HEADER.to_string(),
+        Value::String(header_value),
+    );
+}
+
+pub fn add_auth_query_param(auth: &Auth, endpoint: String) -> String {
+    match auth {
+        Auth::ApiKeyQuery { name, value } => {
+            let separator = if endpoint.contains("?") { "&" } else { "?" };
+            let query_param: String = form_urlencoded::Serializer::new(String::new())
+                .append_pair(name, value)
+                .finish();
+            format!("{}{}{}", endpoint, separator, query_param)
+        }
+        _ => endpoint,
+    }
+}
+
+/// Signs the final request for auth types that cover the url, headers and body.
+pub fn sign_request(auth: &Auth, request: &mut Request, payload: &[u8]) -> error::Result<()> {
+    if let Auth::AwsSigV4 {
+        credentials,
+        service,
+        region,
+    } = auth
+    {
+        sigv4::sign_request(request, payload, credentials, service, region)?;
+    }
+    Ok(())
+}
+
+fn parse_digest_challenge(challenge: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let trimmed = challenge.trim();
+    if trimmed.len() < 6 || !trimmed[..6].eq_ignore_ascii_case("digest") {
+        return params;
+    }
+    let mut rest = trimmed[6..].trim_start();
+    while !rest.is_empty() {
+        let eq = match rest.find('=') {
+            Some(i) => i,
+            None => break,
+        };
+        let key = rest[..eq].trim().to_lowercase();
+        rest = &rest[eq + 1..];
+        let value;
+        if rest.starts_with('"') {
+            let end = rest[1..].find('"').map(|i| i + 1).unwrap_or(rest.len());
+            value = rest[1..end].to_string();
+            rest = if end < rest.len() {
+                &rest[end + 1..]
+            } else {
+                ""
+            };
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            value = rest[..end].trim().to_string();
+            rest = &rest[end..];
+        }
+        params.insert(key, value);
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+    }
+    params
+}
+
+fn md5_hex(value: &str) -> String {
+    format!("{:x}", md5::compute(value.as_bytes()))
+}
+
+fn get_digest_header(
+    username: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+    challenge: &HashMap<String, String>,
+    cnonce: &str,
+) -> String {
+    let empty = String::new();
+    let realm = challenge.get("realm").unwrap_or(&empty);
+    let nonce = challenge.get("nonce").unwrap_or(&empty);
+    let algorithm = challenge
+        .get("algorithm")
+        .map(|a| a.to_string())
+        .unwrap_or(String::from("MD5"));
+    let qop = challenge.get("qop").and_then(|q| {
+        q.split(',')
+            .map(|o| o.trim())
+            .find(|o| *o == "auth")
+            .map(|o| o.to_string())
+    });
+    let nc = "00000001";
+
+    let mut ha1 = md5_hex(&format!("{}:{}:{}", username, realm, password));
+    if algorithm.eq_ignore_ascii_case("MD5-sess") {
+        ha1 = md5_hex(&format!("{}:{}:{}", ha1, nonce, cnonce));
+    }
+    let ha2 = md5_hex(&format!("{}:{}", method.to_uppercase(), uri));
+    let response = match &qop {
+        Some(q) => md5_hex(&format!(
+            "{}:{}:{}:{}:{}:{}",
+            ha1, nonce, nc, cnonce, q, ha2
+        )),
+        None => md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2)),
+    };
+
+    let mut header = format!(
+        "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+        username, realm, nonce, uri, algorithm, response
+    );
+    if let Some(q) = qop {
+        header.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", q, nc, cnonce));
+    }
+    if let Some(opaque) = challenge.get("opaque") {
+        header.push_str(&format!(", opaque=\"{}\"", opaque));
+    }
+    header
+}
+
+fn get_request_uri(endpoint: &str) -> error::Result<String> {
+    let url = Url::parse(endpoint)
+        .map_err(|e| JoatError::Config(format!("Invalid endpoint {}: {}", endpoint, e)))?;
+    Ok(match url.query() {
+        Some(q) => format!("{}?{}", url.path(), q),
+        None => url.path().to_string(),
+    })
+}
+
+///
+/// Answers a Digest challenge.
+///
+/// Returns the headers to retry the request with when the response is a 401 carrying a Digest
+/// challenge and the subcommand is configured with digest auth.
+///
+pub fn get_digest_retry_headers(
+    auth: &Auth,
+    method: &str,
+    endpoint: &str,
+    response: &Response,
+    headers: &HashMap<String, Value>,
+) -> error::Result<Option<HashMap<String, Value>>> {
+    let (username, password) = match auth {
+        Auth::Digest { username, password } => (username, password),
+        _ => return Ok(None),
+    };
+    if response.status() != StatusCode::UNAUTHORIZED {
+        return Ok(None);
+    }
+    let challenge = match response
+        .headers()
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .map(parse_digest_challenge)
+        .find(|c| c.contains_key("nonce"))
+    {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    debug!("Digest challenge {:?}", challenge);
+
+    let cnonce = format!("{:016x}", rand::thread_rng().gen::<u64>());
+    let uri = get_request_uri(endpoint)?;
+    let digest_header = get_digest_header(username, password, method, &uri, &challenge, &cnonce);
+    let mut retry_headers = headers.clone();
+    retry_headers.insert(
+        AUTHORIZATION_HEADER.to_string(),
+        Value::String(digest_header),
+    );
+    Ok(Some(retry_headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_basic_header() {
+        // Act
+        let header = get_basic_header("Aladdin", "open sesame");
+
+        // Assert
+        assert_eq!("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==", header);
+    }
+
+    #[test]
+    fn test_add_auth_query_param() {
+        // Arrange
+        let auth = Auth::ApiKeyQuery {
+            name: String::from("key"),
+            value: String::from("a b"),
+        };
+
+        // Act
+        let endpoint = add_auth_query_param(&auth, String::from("http://example.com/p?x=1"));
+
+        // Assert
+        assert_eq!("http://example.com/p?x=1&key=a+b", endpoint);
+    }
+
+    #[test]
+    fn test_parse_digest_challenge() {
+        // Arrange
+        let challenge = r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+
+        // Act
+        let params = parse_digest_challenge(challenge);
+
+        // Assert
+        assert_eq!(params["realm"], "testrealm@host.com");
+        assert_eq!(params["qop"], "auth,auth-int");
+        assert_eq!(params["nonce"], "dcd98b7102dd2f0e8b11d0f600bfb0c093");
+        assert_eq!(params["opaque"], "5ccc069c403ebaf9f0171e9517f40e41");
+    }
+
+    #[test]
+    fn test_get_digest_header_rfc2617_example() {
+        // Arrange
+        let challenge = parse_digest_challenge(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        );
+
+        // Act
+        let header = get_digest_header(
+            "Mufasa",
+            "Circle Of Life",
+            "get",
+            "/dir/index.html",
+            &challenge,
+            "0a4f113b",
+        );
+
+        // Assert
+        assert!(header.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(header.contains("qop=auth, nc=00000001, cnonce=\"0a4f113b\""));
+    }
+}
@@ -0,0 +1,583 @@
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+use yaml_rust::Yaml;
+
+use crate::import::{self, get_yaml_string, ImportReport};
+
+// `{{name}}` (Postman) and `{{ _.name }}` (Insomnia)
+const VARIABLE_REGEX: &str = r"\{\{\s*(?:_\.)?([^{}\s]+)\s*\}\}";
+
+///
+/// How the auth of a collection, folder or request maps to a joat config.
+///
+pub enum AuthMapping {
+    // An `auth` block, e.g. `{type: bearer, token: ...}`
+    Auth(Yaml),
+    // A header, e.g. an api key
+    Header(String, String),
+    // An `oauth` block, only supported at the top level
+    Oauth(Yaml),
+}
+
+fn get_options_hash(options: &[(&str, Option<String>)]) -> Yaml {
+    Yaml::Hash(
+        options
+            .iter()
+            .filter_map(|(k, v)| match v {
+                Some(v) if !v.is_empty() => Some((get_yaml_string(k), get_yaml_string(v))),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+impl AuthMapping {
+    ///
+    /// `basic` or `digest` auth.
+    ///
+    pub fn credentials(
+        auth_type: &str,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Self {
+        AuthMapping::Auth(get_options_hash(&[
+            ("type", Some(auth_type.to_string())),
+            ("username", username),
+            ("password", password),
+        ]))
+    }
+
+    pub fn bearer(token: Option<String>) -> Self {
+        AuthMapping::Auth(get_options_hash(&[
+            ("type", Some(String::from("bearer"))),
+            ("token", token),
+        ]))
+    }
+
+    pub fn api_key(name: String, value: String, in_query: bool) -> Self {
+        match in_query {
+            true => AuthMapping::Auth(get_options_hash(&[
+                ("type", Some(String::from("api_key_query"))),
+                ("name", Some(name)),
+                ("value", Some(value)),
+            ])),
+            false => AuthMapping::Header(name, value),
+        }
+    }
+
+    pub fn aws_sigv4(service: Option<String>, region: Option<String>) -> Self {
+        AuthMapping::Auth(get_options_hash(&[
+            ("type", Some(String::from("aws_sigv4"))),
+            ("service", service),
+            ("region", region),
+        ]))
+    }
+
+    pub fn none() -> Self {
+        AuthMapping::Auth(get_yaml_string("none"))
+    }
+
+    ///
+    /// An `oauth` block, only the authorization code and client credentials grants are
+    /// supported.
+    ///
+    pub fn oauth(
+        grant: &str,
+        client_id: Option<String>,
+        client_secret: Option<String>,
+        auth_url: Option<String>,
+        token_url: Option<String>,
+        scopes: Option<String>,
+    ) -> Option<Self> {
+        let grant = match grant {
+            "authorization_code" | "authorization_code_with_pkce" => "authorization_code",
+            "client_credentials" => "client_credentials",
+            _ => return None,
+        };
+        Some(AuthMapping::Oauth(get_options_hash(&[
+            ("grant", Some(grant.to_string())),
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("auth_url", auth_url),
+            ("token_url", token_url),
+            ("scopes", scopes),
+            ("header_key", Some(String::from("Authorization"))),
+            ("header_prefix", Some(String::from("Bearer"))),
+        ])))
+    }
+}
+
+///
+/// A request saved in a collection, with the variables of its values already converted to joat
+/// templates (see Variables::convert).
+///
+pub struct SavedRequest {
+    pub name: String,
+    pub method: String,
+    // Without the query string
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub query: Vec<(String, String)>,
+    // `body` or `form` and the value
+    pub body: Option<(&'static str, Yaml)>,
+    pub auth: Option<AuthMapping>,
+}
+
+///
+/// A folder of a collection, becomes a subcommand group. The collection itself is the root one.
+///
+#[derive(Default)]
+pub struct Folder {
+    pub name: String,
+    pub about: Option<String>,
+    pub auth: Option<AuthMapping>,
+    pub requests: Vec<SavedRequest>,
+    pub folders: Vec<Folder>,
+}
+
+///
+/// Variables of a collection, the ones with a value become `vars` and the other ones (e.g. set
+/// in an environment) are read from environment variables.
+///
+#[derive(Default)]
+pub struct Variables {
+    pub values: BTreeMap<String, String>,
+    // Named sets of values, e.g. Insomnia sub environments, imported as profiles
+    pub profiles: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Variables {
+    fn get_var_reference(&self, name: &str, source: &str, report: &mut ImportReport) -> String {
+        if name.starts_with('$') {
+            report.add_unsupported(source, &format!("dynamic variable {}", name));
+            return String::new();
+        }
+        let is_var =
+            self.values.contains_key(name) || self.profiles.values().any(|p| p.contains_key(name));
+        match is_var {
+            true => format!("{{{{vars.{}}}}}", import::to_snake_case(name)),
+            false => {
+                let env_name = import::to_snake_case(name).to_uppercase();
+                report.add_env_var(&env_name);
+                format!("{{{{env.{}}}}}", env_name)
+            }
+        }
+    }
+
+    ///
+    /// Replaces the `{{name}}` (Postman) and `{{ _.name }}` (Insomnia) variables with joat
+    /// templates, Insomnia template tags like `{% response %}` aren't supported.
+    ///
+    pub fn convert(&self, text: &str, source: &str, report: &mut ImportReport) -> String {
+        let var_re = Regex::new(VARIABLE_REGEX).unwrap();
+        let converted = var_re.replace_all(text, |c: &Captures| {
+            self.get_var_reference(&c[1], source, report)
+        });
+        let tag_re = Regex::new(r"\{%.*?%\}").unwrap();
+        for tag in tag_re.find_iter(&converted) {
+            report.add_unsupported(source, &format!("template tag {}", tag.as_str()));
+        }
+        tag_re.replace_all(&converted, "").to_string()
+    }
+
+    ///
+    /// Converts the variables of the strings of a body.
+    ///
+    pub fn convert_yaml(&self, value: &Yaml, source: &str, report: &mut ImportReport) -> Yaml {
+        match value {
+            Yaml::String(s) => Yaml::String(self.convert(s, source, report)),
+            Yaml::Array(a) => Yaml::Array(
+                a.iter()
+                    .map(|v| self.convert_yaml(v, source, report))
+                    .collect(),
+            ),
+            Yaml::Hash(h) => Yaml::Hash(
+                h.iter()
+                    .map(|(k, v)| (k.clone(), self.convert_yaml(v, source, report)))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+
+    ///
+    /// Parses a JSON object body, variables can be outside strings (e.g. `{"n": {{count}}}`) so
+    /// they're swapped for placeholders while parsing. They become template strings.
+    ///
+    pub fn parse_json_body(
+        &self,
+        raw: &str,
+        source: &str,
+        report: &mut ImportReport,
+    ) -> Option<Yaml> {
+        let var_re = Regex::new(VARIABLE_REGEX).unwrap();
+        let mut variables = Vec::new();
+        let replaced = var_re.replace_all(raw, |c: &Captures| {
+            variables.push(c[0].to_string());
+            let placeholder = format!("joat_var_{}_", variables.len() - 1);
+            // Unquoted vars (e.g. a number) become strings so the body is valid JSON
+            match is_in_json_string(raw, c.get(0).unwrap().start()) {
+                true => placeholder,
+                false => format!("\"{}\"", placeholder),
+            }
+        });
+        let parsed = import::parse_json(&replaced)?;
+        match restore_placeholders(&parsed, &variables) {
+            body @ Yaml::Hash(_) => Some(self.convert_yaml(&body, source, report)),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Vars are rendered without the other vars, the ones they use are inlined.
+    ///
+    fn get_vars_yaml(
+        &self,
+        values: &BTreeMap<String, String>,
+        source: &str,
+        report: &mut ImportReport,
+    ) -> Yaml {
+        let var_re = Regex::new(VARIABLE_REGEX).unwrap();
+        let mut vars = BTreeMap::new();
+        for (k, v) in values.iter() {
+            let inlined = var_re.replace_all(v, |c: &Captures| {
+                values
+                    .get(&c[1])
+                    .or(self.values.get(&c[1]))
+                    .cloned()
+                    .unwrap_or(c[0].to_string())
+            });
+            vars.insert(
+                get_yaml_string(&import::to_snake_case(k)),
+                get_yaml_string(&self.convert(&inlined, source, report)),
+            );
+        }
+        Yaml::Hash(vars)
+    }
+}
+
+fn is_in_json_string(text: &str, position: usize) -> bool {
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in text[..position].chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ => (),
+        }
+    }
+    in_string
+}
+
+fn restore_placeholders(value: &Yaml, variables: &[String]) -> Yaml {
+    match value {
+        Yaml::String(s) => {
+            let mut restored = s.clone();
+            for (i, v) in variables.iter().enumerate() {
+                restored = restored.replace(&format!("joat_var_{}_", i), v);
+            }
+            Yaml::String(restored)
+        }
+        Yaml::Array(a) => Yaml::Array(
+            a.iter()
+                .map(|v| restore_placeholders(v, variables))
+                .collect(),
+        ),
+        Yaml::Hash(h) => Yaml::Hash(
+            h.iter()
+                .map(|(k, v)| {
+                    (
+                        restore_placeholders(k, variables),
+                        restore_placeholders(v, variables),
+                    )
+                })
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+///
+/// Splits a url in its origin (scheme and host, or a leading template like `{{vars.base_url}}`)
+/// and its path, without the query string.
+///
+pub fn split_url(url: &str) -> (String, String) {
+    let url = url.split('?').next().unwrap_or("");
+    let origin_re = Regex::new(r"^([a-zA-Z][a-zA-Z0-9+.-]*://[^/]*|\{\{[^}]*\}\})").unwrap();
+    let origin = origin_re
+        .find(url)
+        .map(|m| m.as_str().to_string())
+        .unwrap_or_default();
+    let path = &url[origin.len()..];
+    match path.starts_with('/') || path.is_empty() {
+        true => (origin, path.to_string()),
+        false => (origin, format!("/{}", path)),
+    }
+}
+
+///
+/// The params of the query string of a url, kept encoded.
+///
+pub fn get_query_pairs(url: &str) -> Vec<(String, String)> {
+    match url.split_once('?') {
+        Some((_, q)) => get_encoded_pairs(q.split('#').next().unwrap_or("")),
+        None => Vec::new(),
+    }
+}
+
+///
+/// The `name=value` pairs of a query string, kept encoded.
+///
+pub fn get_encoded_pairs(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((k, v)) => (k.to_string(), v.to_string()),
+            None => (p.to_string(), String::new()),
+        })
+        .collect()
+}
+
+fn collect_origins(folder: &Folder, origins: &mut Vec<(String, usize)>) {
+    for request in folder.requests.iter() {
+        let (origin, _path) = split_url(&request.url);
+        match origins.iter_mut().find(|(o, _)| *o == origin) {
+            Some((_, count)) => *count += 1,
+            None => origins.push((origin, 1)),
+        }
+    }
+    for f in folder.folders.iter() {
+        collect_origins(f, origins);
+    }
+}
+
+///
+/// The most used origin becomes the `base_endpoint`, requests to other ones set their own.
+///
+fn get_base_endpoint(root: &Folder) -> String {
+    let mut origins = Vec::new();
+    collect_origins(root, &mut origins);
+    origins
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(o, _)| o)
+        .unwrap_or_default()
+}
+
+fn get_string_map(pairs: &[(String, String)]) -> Yaml {
+    Yaml::Hash(
+        pairs
+            .iter()
+            .map(|(k, v)| (get_yaml_string(k), get_yaml_string(v)))
+            .collect(),
+    )
+}
+
+fn insert_header(options: &mut BTreeMap<Yaml, Yaml>, name: &str, value: &str) {
+    let headers = options
+        .entry(get_yaml_string("headers"))
+        .or_insert(Yaml::Hash(BTreeMap::new()));
+    if let Yaml::Hash(h) = headers {
+        h.insert(get_yaml_string(name), get_yaml_string(value));
+    }
+}
+
+fn add_auth(
+    options: &mut BTreeMap<Yaml, Yaml>,
+    auth: &Option<AuthMapping>,
+    source: &str,
+    is_top_level: bool,
+    report: &mut ImportReport,
+) {
+    match auth {
+        Some(AuthMapping::Auth(a)) => {
+            options.insert(get_yaml_string("auth"), a.clone());
+        }
+        Some(AuthMapping::Header(name, value)) => insert_header(options, name, value),
+        Some(AuthMapping::Oauth(o)) if is_top_level => {
+            options.insert(get_yaml_string("oauth"), o.clone());
+        }
+        Some(AuthMapping::Oauth(_o)) => report.add_unsupported(
+            source,
+            "oauth2 auth, only supported for the whole collection",
+        ),
+        None => (),
+    }
+}
+
+///
+/// Path segments like `:id` become positional args.
+///
+fn get_path_args(path: &str) -> (String, Vec<Yaml>) {
+    let mut args = Vec::new();
+    let mut used_names = Vec::new();
+    let segments: Vec<String> = path
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) if !name.is_empty() => {
+                let arg_name = import::get_unique_name(
+                    &import::to_snake_case(name).to_uppercase(),
+                    &mut used_names,
+                );
+                args.push(import::get_positional_arg(
+                    &arg_name,
+                    name,
+                    args.len() as i64 + 1,
+                ));
+                format!("{{{{args.{}}}}}", arg_name)
+            }
+            _ => segment.to_string(),
+        })
+        .collect();
+    (segments.join("/"), args)
+}
+
+fn get_request_scmd(
+    request: &SavedRequest,
+    base_endpoint: &str,
+    report: &mut ImportReport,
+) -> Yaml {
+    let mut options = BTreeMap::new();
+    options.insert(get_yaml_string("about"), get_yaml_string(&request.name));
+    let method = request.method.to_lowercase();
+    if method != "get" {
+        options.insert(get_yaml_string("method"), get_yaml_string(&method));
+    }
+    let (origin, path) = split_url(&request.url);
+    if origin != base_endpoint {
+        options.insert(get_yaml_string("base_endpoint"), get_yaml_string(&origin));
+    }
+    let (path, args) = get_path_args(&path);
+    options.insert(get_yaml_string("path"), get_yaml_string(&path));
+    if !args.is_empty() {
+        options.insert(get_yaml_string("args"), Yaml::Array(args));
+    }
+    if !request.headers.is_empty() {
+        options.insert(get_yaml_string("headers"), get_string_map(&request.headers));
+    }
+    if !request.query.is_empty() {
+        options.insert(
+            get_yaml_string("query_params"),
+            get_string_map(&request.query),
+        );
+    }
+    if let Some((key, body)) = &request.body {
+        options.insert(get_yaml_string(key), body.clone());
+    }
+    add_auth(&mut options, &request.auth, &request.name, false, report);
+    Yaml::Hash(options)
+}
+
+fn push_scmd(scmds: &mut Vec<Yaml>, used_names: &mut Vec<String>, name: &str, options: Yaml) {
+    let mut scmd_name = import::to_snake_case(name);
+    if scmd_name.is_empty() {
+        scmd_name = String::from("request");
+    }
+    let mut scmd = BTreeMap::new();
+    scmd.insert(
+        get_yaml_string(&import::get_unique_name(&scmd_name, used_names)),
+        options,
+    );
+    scmds.push(Yaml::Hash(scmd));
+}
+
+fn get_folder_scmds(folder: &Folder, base_endpoint: &str, report: &mut ImportReport) -> Vec<Yaml> {
+    let mut scmds = Vec::new();
+    let mut used_names = Vec::new();
+    for request in folder.requests.iter() {
+        let options = get_request_scmd(request, base_endpoint, report);
+        push_scmd(&mut scmds, &mut used_names, &request.name, options);
+    }
+    for f in folder.folders.iter() {
+        let group_scmds = get_folder_scmds(f, base_endpoint, report);
+        if group_scmds.is_empty() {
+            continue;
+        }
+        let mut options = BTreeMap::new();
+        let about = f.about.clone().unwrap_or(f.name.clone());
+        options.insert(get_yaml_string("about"), get_yaml_string(&about));
+        add_auth(&mut options, &f.auth, &f.name, false, report);
+        options.insert(get_yaml_string("subcommands"), Yaml::Array(group_scmds));
+        push_scmd(&mut scmds, &mut used_names, &f.name, Yaml::Hash(options));
+    }
+    scmds
+}
+
+///
+/// An extension config from a collection: a subcommand per request and a group per folder.
+///
+pub fn get_extension_config(
+    root: &Folder,
+    variables: &Variables,
+    name: &str,
+    report: &mut ImportReport,
+) -> Yaml {
+    let base_endpoint = get_base_endpoint(root);
+    let mut config = BTreeMap::new();
+    config.insert(get_yaml_string("name"), get_yaml_string(name));
+    config.insert(get_yaml_string("version"), get_yaml_string("0.0.0"));
+    let about = root.about.clone().unwrap_or(root.name.clone());
+    config.insert(get_yaml_string("about"), get_yaml_string(&about));
+    config.insert(
+        get_yaml_string("base_endpoint"),
+        get_yaml_string(&base_endpoint),
+    );
+    if !variables.values.is_empty() {
+        config.insert(
+            get_yaml_string("vars"),
+            variables.get_vars_yaml(&variables.values, "vars", report),
+        );
+    }
+    if !variables.profiles.is_empty() {
+        let mut profiles = BTreeMap::new();
+        for (profile_name, values) in variables.profiles.iter() {
+            let mut profile = BTreeMap::new();
+            let vars = variables.get_vars_yaml(values, profile_name, report);
+            profile.insert(get_yaml_string("vars"), vars);
+            profiles.insert(
+                get_yaml_string(&import::to_snake_case(profile_name)),
+                Yaml::Hash(profile),
+            );
+        }
+        config.insert(get_yaml_string("profiles"), Yaml::Hash(profiles));
+    }
+    add_auth(&mut config, &root.auth, &root.name, true, report);
+    let scmds = get_folder_scmds(root, &base_endpoint, report);
+    config.insert(get_yaml_string("subcommands"), Yaml::Array(scmds));
+    Yaml::Hash(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variables_convert() {
+        // Arrange
+        let mut variables = Variables::default();
+        variables
+            .values
+            .insert(String::from("baseUrl"), String::from("https://api.test"));
+        let mut report = ImportReport::default();
+
+        // Act
+        let url = variables.convert(
+            "{{baseUrl}}/users/{{ _.userId }}?t={{$timestamp}}",
+            "get user",
+            &mut report,
+        );
+
+        // Assert
+        assert_eq!(url, "{{vars.base_url}}/users/{{env.USER_ID}}?t=");
+        assert_eq!(
+            split_url(&url),
+            (
+                String::from("{{vars.base_url}}"),
+                String::from("/users/{{env.USER_ID}}")
+            )
+        );
+    }
+}
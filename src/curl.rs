@@ -0,0 +1,672 @@
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use url::form_urlencoded;
+use yaml_rust::Yaml;
+
+use crate::collection;
+use crate::error::{self, JoatError};
+use crate::import::{self, get_scalar_string, get_yaml_string, ImportReport};
+use crate::{template, Context};
+
+// Options taking a value that don't change the request
+const IGNORED_VALUE_OPTIONS: [&str; 22] = [
+    "-o",
+    "--output",
+    "-m",
+    "--max-time",
+    "--connect-timeout",
+    "-w",
+    "--write-out",
+    "--retry",
+    "-x",
+    "--proxy",
+    "--cacert",
+    "--capath",
+    "-E",
+    "--cert",
+    "--key",
+    "-c",
+    "--cookie-jar",
+    "-D",
+    "--dump-header",
+    "-r",
+    "--range",
+    "--limit-rate",
+];
+// Short options taking a value, e.g. `-XPOST` or `-X POST`
+const SHORT_VALUE_OPTIONS: &str = "XHdFuAbeoumwxEcDrT";
+// Headers curl or the http client set
+const IGNORED_HEADERS: [&str; 3] = ["host", "content-length", "accept-encoding"];
+// Environment variables too generic to be what a value means
+const IGNORED_ENV_VARS: [&str; 14] = [
+    "HOME", "PWD", "OLDPWD", "SHELL", "TERM", "USER", "LOGNAME", "LANG", "PATH", "HOSTNAME",
+    "SHLVL", "_", "TMPDIR", "EDITOR",
+];
+// Shorter values are too likely to be part of other values
+const MIN_VAR_VALUE_LENGTH: usize = 3;
+const MIN_ENV_VALUE_LENGTH: usize = 6;
+// Replaces the password of `-u user:password` when no env var holds it
+const PASSWORD_ENV_VAR: &str = "PASSWORD";
+
+///
+/// The parts of a curl command line joat can turn into a subcommand.
+///
+#[derive(Default, Debug)]
+pub struct CurlCommand {
+    pub method: Option<String>,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub data: Vec<String>,
+    // `-F name=value`
+    pub form: Vec<(String, String)>,
+    // `-G`, the data goes in the query string
+    pub is_get: bool,
+    // `-u user:password`
+    pub user: Option<String>,
+    pub auth_type: Option<&'static str>,
+}
+
+impl CurlCommand {
+    pub fn get_method(&self) -> String {
+        match &self.method {
+            Some(m) => m.to_lowercase(),
+            None if !self.is_get && (!self.data.is_empty() || !self.form.is_empty()) => {
+                String::from("post")
+            }
+            None => String::from("get"),
+        }
+    }
+}
+
+///
+/// Splits a command line in words like a shell, for commands pasted as a single argument.
+///
+pub fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => word.extend(chars.next()),
+            (Some(_), c) => word.push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            // Line continuations
+            (None, '\\') => match chars.next() {
+                Some('\n') | None => (),
+                Some(escaped) => {
+                    word.push(escaped);
+                    in_word = true;
+                }
+            },
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(word.clone());
+                    word.clear();
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+///
+/// `--name=value`, `-Xvalue` and `-sSL` become `--name value`, `-X value` and `-s -S -L`.
+///
+fn expand_options(args: &[String]) -> Vec<String> {
+    let mut expanded = Vec::new();
+    let mut is_value = false;
+    for arg in args {
+        if is_value || !arg.starts_with('-') || arg == "-" {
+            expanded.push(arg.clone());
+            is_value = false;
+            continue;
+        }
+        if arg.starts_with("--") {
+            match arg.split_once('=') {
+                Some((name, value)) => {
+                    expanded.push(name.to_string());
+                    expanded.push(value.to_string());
+                }
+                None => expanded.push(arg.clone()),
+            }
+            continue;
+        }
+        for (i, c) in arg.char_indices().skip(1) {
+            expanded.push(format!("-{}", c));
+            if SHORT_VALUE_OPTIONS.contains(c) {
+                let value = &arg[i + c.len_utf8()..];
+                match value.is_empty() {
+                    true => is_value = true,
+                    false => expanded.push(value.to_string()),
+                }
+                break;
+            }
+        }
+    }
+    expanded
+}
+
+///
+/// Parses the args of a curl command (with or without `curl`), unknown options are reported.
+///
+pub fn parse(args: &[String], report: &mut ImportReport) -> error::Result<CurlCommand> {
+    let args = match args {
+        [line] if line.contains(char::is_whitespace) => split_words(line),
+        _ => args.to_vec(),
+    };
+    let args = match args.first().map(|a| a.as_str()) {
+        Some("curl") => &args[1..],
+        _ => &args[..],
+    };
+    let args = expand_options(args);
+    let mut curl = CurlCommand::default();
+    let mut i = 0;
+    while i < args.len() {
+        let option = args[i].as_str();
+        let value = args.get(i + 1).cloned();
+        let missing_value =
+            || JoatError::Config(format!("Missing value of curl option {}", option));
+        let mut takes_value = true;
+        match option {
+            "-X" | "--request" => curl.method = Some(value.ok_or_else(missing_value)?),
+            "-H" | "--header" => {
+                let header = value.ok_or_else(missing_value)?;
+                match header.split_once(':') {
+                    Some((name, v)) => curl
+                        .headers
+                        .push((name.trim().to_string(), v.trim().to_string())),
+                    None => report.add_unsupported(option, &format!("header {}", header)),
+                }
+            }
+            "-d" | "--data" | "--data-raw" | "--data-binary" | "--data-ascii" => {
+                curl.data.push(value.ok_or_else(missing_value)?)
+            }
+            "--data-urlencode" => {
+                let data = value.ok_or_else(missing_value)?;
+                let encoded = match data.split_once('=') {
+                    Some((name, v)) => form_urlencoded::Serializer::new(String::new())
+                        .append_pair(name, v)
+                        .finish(),
+                    None => form_urlencoded::byte_serialize(data.as_bytes()).collect(),
+                };
+                curl.data.push(encoded);
+            }
+            "--json" => {
+                curl.data.push(value.ok_or_else(missing_value)?);
+                curl.headers.push((
+                    String::from("Content-Type"),
+                    String::from("application/json"),
+                ));
+                curl.headers
+                    .push((String::from("Accept"), String::from("application/json")));
+            }
+            "-F" | "--form" | "--form-string" => {
+                let field = value.ok_or_else(missing_value)?;
+                match field.split_once('=') {
+                    Some((name, v)) if option != "--form-string" && v.starts_with(['@', '<']) => {
+                        report.add_unsupported(name, "file form field")
+                    }
+                    Some((name, v)) => curl.form.push((name.to_string(), v.to_string())),
+                    None => report.add_unsupported(option, &format!("form field {}", field)),
+                }
+            }
+            "-u" | "--user" => curl.user = Some(value.ok_or_else(missing_value)?),
+            "--oauth2-bearer" => curl.headers.push((
+                String::from("Authorization"),
+                format!("Bearer {}", value.ok_or_else(missing_value)?),
+            )),
+            "-A" | "--user-agent" => curl
+                .headers
+                .push((String::from("User-Agent"), value.ok_or_else(missing_value)?)),
+            "-e" | "--referer" => curl
+                .headers
+                .push((String::from("Referer"), value.ok_or_else(missing_value)?)),
+            "-b" | "--cookie" => curl
+                .headers
+                .push((String::from("Cookie"), value.ok_or_else(missing_value)?)),
+            "--url" => curl.url = value.ok_or_else(missing_value)?,
+            "-T" | "--upload-file" => {
+                report.add_unsupported(option, "upload file");
+            }
+            "-G" | "--get" => {
+                curl.is_get = true;
+                takes_value = false;
+            }
+            "-I" | "--head" => {
+                curl.method = Some(String::from("HEAD"));
+                takes_value = false;
+            }
+            "--basic" => {
+                curl.auth_type = Some("basic");
+                takes_value = false;
+            }
+            "--digest" => {
+                curl.auth_type = Some("digest");
+                takes_value = false;
+            }
+            o if IGNORED_VALUE_OPTIONS.contains(&o) => (),
+            o if o.starts_with('-') && o.len() > 1 => {
+                // Other options (-s, -L, -k, -v...) change how curl runs, not the request
+                takes_value = false;
+            }
+            url => {
+                curl.url = url.to_string();
+                takes_value = false;
+            }
+        }
+        i += match takes_value {
+            true => 2,
+            false => 1,
+        };
+    }
+    if curl.url.is_empty() {
+        return Err(JoatError::Config(String::from(
+            "No url found in the curl command",
+        )));
+    }
+    Ok(curl)
+}
+
+///
+/// Values of the app vars and of the environment, the longest first, with the template that
+/// references them.
+///
+pub fn get_references(config: &Yaml, env: &Value) -> Vec<(String, String)> {
+    let mut references = Vec::new();
+    for (k, v) in config["vars"].as_hash().into_iter().flatten() {
+        let (name, value) = match (k.as_str(), get_scalar_string(v)) {
+            (Some(n), Some(v)) => (n, v),
+            _ => continue,
+        };
+        if value.len() >= MIN_VAR_VALUE_LENGTH && !value.contains("{{") {
+            references.push((value, format!("{{{{vars.{}}}}}", name)));
+        }
+    }
+    for (name, value) in env.as_object().into_iter().flatten() {
+        let value = value.as_str().unwrap_or("");
+        let is_ignored = IGNORED_ENV_VARS.contains(&name.as_str()) || name.starts_with("JOAT_");
+        let is_referenced = references.iter().any(|(v, _)| v == value);
+        if value.len() >= MIN_ENV_VALUE_LENGTH && !is_ignored && !is_referenced {
+            references.push((value.to_string(), format!("{{{{env.{}}}}}", name)));
+        }
+    }
+    // Vars come first and stay first among equally long values
+    references.sort_by_key(|(v, _)| std::cmp::Reverse(v.len()));
+    references
+}
+
+///
+/// Replaces the values of `references` found in a text, only whole values: `1234` isn't replaced
+/// in `12345`.
+///
+pub fn replace_references(text: &str, references: &[(String, String)]) -> String {
+    if references.is_empty() {
+        return text.to_string();
+    }
+    let pattern = references
+        .iter()
+        .map(|(v, _)| regex::escape(v))
+        .collect::<Vec<String>>()
+        .join("|");
+    let re = Regex::new(&pattern).unwrap();
+    re.replace_all(text, |c: &Captures| {
+        let m = c.get(0).unwrap();
+        let is_alphanumeric = |ch: Option<char>| ch.is_some_and(|ch| ch.is_alphanumeric());
+        let is_bounded = !is_alphanumeric(text[..m.start()].chars().next_back())
+            && !is_alphanumeric(text[m.end()..].chars().next());
+        match (is_bounded, references.iter().find(|(v, _)| v == m.as_str())) {
+            (true, Some((_, template))) => template.clone(),
+            _ => m.as_str().to_string(),
+        }
+    })
+    .to_string()
+}
+
+fn replace_yaml_references(value: &Yaml, references: &[(String, String)]) -> Yaml {
+    match value {
+        Yaml::String(s) => Yaml::String(replace_references(s, references)),
+        Yaml::Array(a) => Yaml::Array(
+            a.iter()
+                .map(|v| replace_yaml_references(v, references))
+                .collect(),
+        ),
+        Yaml::Hash(h) => Yaml::Hash(
+            h.iter()
+                .map(|(k, v)| (k.clone(), replace_yaml_references(v, references)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn get_decoded_pairs(encoded: &str) -> Vec<(String, String)> {
+    form_urlencoded::parse(encoded.as_bytes())
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn get_string_map(pairs: &[(String, String)], references: &[(String, String)]) -> Yaml {
+    Yaml::Hash(
+        pairs
+            .iter()
+            .map(|(k, v)| {
+                (
+                    get_yaml_string(k),
+                    get_yaml_string(&replace_references(v, references)),
+                )
+            })
+            .collect(),
+    )
+}
+
+///
+/// The app `base_endpoint` with its templates rendered, to find it in the curl url.
+///
+fn get_rendered_base_endpoint(config: &Yaml, env: &Value) -> String {
+    let base_endpoint = config["base_endpoint"].as_str().unwrap_or("").to_string();
+    let vars: serde_json::Map<String, Value> = config["vars"]
+        .as_hash()
+        .into_iter()
+        .flatten()
+        .filter_map(|(k, v)| Some((k.as_str()?.to_string(), Value::from(get_scalar_string(v)?))))
+        .collect();
+    let mut context: Context = HashMap::new();
+    context.insert(String::from("env"), env.clone());
+    context.insert(String::from("vars"), Value::Object(vars));
+    template::get_compiled_template_str_with_context(&base_endpoint, &context)
+        .unwrap_or(base_endpoint)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+fn get_body(
+    curl: &CurlCommand,
+    references: &[(String, String)],
+    report: &mut ImportReport,
+) -> Option<(&'static str, Yaml)> {
+    if !curl.form.is_empty() {
+        report.add_unsupported("-F", "multipart form, sent url encoded");
+        return Some(("form", get_string_map(&curl.form, references)));
+    }
+    if curl.data.is_empty() || curl.is_get {
+        return None;
+    }
+    let data = curl.data.join("&");
+    if data.starts_with('@') {
+        report.add_unsupported("-d", "data read from a file");
+        return None;
+    }
+    let is_json = curl
+        .headers
+        .iter()
+        .any(|(k, v)| k.eq_ignore_ascii_case("content-type") && v.contains("json"));
+    let parsed = match is_json || data.trim_start().starts_with('{') {
+        true => import::parse_json(&data),
+        false => None,
+    };
+    match parsed {
+        Some(b @ Yaml::Hash(_)) => Some(("body", replace_yaml_references(&b, references))),
+        _ if !is_json && data.contains('=') => Some((
+            "form",
+            get_string_map(&get_decoded_pairs(&data), references),
+        )),
+        _ => {
+            report.add_unsupported("-d", "data that isn't a JSON object or a url encoded form");
+            None
+        }
+    }
+}
+
+///
+/// A name from the method and the words of the path, e.g. `post_projects_issues` for
+/// `POST /projects/12/issues` or `POST /projects/{{vars.project}}/issues`.
+///
+pub fn get_subcommand_name(method: &str, path: &str) -> String {
+    let words: Vec<&str> = path
+        .split(['/', '?'])
+        .take_while(|s| !s.contains('='))
+        .filter(|s| !s.is_empty() && s.chars().all(|c| c.is_alphabetic() || c == '-' || c == '_'))
+        .collect();
+    match words.is_empty() {
+        true => method.to_string(),
+        false => import::to_snake_case(&format!("{} {}", method, words.join(" "))),
+    }
+}
+
+///
+/// The subcommand options of a curl command, values repeating the app vars or environment
+/// variables become template references.
+///
+pub fn get_subcommand(
+    curl: &CurlCommand,
+    config: &Yaml,
+    env: &Value,
+    report: &mut ImportReport,
+) -> (String, Yaml) {
+    let references = get_references(config, env);
+    let url = match curl.url.contains("://") {
+        true => curl.url.clone(),
+        false => format!("http://{}", curl.url),
+    };
+    let (url, query) = match url.split_once('?') {
+        Some((u, q)) => (u.to_string(), q.split('#').next().unwrap_or("").to_string()),
+        None => (
+            url.split('#').next().unwrap_or("").to_string(),
+            String::new(),
+        ),
+    };
+    let base_endpoint = get_rendered_base_endpoint(config, env);
+    let mut options = BTreeMap::new();
+    let path = match url.strip_prefix(&base_endpoint) {
+        Some(p) if !base_endpoint.is_empty() && (p.is_empty() || p.starts_with('/')) => {
+            p.to_string()
+        }
+        _ => {
+            let (origin, path) = collection::split_url(&url);
+            options.insert(
+                get_yaml_string("base_endpoint"),
+                get_yaml_string(&replace_references(&origin, &references)),
+            );
+            path
+        }
+    };
+    let method = curl.get_method();
+    let templated_path = replace_references(&path, &references);
+    let name = get_subcommand_name(&method, &templated_path);
+    options.insert(
+        get_yaml_string("about"),
+        get_yaml_string(&format!("{} {}", method, path)),
+    );
+    if method != "get" {
+        options.insert(get_yaml_string("method"), get_yaml_string(&method));
+    }
+    options.insert(get_yaml_string("path"), get_yaml_string(&templated_path));
+
+    // Headers the app already sends are left out
+    let headers: Vec<(String, String)> = curl
+        .headers
+        .iter()
+        .filter(|(k, _)| !IGNORED_HEADERS.contains(&k.to_lowercase().as_str()))
+        .map(|(k, v)| (k.clone(), replace_references(v, &references)))
+        .filter(|(k, v)| config["headers"][k.as_str()].as_str() != Some(v.as_str()))
+        .map(|(k, v)| match v.contains("{{") {
+            true => (k, v),
+            // A literal credential, e.g. a token pasted in -H or --oauth2-bearer
+            false => {
+                let value = import::get_credential_header_value(&k, &v, report);
+                (k, value)
+            }
+        })
+        .collect();
+    if !headers.is_empty() {
+        options.insert(get_yaml_string("headers"), get_string_map(&headers, &[]));
+    }
+    // Kept encoded, http::get_endpoint doesn't encode the query params
+    let mut query_params = collection::get_encoded_pairs(&query);
+    if curl.is_get {
+        query_params.extend(collection::get_encoded_pairs(&curl.data.join("&")));
+    }
+    if !query_params.is_empty() {
+        options.insert(
+            get_yaml_string("query_params"),
+            get_string_map(&query_params, &references),
+        );
+    }
+    if let Some((key, body)) = get_body(curl, &references, report) {
+        options.insert(get_yaml_string(key), body);
+    }
+    if let Some(user) = &curl.user {
+        let (username, password) = match user.split_once(':') {
+            Some((u, p)) => (u, Some(p)),
+            None => (user.as_str(), None),
+        };
+        let mut auth = BTreeMap::new();
+        let auth_type = curl.auth_type.unwrap_or("basic");
+        auth.insert(get_yaml_string("type"), get_yaml_string(auth_type));
+        auth.insert(
+            get_yaml_string("username"),
+            get_yaml_string(&replace_references(username, &references)),
+        );
+        // Without a password joat prompts for it
+        if let Some(p) = password {
+            let mut password = replace_references(p, &references);
+            if !password.contains("{{") {
+                report.add_env_var(PASSWORD_ENV_VAR);
+                password = format!("{{{{env.{}}}}}", PASSWORD_ENV_VAR);
+            }
+            auth.insert(get_yaml_string("password"), get_yaml_string(&password));
+        }
+        options.insert(get_yaml_string("auth"), Yaml::Hash(auth));
+    }
+    (name, Yaml::Hash(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_parse() {
+        // Arrange
+        let line = "curl -sSL -XPOST 'https://api.test/v1/items?tag=a%20b' \\\n  -H 'Content-Type: application/json' --data-raw '{\"name\": \"box\"}' -u bob:secret";
+        let mut report = ImportReport::default();
+
+        // Act
+        let curl = parse(&[line.to_string()], &mut report).unwrap();
+
+        // Assert
+        assert_eq!(curl.get_method(), "post");
+        assert_eq!(curl.url, "https://api.test/v1/items?tag=a%20b");
+        assert_eq!(
+            curl.headers,
+            vec![(
+                String::from("Content-Type"),
+                String::from("application/json")
+            )]
+        );
+        assert_eq!(curl.data, vec![String::from("{\"name\": \"box\"}")]);
+        assert_eq!(curl.user, Some(String::from("bob:secret")));
+    }
+
+    #[test]
+    fn test_get_subcommand() {
+        // Arrange
+        let config = YamlLoader::load_from_str(
+            "base_endpoint: https://{{vars.host}}/v1\nvars:\n    host: api.test\n    project: acme\nheaders:\n    Accept: application/json\n",
+        )
+        .unwrap()
+        .remove(0);
+        let env = serde_json::json!({"API_TOKEN": "s3cr3t-token", "SHLVL": "1"});
+        let args: Vec<String> = [
+            "-X",
+            "POST",
+            "https://api.test/v1/projects/acme/issues?label=bug%20fix",
+            "-H",
+            "Accept: application/json",
+            "-H",
+            "Authorization: Bearer s3cr3t-token",
+            "-d",
+            "{\"title\": \"acme broken\", \"project\": \"acmes\"}",
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let mut report = ImportReport::default();
+        let curl = parse(&args, &mut report).unwrap();
+
+        // Act
+        let (name, scmd) = get_subcommand(&curl, &config, &env, &mut report);
+
+        // Assert
+        assert_eq!(name, "post_projects_issues");
+        assert!(scmd["base_endpoint"].is_badvalue());
+        assert_eq!(
+            scmd["path"].as_str(),
+            Some("/projects/{{vars.project}}/issues")
+        );
+        assert_eq!(scmd["query_params"]["label"].as_str(), Some("bug%20fix"));
+        assert!(scmd["headers"]["Accept"].is_badvalue());
+        assert_eq!(
+            scmd["headers"]["Authorization"].as_str(),
+            Some("Bearer {{env.API_TOKEN}}")
+        );
+        assert_eq!(
+            scmd["body"]["title"].as_str(),
+            Some("{{vars.project}} broken")
+        );
+        assert_eq!(scmd["body"]["project"].as_str(), Some("acmes"));
+        assert!(report.env_vars.is_empty());
+    }
+
+    #[test]
+    fn test_get_subcommand_literal_credentials() {
+        // Arrange
+        let config = YamlLoader::load_from_str(
+            "base_endpoint: https://api.test
+",
+        )
+        .unwrap()
+        .remove(0);
+        let args: Vec<String> = [
+            "https://api.test/items?q=a%26b&page=2",
+            "--oauth2-bearer",
+            "abc123",
+            "-u",
+            "bob:hunter2",
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+        let mut report = ImportReport::default();
+        let curl = parse(&args, &mut report).unwrap();
+
+        // Act
+        let (_name, scmd) = get_subcommand(&curl, &config, &serde_json::json!({}), &mut report);
+
+        // Assert
+        assert_eq!(scmd["query_params"]["q"].as_str(), Some("a%26b"));
+        assert_eq!(
+            scmd["headers"]["Authorization"].as_str(),
+            Some("Bearer {{env.AUTHORIZATION}}")
+        );
+        assert_eq!(scmd["auth"]["username"].as_str(), Some("bob"));
+        assert_eq!(scmd["auth"]["password"].as_str(), Some("{{env.PASSWORD}}"));
+        assert_eq!(report.env_vars, vec!["AUTHORIZATION", "PASSWORD"]);
+    }
+}
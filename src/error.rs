@@ -0,0 +1,215 @@
+use serde_json::json;
+use std::fmt;
+use std::panic;
+use std::sync::Mutex;
+
+use crate::redact;
+
+const ERROR_FORMAT_ARG: &str = "--error-format";
+
+///
+/// Errors that stop a command, each kind exits with its own code so wrappers can tell them apart.
+///
+/// Codes follow sysexits.h, 1 is left to scripts and commands reporting a state (e.g. not logged
+/// in).
+///
+#[derive(Debug)]
+pub enum JoatError {
+    Config(String),
+    Template(String),
+    Network(String),
+    Auth(String),
+    Script(String),
+    Io(String),
+    // Panics, reported by the panic hook
+    Internal(String),
+}
+
+pub type Result<T> = ::std::result::Result<T, JoatError>;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+// Errors can happen before the args are parsed by clap, main sets it from the raw args
+static ERROR_FORMAT: Mutex<Option<ErrorFormat>> = Mutex::new(None);
+
+impl JoatError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            JoatError::Config(_) => "config",
+            JoatError::Template(_) => "template",
+            JoatError::Network(_) => "network",
+            JoatError::Auth(_) => "auth",
+            JoatError::Script(_) => "script",
+            JoatError::Io(_) => "io",
+            JoatError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            JoatError::Config(_) => 78,
+            JoatError::Template(_) => 65,
+            JoatError::Network(_) => 69,
+            JoatError::Auth(_) => 77,
+            JoatError::Script(_) => 71,
+            JoatError::Io(_) => 74,
+            JoatError::Internal(_) => 70,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            JoatError::Config(m)
+            | JoatError::Template(m)
+            | JoatError::Network(m)
+            | JoatError::Auth(m)
+            | JoatError::Script(m)
+            | JoatError::Io(m)
+            | JoatError::Internal(m) => m,
+        }
+    }
+}
+
+impl fmt::Display for JoatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} error: {}", self.kind(), self.message())
+    }
+}
+
+impl From<tera::Error> for JoatError {
+    fn from(e: tera::Error) -> JoatError {
+        // Tera keeps the cause of render errors (e.g. a missing variable) in the source chain
+        let mut message = e.to_string();
+        let mut source = ::std::error::Error::source(&e);
+        while let Some(s) = source {
+            message = format!("{}: {}", message, s);
+            source = s.source();
+        }
+        JoatError::Template(message)
+    }
+}
+
+impl From<reqwest::Error> for JoatError {
+    fn from(e: reqwest::Error) -> JoatError {
+        JoatError::Network(e.to_string())
+    }
+}
+
+fn get_error_format_from_args(args: &[String]) -> ErrorFormat {
+    // Args after `--` belong to the subcommand
+    let args = match args.iter().position(|a| a == "--") {
+        Some(end) => &args[..end],
+        None => args,
+    };
+    let mut format = None;
+    for (i, arg) in args.iter().enumerate() {
+        if arg == ERROR_FORMAT_ARG {
+            format = args.get(i + 1).map(|f| f.as_str());
+        } else if let Some(f) = arg.strip_prefix(&format!("{}=", ERROR_FORMAT_ARG)) {
+            format = Some(f);
+        }
+    }
+    match format {
+        Some("json") => ErrorFormat::Json,
+        _ => ErrorFormat::Text,
+    }
+}
+
+pub fn init(args: &[String]) {
+    *ERROR_FORMAT.lock().unwrap() = Some(get_error_format_from_args(args));
+}
+
+fn get_error_format() -> ErrorFormat {
+    ERROR_FORMAT.lock().unwrap().unwrap_or(ErrorFormat::Text)
+}
+
+fn format_error(error: &JoatError, format: ErrorFormat) -> String {
+    let message = redact::redact(error.message());
+    match format {
+        ErrorFormat::Text => format!("{} error: {}", error.kind(), message),
+        ErrorFormat::Json => json!({
+            "error": {
+                "kind": error.kind(),
+                "message": message,
+                "exit_code": error.exit_code(),
+            }
+        })
+        .to_string(),
+    }
+}
+
+///
+/// Prints the error to stderr, in the format selected with `--error-format`, and exits with its
+/// code.
+///
+pub fn exit(error: JoatError) -> ! {
+    eprintln!("{}", format_error(&error, get_error_format()));
+    ::std::process::exit(error.exit_code());
+}
+
+///
+/// Reports the remaining `expect` and `panic!` failures as internal errors instead of a panic
+/// message and exit code 101.
+///
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(s) => s.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(s) => s.clone(),
+                None => String::from("Box<Any>"),
+            },
+        };
+        if let Some(l) = info.location() {
+            log::debug!("Panicked at {}:{}", l.file(), l.line());
+        }
+        exit(JoatError::Internal(message));
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_error_format_from_args() {
+        // Arrange
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+
+        // Act, Assert
+        assert!(get_error_format_from_args(&args(&["app", "show"])) == ErrorFormat::Text);
+        assert!(
+            get_error_format_from_args(&args(&["app", "--error-format", "json", "show"]))
+                == ErrorFormat::Json
+        );
+        assert!(
+            get_error_format_from_args(&args(&["app", "show", "--error-format=json"]))
+                == ErrorFormat::Json
+        );
+        assert!(
+            get_error_format_from_args(&args(&["app", "run", "--", "--error-format", "json"]))
+                == ErrorFormat::Text
+        );
+    }
+
+    #[test]
+    fn test_format_error() {
+        // Arrange
+        let error = JoatError::Network(String::from("Could not reach http://example.com"));
+
+        // Act
+        let text = format_error(&error, ErrorFormat::Text);
+        let json = format_error(&error, ErrorFormat::Json);
+
+        // Assert
+        assert_eq!(text, "network error: Could not reach http://example.com");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            json!({"error": {"kind": "network", "message": "Could not reach http://example.com", "exit_code": 69}})
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use yaml_rust::Yaml;
+
+use crate::collection::{self, Folder, SavedRequest, Variables};
+use crate::error::{self, JoatError};
+use crate::import::{self, get_yaml_string, ImportReport};
+
+// Headers set by the browser or by the http client
+const IGNORED_HEADERS: [&str; 14] = [
+    "host",
+    "connection",
+    "content-length",
+    "accept-encoding",
+    "accept-language",
+    "user-agent",
+    "referer",
+    "origin",
+    "cookie",
+    "pragma",
+    "cache-control",
+    "dnt",
+    "priority",
+    "upgrade-insecure-requests",
+];
+const FORM_MIME_TYPES: [&str; 2] = ["application/x-www-form-urlencoded", "multipart/form-data"];
+const STATIC_EXTENSIONS: [&str; 16] = [
+    "js", "mjs", "css", "map", "html", "htm", "png", "jpg", "jpeg", "gif", "svg", "ico", "webp",
+    "woff", "woff2", "ttf",
+];
+
+fn is_ignored_header(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.starts_with(':') || name.starts_with("sec-") || IGNORED_HEADERS.contains(&name.as_str())
+}
+
+fn is_static_asset(path: &str) -> bool {
+    path.rsplit('/')
+        .next()
+        .and_then(|segment| segment.rsplit_once('.'))
+        .is_some_and(|(_, extension)| {
+            STATIC_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+        })
+}
+
+fn get_pairs(list: &Yaml) -> Vec<(String, String)> {
+    list.as_vec()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| {
+            Some((
+                p["name"].as_str()?.to_string(),
+                p["value"].as_str().unwrap_or("").to_string(),
+            ))
+        })
+        .collect()
+}
+
+fn get_body(
+    post_data: &Yaml,
+    source: &str,
+    report: &mut ImportReport,
+) -> Option<(&'static str, Yaml)> {
+    let mime_type = post_data["mimeType"].as_str().unwrap_or("");
+    let text = post_data["text"].as_str().unwrap_or("");
+    let has_params = post_data["params"].as_vec().is_some_and(|p| !p.is_empty());
+    if has_params || FORM_MIME_TYPES.iter().any(|t| mime_type.starts_with(t)) {
+        let pairs = get_pairs(&post_data["params"]);
+        return Some((
+            "form",
+            Yaml::Hash(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (get_yaml_string(k), get_yaml_string(v)))
+                    .collect(),
+            ),
+        ));
+    }
+    if text.is_empty() {
+        return None;
+    }
+    let parsed = match mime_type.contains("json") {
+        true => import::parse_json(text),
+        false => None,
+    };
+    match parsed {
+        Some(b @ Yaml::Hash(_)) => Some(("body", b)),
+        _ => {
+            report.add_unsupported(source, &format!("{} body", mime_type));
+            None
+        }
+    }
+}
+
+fn get_request(entry: &Yaml, report: &mut ImportReport) -> Option<SavedRequest> {
+    let request = &entry["request"];
+    let method = request["method"].as_str()?.to_string();
+    let url = request["url"].as_str()?.to_string();
+    let (_origin, path) = collection::split_url(&url);
+    let name = format!("{} {}", method.to_lowercase(), path);
+    if !request["cookies"].as_vec().is_none_or(|c| c.is_empty()) {
+        report.add_unsupported(&name, "cookies");
+    }
+    let headers = get_pairs(&request["headers"])
+        .into_iter()
+        .filter(|(k, _)| !is_ignored_header(k))
+        .map(|(k, v)| {
+            let value = import::get_credential_header_value(&k, &v, report);
+            (k, value)
+        })
+        .collect();
+    Some(SavedRequest {
+        method,
+        url: url.clone(),
+        headers,
+        query: collection::get_query_pairs(&url),
+        body: get_body(&request["postData"], &name, report),
+        auth: None,
+        name,
+    })
+}
+
+///
+/// An extension config from the requests recorded in a HAR file (e.g. exported from the browser
+/// dev tools). Static assets and repeated requests are skipped.
+///
+pub fn get_extension_config(
+    har: &Yaml,
+    name: &str,
+    report: &mut ImportReport,
+) -> error::Result<Yaml> {
+    let entries = har["log"]["entries"]
+        .as_vec()
+        .ok_or(JoatError::Config(String::from(
+            "Not a HAR file, the log.entries key is missing",
+        )))?;
+    let mut root = Folder {
+        name: name.to_string(),
+        about: har["log"]["creator"]["name"]
+            .as_str()
+            .map(|c| format!("Requests recorded with {}", c)),
+        ..Default::default()
+    };
+    for entry in entries {
+        let request = match get_request(entry, report) {
+            Some(r) => r,
+            None => continue,
+        };
+        let (_origin, path) = collection::split_url(&request.url);
+        let is_repeated = root.requests.iter().any(|r| {
+            r.method == request.method
+                && collection::split_url(&r.url) == collection::split_url(&request.url)
+        });
+        if is_static_asset(&path) || is_repeated {
+            continue;
+        }
+        root.requests.push(request);
+    }
+    Ok(collection::get_extension_config(
+        &root,
+        &Variables::default(),
+        name,
+        report,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_get_extension_config() {
+        // Arrange
+        let content = r#"{"log": {"entries": [
+            {"request": {"method": "GET", "url": "https://app.test/main.js", "headers": []}},
+            {"request": {
+                "method": "POST", "url": "https://app.test/api/items?draft=1",
+                "headers": [{"name": ":authority", "value": "app.test"}, {"name": "Authorization", "value": "Bearer x"}, {"name": "X-Api-Key", "value": "k"}, {"name": "Accept", "value": "application/json"}],
+                "postData": {"mimeType": "application/json", "text": "{\"name\": \"a\"}"}
+            }},
+            {"request": {"method": "POST", "url": "https://app.test/api/items", "headers": []}}
+        ]}}"#;
+        let har = YamlLoader::load_from_str(content).unwrap().remove(0);
+        let mut report = ImportReport::default();
+
+        // Act
+        let config = get_extension_config(&har, "app", &mut report).unwrap();
+
+        // Assert
+        assert_eq!(config["base_endpoint"].as_str(), Some("https://app.test"));
+        let scmds = config["subcommands"].as_vec().unwrap();
+        assert_eq!(scmds.len(), 1);
+        let items = &scmds[0]["post_api_items"];
+        assert_eq!(items["path"].as_str(), Some("/api/items"));
+        assert_eq!(items["query_params"]["draft"].as_str(), Some("1"));
+        assert_eq!(
+            items["headers"]["Authorization"].as_str(),
+            Some("Bearer {{env.AUTHORIZATION}}")
+        );
+        assert_eq!(
+            items["headers"]["X-Api-Key"].as_str(),
+            Some("{{env.X_API_KEY}}")
+        );
+        assert_eq!(
+            items["headers"]["Accept"].as_str(),
+            Some("application/json")
+        );
+        assert_eq!(report.env_vars, &["AUTHORIZATION", "X_API_KEY"]);
+        assert!(items["headers"][":authority"].is_badvalue());
+        assert_eq!(items["body"]["name"].as_str(), Some("a"));
+    }
+
+    #[test]
+    fn test_get_body_firefox_json_with_empty_params() {
+        // Arrange
+        let post_data = YamlLoader::load_from_str(
+            r#"{"mimeType": "application/json", "params": [], "text": "{\"name\": \"a\"}"}"#,
+        )
+        .unwrap()
+        .remove(0);
+        let mut report = ImportReport::default();
+
+        // Act
+        let body = get_body(&post_data, "post /items", &mut report);
+
+        // Assert
+        let (kind, body) = body.unwrap();
+        assert_eq!(kind, "body");
+        assert_eq!(body["name"].as_str(), Some("a"));
+    }
+}
@@ -1,4 +1,5 @@
 use log::{debug, info};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
 use reqwest::Method;
 use reqwest::Response;
 use serde_json::value::Value;
@@ -6,7 +7,9 @@ use std::collections::HashMap;
 use std::time::Duration;
 use std::vec::Vec;
 
-use crate::{template, Context};
+use crate::auth::{self, Auth};
+use crate::error::{self, JoatError};
+use crate::{redact, template, Context};
 
 fn get_complete_endpoint(base_endpoint: &str, path: &str) -> String {
     let mut endpoint = String::from(base_endpoint);
@@ -33,9 +36,9 @@ fn get_endpoint_with_qp(
     endpoint: String,
     query_params: &HashMap<String, Value>,
     context: &Context,
-) -> String {
+) -> error::Result<String> {
     if endpoint.contains("?") || query_params.is_empty() {
-        return endpoint;
+        return Ok(endpoint);
     }
     let mut param_vec = Vec::new();
     for (key, value) in query_params {
@@ -45,11 +48,10 @@ fn get_endpoint_with_qp(
         let parsed_qp_value = template::get_compiled_template_str_with_context(
             &get_string_from_value(value).to_string(),
             &context,
-        )
-        .expect("Error parsing query param option, check your template");
+        )?;
         param_vec.push((key.clone(), parsed_qp_value));
     }
-    format!("{}?{}", endpoint, stringify(param_vec))
+    Ok(format!("{}?{}", endpoint, stringify(param_vec)))
 }
 
 pub fn get_endpoint(
@@ -57,14 +59,13 @@ pub fn get_endpoint(
     path: &str,
     context: &Context,
     query_params: &HashMap<String, Value>,
-) -> String {
+) -> error::Result<String> {
     let raw_endpoint = get_complete_endpoint(endpoint, path);
-    let endpoint_with_qp = get_endpoint_with_qp(raw_endpoint, query_params, context);
+    let endpoint_with_qp = get_endpoint_with_qp(raw_endpoint, query_params, context)?;
     let parsed_endpoint =
-        template::get_compiled_template_str_with_context(&endpoint_with_qp, &context)
-            .expect(format!("Could not parse endpoint {}", endpoint_with_qp).as_str());
+        template::get_compiled_template_str_with_context(&endpoint_with_qp, &context)?;
 
-    return parsed_endpoint;
+    return Ok(parsed_endpoint);
 }
 
 fn get_method(method: &String) -> Method {
@@ -86,6 +87,34 @@ fn get_method(method: &String) -> Method {
     return Method::GET;
 }
 
+fn get_payload(
+    body: &HashMap<String, Value>,
+    form: &HashMap<String, Value>,
+) -> Option<(Vec<u8>, &'static str)> {
+    if form.len() > 0 {
+        let encoded_form = serde_urlencoded::to_string(form).expect("Could not encode form");
+        return Some((
+            encoded_form.into_bytes(),
+            "application/x-www-form-urlencoded",
+        ));
+    }
+    if body.len() > 0 {
+        let json_body = serde_json::to_vec(body).expect("Could not encode body");
+        return Some((json_body, "application/json"));
+    }
+    return None;
+}
+
+///
+/// The content type of the payload, unless the subcommand sets its own (e.g.
+/// `application/vnd.api+json`). `RequestBuilder::header` appends, it would send both.
+///
+fn set_default_content_type(headers: &mut HeaderMap, content_type: &'static str) {
+    if !headers.contains_key(CONTENT_TYPE) {
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    }
+}
+
 pub fn request(
     method: &String,
     endpoint: &String,
@@ -93,40 +122,67 @@ pub fn request(
     body: &HashMap<String, Value>,
     form: &HashMap<String, Value>,
     timeout: Option<Duration>,
-) -> Response {
-    let client = reqwest::Client::builder().timeout(timeout).build().unwrap();
+    auth: &Auth,
+) -> error::Result<Response> {
+    let client = reqwest::Client::builder().timeout(timeout).build()?;
     let reqwest_method = get_method(&method);
     let mut request = client.request(reqwest_method, endpoint);
-    debug!("headers dict {:?}", headers);
+    debug!("headers dict {}", redact::redact(&format!("{:?}", headers)));
     for (name, value) in headers.iter() {
         let header_value = get_string_from_value(value);
         request = request.header(&name[..], header_value);
     }
 
-    if body.len() > 0 {
-        request = request.json(&body);
+    // Serialize the payload here so signing auth types can hash the final body
+    let (payload, content_type) = match get_payload(body, form) {
+        Some((bytes, content_type)) => (bytes, Some(content_type)),
+        None => (Vec::new(), None),
+    };
+    if payload.len() > 0 {
+        request = request.body(payload.clone());
     }
+    let mut built_request = request.build()?;
+    if let Some(c) = content_type {
+        set_default_content_type(built_request.headers_mut(), c);
+    }
+    auth::sign_request(auth, &mut built_request, &payload)?;
 
-    if form.len() > 0 {
-        request = request.form(&form);
-    }
-    info!("{:?}", request);
-    let response = match request.send() {
-        Ok(t) => t,
-        Err(e) => {
-            println!("Could not get response for endpoint {}", endpoint);
-            println!("Error: {}", e);
-            panic!("Failed request");
-        }
-    };
-    debug!("Response {:?}", response);
-    return response;
+    info!("{}", redact::redact(&format!("{:?}", built_request)));
+    let response = client.execute(built_request).map_err(|e| {
+        JoatError::Network(format!(
+            "Could not get response for endpoint {}: {}",
+            endpoint, e
+        ))
+    })?;
+    debug!("Response {}", redact::redact(&format!("{:?}", response)));
+    return Ok(response);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_set_default_content_type() {
+        // Arrange
+        let mut headers = HeaderMap::new();
+        let mut custom_headers = HeaderMap::new();
+        custom_headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/vnd.api+json"),
+        );
+
+        // Act
+        set_default_content_type(&mut headers, "application/json");
+        set_default_content_type(&mut custom_headers, "application/json");
+
+        // Assert
+        assert_eq!(headers[CONTENT_TYPE], "application/json");
+        let custom_values: Vec<&HeaderValue> =
+            custom_headers.get_all(CONTENT_TYPE).iter().collect();
+        assert_eq!(custom_values, vec!["application/vnd.api+json"]);
+    }
+
     #[test]
     fn test_get_string_from_yaml() {
         // Arrange
@@ -163,7 +219,7 @@ mod tests {
         let context = HashMap::new();
 
         // Act
-        let endpoint = get_endpoint_with_qp(endpoint, &query_params, &context);
+        let endpoint = get_endpoint_with_qp(endpoint, &query_params, &context).unwrap();
 
         // Assert
         assert_eq!("http://example.com/path?foo=bar", endpoint);
@@ -177,7 +233,7 @@ mod tests {
         let context = HashMap::new();
 
         // Act
-        let endpoint = get_endpoint_with_qp(endpoint, &query_params, &context);
+        let endpoint = get_endpoint_with_qp(endpoint, &query_params, &context).unwrap();
 
         // Assert
         assert_eq!("http://example.com/path", endpoint);
@@ -192,7 +248,7 @@ mod tests {
         let query_params = HashMap::new();
 
         // Act
-        let endpoint = get_endpoint(&base_endpoint, &path, &context, &query_params);
+        let endpoint = get_endpoint(&base_endpoint, &path, &context, &query_params).unwrap();
 
         // Assert
         assert_eq!(endpoint, format!("{}{}", base_endpoint, path));
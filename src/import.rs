@@ -0,0 +1,521 @@
+use regex::Regex;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::error::{self, JoatError};
+use crate::{merge, redact};
+
+const INDENT: usize = 4;
+// Keys written first, in this order, the other ones follow alphabetically
+const KEY_ORDER: [&str; 24] = [
+    "name",
+    "version",
+    "author",
+    "about",
+    "base_endpoint",
+    "method",
+    "path",
+    "args",
+    "help",
+    "short",
+    "long",
+    "required",
+    "index",
+    "takes_value",
+    "vars",
+    "profiles",
+    "oauth",
+    "auth",
+    "headers",
+    "query_params",
+    "body",
+    "form",
+    "script",
+    "subcommands",
+];
+// Args joat or clap add to every subcommand
+const RESERVED_ARG_NAMES: [&str; 8] = [
+    "template",
+    "quiet",
+    "raw_response",
+    "help",
+    "version",
+    "profile",
+    "config",
+    "error_format",
+];
+// Schemes kept in front of a credential header value, e.g. `Bearer {{env.AUTHORIZATION}}`
+const AUTH_SCHEMES: [&str; 3] = ["bearer", "basic", "token"];
+const YAML_KEYWORDS: [&str; 9] = ["true", "false", "yes", "no", "on", "off", "null", "~", ""];
+
+///
+/// What an import couldn't convert, printed once the extension is written.
+///
+#[derive(Default)]
+pub struct ImportReport {
+    pub unsupported: Vec<String>,
+    pub env_vars: Vec<String>,
+}
+
+impl ImportReport {
+    pub fn add_unsupported(&mut self, source: &str, message: &str) {
+        let item = format!("{}: {}", source, message);
+        if !self.unsupported.contains(&item) {
+            self.unsupported.push(item);
+        }
+    }
+
+    pub fn add_env_var(&mut self, name: &str) {
+        if !self.env_vars.iter().any(|v| v == name) {
+            self.env_vars.push(name.to_string());
+        }
+    }
+
+    pub fn print(&self) {
+        if !self.env_vars.is_empty() {
+            println!(
+                "Set these environment variables: {}",
+                self.env_vars.join(", ")
+            );
+        }
+        if self.unsupported.is_empty() {
+            return;
+        }
+        println!("Not imported, review the config:");
+        for item in self.unsupported.iter() {
+            println!("  - {}", item);
+        }
+    }
+}
+
+///
+/// Recorded credentials (e.g. Authorization, X-Api-Key or X-CSRF-Token values) become env
+/// references, `Bearer <token>` keeps its scheme.
+///
+pub fn get_credential_header_value(name: &str, value: &str, report: &mut ImportReport) -> String {
+    if !redact::is_sensitive_name(name) {
+        return value.to_string();
+    }
+    let env_name = to_snake_case(name).to_uppercase();
+    report.add_env_var(&env_name);
+    match value.split_once(' ') {
+        Some((scheme, _)) if AUTH_SCHEMES.contains(&scheme.to_lowercase().as_str()) => {
+            format!("{} {{{{env.{}}}}}", scheme, env_name)
+        }
+        _ => format!("{{{{env.{}}}}}", env_name),
+    }
+}
+
+pub fn get_yaml_string(rust_str: &str) -> Yaml {
+    Yaml::String(String::from(rust_str))
+}
+
+///
+/// The string of a scalar, None for maps, lists and nulls.
+///
+pub fn get_scalar_string(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(s) => Some(s.clone()),
+        Yaml::Real(r) => Some(r.clone()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn json_to_yaml(value: &Value) -> Yaml {
+    match value {
+        Value::Null => Yaml::Null,
+        Value::Bool(b) => Yaml::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Yaml::Integer(i),
+            None => Yaml::Real(n.to_string()),
+        },
+        Value::String(s) => Yaml::String(s.clone()),
+        Value::Array(a) => Yaml::Array(a.iter().map(json_to_yaml).collect()),
+        Value::Object(o) => Yaml::Hash(
+            o.iter()
+                .map(|(k, v)| (get_yaml_string(k), json_to_yaml(v)))
+                .collect(),
+        ),
+    }
+}
+
+///
+/// Parses a JSON document with serde_json, yaml-rust rejects valid JSON like the `\/` escape.
+///
+pub fn parse_json(text: &str) -> Option<Yaml> {
+    serde_json::from_str::<Value>(text)
+        .ok()
+        .map(|v| json_to_yaml(&v))
+}
+
+///
+/// Parses an imported file, JSON documents with serde_json and the other ones as YAML.
+///
+pub fn parse_spec(content: &str) -> Result<Yaml, String> {
+    if content.trim_start().starts_with(['{', '[']) {
+        return serde_json::from_str::<Value>(content)
+            .map(|v| json_to_yaml(&v))
+            .map_err(|e| e.to_string());
+    }
+    let spec = YamlLoader::load_from_str(content).map_err(|e| e.to_string())?;
+    Ok(spec.into_iter().next().unwrap_or(Yaml::BadValue))
+}
+
+///
+/// Subcommand and arg names from API names, e.g. `listPets` or `pet-id` to `list_pets` and
+/// `pet_id`.
+///
+pub fn to_snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    let mut previous: Option<char> = None;
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            let is_word_start =
+                c.is_uppercase() && previous.is_some_and(|p| p.is_lowercase() || p.is_numeric());
+            if is_word_start {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else if !snake.is_empty() && !snake.ends_with('_') {
+            snake.push('_');
+        }
+        previous = Some(c);
+    }
+    snake.trim_end_matches('_').to_string()
+}
+
+///
+/// A name not in `used`, suffixed with a number when needed, added to `used`.
+///
+pub fn get_unique_name(name: &str, used: &mut Vec<String>) -> String {
+    let mut unique_name = name.to_string();
+    let mut i = 2;
+    while used.contains(&unique_name) {
+        unique_name = format!("{}_{}", name, i);
+        i += 1;
+    }
+    used.push(unique_name.clone());
+    unique_name
+}
+
+///
+/// The name of an option arg, args clashing with the ones joat adds get a `_param` suffix.
+///
+pub fn get_option_arg_name(name: &str, used: &mut Vec<String>) -> String {
+    let arg_name = to_snake_case(name);
+    match RESERVED_ARG_NAMES.contains(&arg_name.as_str()) || arg_name.is_empty() {
+        true => get_unique_name(&format!("{}_param", arg_name), used),
+        false => get_unique_name(&arg_name, used),
+    }
+}
+
+///
+/// An option arg taking a value, e.g. `--status available`.
+///
+pub fn get_option_arg(name: &str, help: &str, required: bool) -> Yaml {
+    let mut options = BTreeMap::new();
+    options.insert(get_yaml_string("help"), get_yaml_string(help));
+    options.insert(get_yaml_string("long"), get_yaml_string(name));
+    options.insert(get_yaml_string("takes_value"), Yaml::Boolean(true));
+    if required {
+        options.insert(get_yaml_string("required"), Yaml::Boolean(true));
+    }
+    let mut arg = BTreeMap::new();
+    arg.insert(get_yaml_string(name), Yaml::Hash(options));
+    Yaml::Hash(arg)
+}
+
+///
+/// A positional arg, e.g. the id in a path.
+///
+pub fn get_positional_arg(name: &str, help: &str, index: i64) -> Yaml {
+    let mut options = BTreeMap::new();
+    options.insert(get_yaml_string("help"), get_yaml_string(help));
+    options.insert(get_yaml_string("required"), Yaml::Boolean(true));
+    options.insert(get_yaml_string("index"), Yaml::Integer(index));
+    let mut arg = BTreeMap::new();
+    arg.insert(get_yaml_string(name), Yaml::Hash(options));
+    Yaml::Hash(arg)
+}
+
+fn needs_quotes(s: &str) -> bool {
+    let special_start = s.starts_with(|c: char| "{}[]*&!|>'\"%@`-?:,#".contains(c));
+    special_start
+        || s.trim() != s
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.ends_with(':')
+        || s.contains(|c: char| c.is_control())
+        || YAML_KEYWORDS.contains(&s.to_lowercase().as_str())
+        || s.parse::<f64>().is_ok()
+}
+
+fn emit_string(s: &str) -> String {
+    match needs_quotes(s) {
+        true => format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+        false => s.to_string(),
+    }
+}
+
+fn emit_scalar(value: &Yaml) -> String {
+    match value {
+        Yaml::String(s) => emit_string(s),
+        Yaml::Integer(i) => i.to_string(),
+        Yaml::Real(r) => r.clone(),
+        Yaml::Boolean(b) => b.to_string(),
+        Yaml::Hash(_) => String::from("{}"),
+        Yaml::Array(_) => String::from("[]"),
+        _ => String::from("~"),
+    }
+}
+
+fn is_block_string(value: &Yaml) -> bool {
+    value.as_str().is_some_and(|s| s.contains('\n'))
+}
+
+fn push_block_string(lines: &mut Vec<String>, s: &str, indent: usize) {
+    for line in s.trim_end_matches('\n').lines() {
+        match line.is_empty() {
+            true => lines.push(String::new()),
+            false => lines.push(format!("{}{}", " ".repeat(indent), line)),
+        }
+    }
+}
+
+fn get_sorted_entries(hash: &BTreeMap<Yaml, Yaml>) -> Vec<(&Yaml, &Yaml)> {
+    let mut entries: Vec<(&Yaml, &Yaml)> = hash.iter().collect();
+    entries.sort_by_key(|(k, _)| {
+        KEY_ORDER
+            .iter()
+            .position(|o| k.as_str() == Some(o))
+            .unwrap_or(KEY_ORDER.len())
+    });
+    entries
+}
+
+fn push_entry(lines: &mut Vec<String>, prefix: &str, key: &Yaml, value: &Yaml, indent: usize) {
+    let (directive, value) = merge::split(value);
+    let tag = directive.map_or(String::new(), |d| format!(" {}", d.key()));
+    let key = emit_scalar(key);
+    match value {
+        Yaml::Hash(h) if !h.is_empty() => {
+            lines.push(format!("{}{}:{}", prefix, key, tag));
+            push_hash(lines, h, indent + INDENT);
+        }
+        Yaml::Array(a) if !a.is_empty() => {
+            lines.push(format!("{}{}:{}", prefix, key, tag));
+            push_array(lines, a, indent + INDENT);
+        }
+        Yaml::String(s) if is_block_string(value) => {
+            let block = match s.ends_with('\n') {
+                true => "|",
+                false => "|-",
+            };
+            lines.push(format!("{}{}: {}", prefix, key, block));
+            push_block_string(lines, s, indent + INDENT);
+        }
+        _ => lines.push(format!("{}{}:{} {}", prefix, key, tag, emit_scalar(value))),
+    }
+}
+
+fn push_hash(lines: &mut Vec<String>, hash: &BTreeMap<Yaml, Yaml>, indent: usize) {
+    let prefix = " ".repeat(indent);
+    for (k, v) in get_sorted_entries(hash) {
+        push_entry(lines, &prefix, k, v, indent);
+    }
+}
+
+fn push_array(lines: &mut Vec<String>, items: &[Yaml], indent: usize) {
+    let prefix = " ".repeat(indent);
+    for item in items {
+        match item {
+            Yaml::Hash(h) if !h.is_empty() => {
+                // The first entry goes on the dash line, the other ones are aligned with it
+                for (i, (k, v)) in get_sorted_entries(h).into_iter().enumerate() {
+                    let entry_prefix = match i {
+                        0 => format!("{}- ", prefix),
+                        _ => format!("{}  ", prefix),
+                    };
+                    push_entry(lines, &entry_prefix, k, v, indent);
+                }
+            }
+            Yaml::Array(a) if !a.is_empty() => {
+                lines.push(format!("{}-", prefix));
+                push_array(lines, a, indent + INDENT);
+            }
+            Yaml::String(s) if is_block_string(item) => {
+                lines.push(format!("{}- |", prefix));
+                push_block_string(lines, s, indent + INDENT);
+            }
+            _ => lines.push(format!("{}- {}", prefix, emit_scalar(item))),
+        }
+    }
+}
+
+///
+/// Writes a config in the style of the hand written ones: 4 spaces indentation, block strings
+/// for scripts and the most relevant keys first.
+///
+pub fn to_yaml_string(config: &Yaml) -> String {
+    let mut lines = Vec::new();
+    match config {
+        Yaml::Hash(h) => push_hash(&mut lines, h, 0),
+        Yaml::Array(a) => push_array(&mut lines, a, 0),
+        other => lines.push(emit_scalar(other)),
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+///
+/// Writes a generated extension config to `<name>.yml`. With `merge` an existing file is kept
+/// on top of the generated config: edited values, added keys and disabled subcommands stay and
+/// only the new subcommands and keys are added (comments are lost).
+///
+pub fn write_extension(name: &str, config: &Yaml, merge: bool) -> error::Result<String> {
+    let filename = format!("{}.yml", name);
+    let config = match merge && Path::new(&filename).exists() {
+        true => {
+            let content = fs::read_to_string(&filename)
+                .map_err(|e| JoatError::Io(format!("Could not read {}: {}", filename, e)))?;
+            let existing = merge::load_str(&content)
+                .map_err(|e| JoatError::Config(format!("{}: {}", filename, e)))?;
+            merge::merge_layers(&existing, config)
+        }
+        false => config.clone(),
+    };
+    fs::write(&filename, to_yaml_string(&config))
+        .map_err(|e| JoatError::Io(format!("Could not write {}: {}", filename, e)))?;
+    Ok(filename)
+}
+
+pub fn get_subcommand_names(config: &Yaml) -> Vec<String> {
+    config["subcommands"]
+        .as_vec()
+        .into_iter()
+        .flatten()
+        .filter_map(|s| s.as_hash()?.keys().next()?.as_str().map(String::from))
+        .collect()
+}
+
+///
+/// Appends a subcommand at the end of the top level `subcommands` of a config, the rest of the
+/// file (comments included) is kept as it is.
+///
+pub fn append_subcommand(content: &str, name: &str, options: &Yaml) -> error::Result<String> {
+    let mut scmd = BTreeMap::new();
+    scmd.insert(get_yaml_string(name), options.clone());
+    let scmd_string = to_yaml_string(&Yaml::Array(vec![Yaml::Hash(scmd)]));
+    let lines: Vec<&str> = content.lines().collect();
+    let subcommands_re = Regex::new(r"^subcommands:\s*(![a-z]+)?\s*(#.*)?$").unwrap();
+    let start = lines.iter().position(|l| subcommands_re.is_match(l));
+    let (insert_at, indent) = match start {
+        Some(start) => {
+            // The list ends at the next top level key
+            let end = lines[start + 1..]
+                .iter()
+                .position(|l| !l.is_empty() && !l.starts_with([' ', '#', '-']))
+                .map_or(lines.len(), |p| p + start + 1);
+            let indent = lines[start + 1..end]
+                .iter()
+                .find(|l| l.trim_start().starts_with('-'))
+                .map_or(INDENT, |l| l.len() - l.trim_start().len());
+            // Blank lines and comments before the next key belong to it
+            let mut insert_at = end;
+            while insert_at > start + 1
+                && (lines[insert_at - 1].trim().is_empty() || lines[insert_at - 1].starts_with('#'))
+            {
+                insert_at -= 1;
+            }
+            (insert_at, indent)
+        }
+        None => (lines.len(), INDENT),
+    };
+    let mut new_lines: Vec<String> = lines[..insert_at].iter().map(|l| l.to_string()).collect();
+    if start.is_none() {
+        new_lines.push(String::from("subcommands:"));
+    }
+    for line in scmd_string.lines() {
+        match line.is_empty() {
+            true => new_lines.push(String::new()),
+            false => new_lines.push(format!("{}{}", " ".repeat(indent), line)),
+        }
+    }
+    new_lines.extend(lines[insert_at..].iter().map(|l| l.to_string()));
+    new_lines.push(String::new());
+    let new_content = new_lines.join("\n");
+
+    let config = merge::load_str(&new_content).map_err(|e| JoatError::Config(e.to_string()))?;
+    if !get_subcommand_names(&config).iter().any(|n| n == name) {
+        return Err(JoatError::Config(String::from(
+            "Could not append to the subcommands of the config, add the subcommand by hand",
+        )));
+    }
+    Ok(new_content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_snake_case() {
+        // Act, Assert
+        assert_eq!(to_snake_case("listPets"), "list_pets");
+        assert_eq!(to_snake_case("pet-id"), "pet_id");
+        assert_eq!(to_snake_case("GET /v2/users/{id}"), "get_v2_users_id");
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        // Act
+        let json =
+            parse_spec(r#"{"url": "https:\/\/api.test", "n": 1.5, "tags": [1, null]}"#).unwrap();
+        let yaml = parse_spec("url: https://api.test\n").unwrap();
+
+        // Assert
+        assert_eq!(json["url"].as_str(), Some("https://api.test"));
+        assert_eq!(json["n"].as_f64(), Some(1.5));
+        assert_eq!(json["tags"][0].as_i64(), Some(1));
+        assert!(json["tags"][1].is_null());
+        assert_eq!(yaml["url"].as_str(), Some("https://api.test"));
+    }
+
+    #[test]
+    fn test_to_yaml_string_round_trip() {
+        // Arrange
+        let content = "name: api\nsubcommands:\n    - show:\n        about: \"Show: one\"\n        path: \"{{args.ID}}\"\n        args:\n            - ID:\n                required: true\n                index: 1\n    - run:\n        script: |\n            echo a\n            echo b\n";
+        let config = YamlLoader::load_from_str(content).unwrap().remove(0);
+
+        // Act
+        let yaml_string = to_yaml_string(&config);
+
+        // Assert
+        let written = YamlLoader::load_from_str(&yaml_string).unwrap().remove(0);
+        assert_eq!(written, config);
+        assert!(yaml_string.starts_with("name: api\nsubcommands:\n    - show:\n        about:"));
+    }
+
+    #[test]
+    fn test_append_subcommand() {
+        // Arrange
+        let content = "name: api\n# Requests\nsubcommands:\n  - show:\n      path: /a\n\n# Shared headers\nheaders:\n  Accept: application/json\n";
+        let options = YamlLoader::load_from_str("method: post\npath: /b")
+            .unwrap()
+            .remove(0);
+
+        // Act
+        let new_content = append_subcommand(content, "create", &options).unwrap();
+
+        // Assert
+        assert_eq!(
+            new_content,
+            "name: api\n# Requests\nsubcommands:\n  - show:\n      path: /a\n  - create:\n      method: post\n      path: /b\n\n# Shared headers\nheaders:\n  Accept: application/json\n"
+        );
+    }
+}
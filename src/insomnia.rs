@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use yaml_rust::Yaml;
+
+use crate::collection::{self, AuthMapping, Folder, SavedRequest, Variables};
+use crate::error::{self, JoatError};
+use crate::import::{get_scalar_string, get_yaml_string, ImportReport};
+
+const SUPPORTED_TYPES: [&str; 4] = ["workspace", "request_group", "request", "environment"];
+
+///
+/// The enabled `name`/`value` entries of a list, e.g. headers or query params.
+///
+fn get_pairs(
+    list: &Yaml,
+    variables: &Variables,
+    source: &str,
+    report: &mut ImportReport,
+) -> Vec<(String, String)> {
+    list.as_vec()
+        .into_iter()
+        .flatten()
+        .filter(|p| p["disabled"].as_bool() != Some(true) && p["type"].as_str() != Some("file"))
+        .filter_map(|p| {
+            let name = get_scalar_string(&p["name"]).filter(|n| !n.is_empty())?;
+            let value = get_scalar_string(&p["value"]).unwrap_or_default();
+            Some((
+                variables.convert(&name, source, report),
+                variables.convert(&value, source, report),
+            ))
+        })
+        .collect()
+}
+
+fn get_auth(
+    auth: &Yaml,
+    variables: &Variables,
+    source: &str,
+    report: &mut ImportReport,
+) -> Option<AuthMapping> {
+    if auth["disabled"].as_bool() == Some(true) {
+        return None;
+    }
+    let auth_type = auth["type"].as_str()?;
+    let mut param = |key: &str| {
+        get_scalar_string(&auth[key])
+            .filter(|v| !v.is_empty())
+            .map(|v| variables.convert(&v, source, report))
+    };
+    let mapping = match auth_type {
+        "none" => AuthMapping::none(),
+        "basic" | "digest" => {
+            AuthMapping::credentials(auth_type, param("username"), param("password"))
+        }
+        "bearer" => AuthMapping::bearer(param("token")),
+        "netrc" => AuthMapping::Auth(get_yaml_string("netrc")),
+        "apikey" if auth["addTo"].as_str() == Some("cookie") => {
+            report.add_unsupported(source, "api key cookie");
+            return None;
+        }
+        "apikey" => AuthMapping::api_key(
+            param("key").unwrap_or(String::from("X-API-Key")),
+            param("value").unwrap_or_default(),
+            auth["addTo"].as_str() == Some("queryParams"),
+        ),
+        "iam" => AuthMapping::aws_sigv4(param("service"), param("region")),
+        "oauth2" => {
+            let grant = param("grantType").unwrap_or(String::from("authorization_code"));
+            let oauth = AuthMapping::oauth(
+                &grant,
+                param("clientId"),
+                param("clientSecret"),
+                param("authorizationUrl"),
+                param("accessTokenUrl"),
+                param("scope"),
+            );
+            match oauth {
+                Some(o) => o,
+                None => {
+                    report.add_unsupported(source, &format!("oauth2 grant {}", grant));
+                    return None;
+                }
+            }
+        }
+        t => {
+            report.add_unsupported(source, &format!("{} auth", t));
+            return None;
+        }
+    };
+    Some(mapping)
+}
+
+fn get_body(
+    body: &Yaml,
+    variables: &Variables,
+    source: &str,
+    report: &mut ImportReport,
+) -> Option<(&'static str, Yaml)> {
+    let mime_type = body["mimeType"].as_str().unwrap_or("");
+    let text = body["text"].as_str().unwrap_or("");
+    match mime_type {
+        "application/x-www-form-urlencoded" | "multipart/form-data" => {
+            if body["params"]
+                .as_vec()
+                .into_iter()
+                .flatten()
+                .any(|p| p["type"].as_str() == Some("file"))
+            {
+                report.add_unsupported(source, "file form field");
+            }
+            let pairs = get_pairs(&body["params"], variables, source, report);
+            match pairs.is_empty() {
+                true => None,
+                false => Some((
+                    "form",
+                    Yaml::Hash(
+                        pairs
+                            .iter()
+                            .map(|(k, v)| (get_yaml_string(k), get_yaml_string(v)))
+                            .collect(),
+                    ),
+                )),
+            }
+        }
+        // Graphql bodies are a JSON object with the query and the variables
+        "application/json" | "application/graphql" if !text.trim().is_empty() => {
+            match variables.parse_json_body(text, source, report) {
+                Some(b) => Some(("body", b)),
+                _ => {
+                    report.add_unsupported(source, "JSON body that isn't an object");
+                    None
+                }
+            }
+        }
+        _ if text.trim().is_empty() && body["fileName"].is_badvalue() => None,
+        m => {
+            report.add_unsupported(source, &format!("{} body", m));
+            None
+        }
+    }
+}
+
+fn get_request(resource: &Yaml, variables: &Variables, report: &mut ImportReport) -> SavedRequest {
+    let name = resource["name"].as_str().unwrap_or("request").to_string();
+    let url = variables.convert(resource["url"].as_str().unwrap_or(""), &name, report);
+    let mut query = collection::get_query_pairs(&url);
+    query.extend(get_pairs(&resource["parameters"], variables, &name, report));
+    SavedRequest {
+        method: resource["method"].as_str().unwrap_or("GET").to_string(),
+        headers: get_pairs(&resource["headers"], variables, &name, report),
+        query,
+        body: get_body(&resource["body"], variables, &name, report),
+        auth: get_auth(&resource["authentication"], variables, &name, report),
+        url,
+        name,
+    }
+}
+
+fn get_children<'a>(resources: &'a [Yaml], parent_id: &str) -> Vec<&'a Yaml> {
+    resources
+        .iter()
+        .filter(|r| r["parentId"].as_str() == Some(parent_id))
+        .collect()
+}
+
+fn get_folder(
+    resources: &[Yaml],
+    resource: &Yaml,
+    variables: &Variables,
+    report: &mut ImportReport,
+) -> Folder {
+    let name = resource["name"].as_str().unwrap_or("folder");
+    let mut folder = Folder {
+        name: name.to_string(),
+        about: resource["description"]
+            .as_str()
+            .and_then(|d| d.lines().next())
+            .filter(|d| !d.trim().is_empty())
+            .map(|d| d.trim().to_string()),
+        auth: get_auth(&resource["authentication"], variables, name, report),
+        ..Default::default()
+    };
+    let is_folder = resource["_type"].as_str() == Some("request_group");
+    if is_folder
+        && resource["environment"]
+            .as_hash()
+            .is_some_and(|e| !e.is_empty())
+    {
+        report.add_unsupported(name, "folder environment");
+    }
+    let id = resource["_id"].as_str().unwrap_or("");
+    for child in get_children(resources, id) {
+        match child["_type"].as_str() {
+            Some("request") => folder.requests.push(get_request(child, variables, report)),
+            Some("request_group") => folder
+                .folders
+                .push(get_folder(resources, child, variables, report)),
+            _ => (),
+        }
+    }
+    folder
+}
+
+///
+/// Environment data can be nested, `{{ _.api.url }}` uses the `url` of the `api` object.
+///
+fn add_environment_values(data: &Yaml, prefix: &str, values: &mut BTreeMap<String, String>) {
+    for (k, v) in data.as_hash().into_iter().flatten() {
+        let key = match (prefix.is_empty(), get_scalar_string(k)) {
+            (_, None) => continue,
+            (true, Some(k)) => k,
+            (false, Some(k)) => format!("{}.{}", prefix, k),
+        };
+        match v {
+            Yaml::Hash(_) => add_environment_values(v, &key, values),
+            _ => {
+                if let Some(value) = get_scalar_string(v).filter(|v| !v.is_empty()) {
+                    values.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+///
+/// The base environment of the workspace becomes the vars and its sub environments profiles.
+///
+fn get_variables(resources: &[Yaml], workspace_id: &str) -> Variables {
+    let mut variables = Variables::default();
+    let base_environment = get_children(resources, workspace_id)
+        .into_iter()
+        .find(|r| r["_type"].as_str() == Some("environment"));
+    let base_environment = match base_environment {
+        Some(e) => e,
+        None => return variables,
+    };
+    add_environment_values(&base_environment["data"], "", &mut variables.values);
+    let base_id = base_environment["_id"].as_str().unwrap_or("");
+    for environment in get_children(resources, base_id) {
+        let mut values = BTreeMap::new();
+        add_environment_values(&environment["data"], "", &mut values);
+        let environment_name = environment["name"].as_str().unwrap_or("environment");
+        variables
+            .profiles
+            .insert(environment_name.to_string(), values);
+    }
+    variables
+}
+
+///
+/// An extension config from an Insomnia export (format 4, json or yaml), with a subcommand per
+/// request and a group per request folder. The base environment becomes the vars and the sub
+/// environments profiles.
+///
+pub fn get_extension_config(
+    export: &Yaml,
+    name: &str,
+    report: &mut ImportReport,
+) -> error::Result<Yaml> {
+    let resources = match export["resources"].as_vec() {
+        Some(r) if export["_type"].as_str() == Some("export") => r,
+        _ => {
+            return Err(JoatError::Config(String::from(
+                "Not an Insomnia export, the resources key is missing",
+            )))
+        }
+    };
+    let workspace = resources
+        .iter()
+        .find(|r| r["_type"].as_str() == Some("workspace"))
+        .ok_or(JoatError::Config(String::from(
+            "No workspace in the Insomnia export",
+        )))?;
+    for resource in resources.iter() {
+        let resource_type = resource["_type"].as_str().unwrap_or("unknown");
+        if !SUPPORTED_TYPES.contains(&resource_type) {
+            let resource_name = resource["name"].as_str().unwrap_or(resource_type);
+            report.add_unsupported(resource_name, resource_type);
+        }
+    }
+    let workspace_id = workspace["_id"].as_str().unwrap_or("");
+    let variables = get_variables(resources, workspace_id);
+    let root = get_folder(resources, workspace, &variables, report);
+    Ok(collection::get_extension_config(
+        &root, &variables, name, report,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_get_extension_config() {
+        // Arrange
+        let content = r#"{
+            "_type": "export",
+            "__export_format": 4,
+            "resources": [
+                {"_id": "wrk_1", "_type": "workspace", "name": "Shop"},
+                {"_id": "env_1", "_type": "environment", "parentId": "wrk_1", "data": {"api": {"url": "https://shop.test"}}},
+                {"_id": "env_2", "_type": "environment", "parentId": "env_1", "name": "Staging", "data": {"api": {"url": "https://staging.shop.test"}}},
+                {"_id": "fld_1", "_type": "request_group", "parentId": "wrk_1", "name": "Orders"},
+                {
+                    "_id": "req_1", "_type": "request", "parentId": "fld_1", "name": "List orders",
+                    "method": "GET", "url": "{{ _.api.url }}/orders",
+                    "parameters": [{"name": "status", "value": "open"}, {"name": "page", "value": "1", "disabled": true}],
+                    "authentication": {"type": "apikey", "key": "X-Key", "value": "{{ _.key }}", "addTo": "header"}
+                },
+                {"_id": "ck_1", "_type": "cookie_jar", "parentId": "wrk_1", "name": "Default Jar"}
+            ]
+        }"#;
+        let export = YamlLoader::load_from_str(content).unwrap().remove(0);
+        let mut report = ImportReport::default();
+
+        // Act
+        let config = get_extension_config(&export, "shop", &mut report).unwrap();
+
+        // Assert
+        assert_eq!(config["base_endpoint"].as_str(), Some("{{vars.api_url}}"));
+        assert_eq!(
+            config["vars"]["api_url"].as_str(),
+            Some("https://shop.test")
+        );
+        assert_eq!(
+            config["profiles"]["staging"]["vars"]["api_url"].as_str(),
+            Some("https://staging.shop.test")
+        );
+        let list = &config["subcommands"][0]["orders"]["subcommands"][0]["list_orders"];
+        assert_eq!(list["path"].as_str(), Some("/orders"));
+        assert_eq!(list["query_params"]["status"].as_str(), Some("open"));
+        assert!(list["query_params"]["page"].is_badvalue());
+        assert_eq!(list["headers"]["X-Key"].as_str(), Some("{{env.KEY}}"));
+        assert_eq!(
+            report.unsupported,
+            &[String::from("Default Jar: cookie_jar")]
+        );
+    }
+}
@@ -1,41 +1,291 @@
-use clap::{App, Shell};
+use clap::{App, ArgMatches, Shell};
+use serde_json::Value;
+use std::env;
 use std::fs;
+use std::path::{Path, PathBuf};
+use yaml_rust::Yaml;
 
-use crate::{template, Context};
+use crate::error::{self, JoatError};
+use crate::explain;
+use crate::layers::{self, LayerKind};
+use crate::lint::{self, Severity};
+use crate::{
+    curl, har, import, insomnia, oauth, openapi, postman, secrets, template, trust, yaml, Context,
+};
 
-pub fn execute_init(context: Context) {
+pub fn execute_init(context: Context) -> error::Result<()> {
     let init_template = String::from(include_str!("../templates/config_template.yml"));
-    let yaml_str = template::get_compiled_template_str_with_context(&init_template, &context)
-        .expect("Could not create yaml template");
-    let raw_cmd_name = &context["args"]["PROJECT_NAME"];
-    let cmd_name = raw_cmd_name.as_str().expect("Project is not string");
+    let yaml_str = template::get_compiled_template_str_with_context(&init_template, &context)?;
+    let cmd_name = context["args"]["PROJECT_NAME"]
+        .as_str()
+        .ok_or(JoatError::Config(String::from(
+            "PROJECT_NAME should be a string",
+        )))?;
     let filename = format!("{}.yml", cmd_name);
-    fs::write(filename, yaml_str).expect("Unable to write file");
-    println!("Config file {}.yml created", cmd_name);
+    fs::write(&filename, yaml_str)
+        .map_err(|e| JoatError::Io(format!("Unable to write {}: {}", filename, e)))?;
+    print_extension_created(&filename, cmd_name);
+    Ok(())
+}
+
+fn print_extension_created(filename: &str, cmd_name: &str) {
+    println!("Config file {} created", filename);
     println!("To start testing with your extension create a symlink in your PATH targeting joat binaries with name: {}", cmd_name);
 }
 
-pub fn execute_auto_complete(mut app: App, app_name: &str, context: Context) {
-    let selected_shell = &context["args"]["SHELL"]
-        .as_str()
-        .expect("Could not convert shell argument to string");
-    let shell;
-    let lower_selected_shell = selected_shell.to_string().to_lowercase();
-    match lower_selected_shell.as_str() {
-        "zsh" => shell = Shell::Zsh,
-        "bash" => shell = Shell::Bash,
-        "fish" => shell = Shell::Fish,
-        "powershell" => shell = Shell::PowerShell,
-        "elvish" => shell = Shell::Elvish,
+pub fn execute_import(import_cmd_name: Option<&str>, context: Context) -> error::Result<()> {
+    let file = context["args"]["FILE"].as_str().unwrap_or("");
+    let content = fs::read_to_string(file)
+        .map_err(|e| JoatError::Io(format!("Could not read {}: {}", file, e)))?;
+    let spec =
+        import::parse_spec(&content).map_err(|e| JoatError::Config(format!("{}: {}", file, e)))?;
+    let name = match context["args"]["name"].as_str() {
+        Some(n) => n.to_string(),
+        // e.g. `github` for `github.postman_collection.json`
+        None => import::to_snake_case(
+            Path::new(file)
+                .file_name()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default()
+                .split('.')
+                .next()
+                .unwrap_or(""),
+        ),
+    };
+    let mut report = import::ImportReport::default();
+    let config = match import_cmd_name {
+        Some("openapi") => openapi::get_extension_config(&spec, &name, &mut report)?,
+        Some("postman") => postman::get_extension_config(&spec, &name, &mut report)?,
+        Some("insomnia") => insomnia::get_extension_config(&spec, &name, &mut report)?,
+        Some("har") => har::get_extension_config(&spec, &name, &mut report)?,
+        _ => return Ok(()),
+    };
+    let merge = context["args"]["merge"].as_bool() == Some(true);
+    let filename = import::write_extension(&name, &config, merge)?;
+    print_extension_created(&filename, &name);
+    report.print();
+    Ok(())
+}
+
+pub fn execute_add_from_curl(context: Context) -> error::Result<()> {
+    let app_name = context["args"]["APP"].as_str().unwrap_or("").to_string();
+    let curl_args: Vec<String> = match &context["args"]["CURL"] {
+        Value::Array(a) => a
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(String::from)
+            .collect(),
+        v => v.as_str().map(String::from).into_iter().collect(),
+    };
+    let mut report = import::ImportReport::default();
+    let curl_command = curl::parse(&curl_args, &mut report)?;
+    let config = yaml::get_yaml_config(&app_name, None)?;
+    let config_file = match context["args"]["file"].as_str() {
+        Some(f) => PathBuf::from(f),
+        None => layers::get_loaded_layers()
+            .into_iter()
+            .rfind(|l| l.kind != LayerKind::BuiltIn)
+            .map(|l| l.path)
+            .ok_or(JoatError::Config(String::from(
+                "No config file to add the subcommand to, pass one with --file",
+            )))?,
+    };
+
+    let env = crate::get_env_context(&config)?;
+    let (default_name, options) = curl::get_subcommand(&curl_command, &config, &env, &mut report);
+    let mut existing_names = import::get_subcommand_names(&config);
+    let name = match context["args"]["name"].as_str() {
+        Some(n) if existing_names.iter().any(|e| e == n) => {
+            return Err(JoatError::Config(format!(
+                "Subcommand {} already exists",
+                n
+            )))
+        }
+        Some(n) => n.to_string(),
+        None => import::get_unique_name(&default_name, &mut existing_names),
+    };
+    let content = fs::read_to_string(&config_file)
+        .map_err(|e| JoatError::Io(format!("Could not read {:?}: {}", config_file, e)))?;
+    let was_trusted = matches!(
+        trust::get_trust_status(&config_file, &content)?,
+        trust::TrustStatus::Trusted
+    );
+    let new_content = import::append_subcommand(&content, &name, &options)?;
+    fs::write(&config_file, new_content)
+        .map_err(|e| JoatError::Io(format!("Could not write {:?}: {}", config_file, e)))?;
+    // The edit comes from the user, a trusted config stays trusted
+    if was_trusted {
+        trust::trust_file(&config_file)?;
+    }
+    println!("Subcommand {} added to {}", name, config_file.display());
+    report.print();
+    Ok(())
+}
+
+///
+/// The config files of the PATH arg (a config file or a dir), the current dir by default.
+///
+fn get_config_files_arg(context: &Context) -> error::Result<Vec<PathBuf>> {
+    let path = match context["args"]["PATH"].as_str() {
+        Some(p) => Path::new(p).to_path_buf(),
+        None => env::current_dir()
+            .map_err(|e| JoatError::Io(format!("Could not find current dir: {}", e)))?,
+    };
+    let config_files = match path.is_file() {
+        true => vec![path.clone()],
+        false => trust::get_config_files_in_dir(&path),
+    };
+    if config_files.is_empty() {
+        return Err(JoatError::Config(format!(
+            "No joat config files in {:?}, pass the config file path",
+            path
+        )));
+    }
+    Ok(config_files)
+}
+
+pub fn execute_trust(context: Context) -> error::Result<()> {
+    let config_files = get_config_files_arg(&context)?;
+    for config_file in config_files {
+        trust::trust_file(&config_file)?;
+        for included_file in yaml::get_included_files(&config_file)? {
+            trust::trust_file(&included_file)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn execute_lint(context: Context) -> error::Result<()> {
+    let config_files = get_config_files_arg(&context)?;
+    let mut error_count = 0;
+    let mut warning_count = 0;
+    for config_file in config_files {
+        let mut findings = lint::lint_file(&config_file.to_string_lossy(), false)?;
+        for included_file in yaml::get_included_files(&config_file)? {
+            findings.append(&mut lint::lint_file(
+                &included_file.to_string_lossy(),
+                true,
+            )?);
+        }
+        for finding in findings {
+            match finding.severity {
+                Severity::Error => error_count += 1,
+                Severity::Warning => warning_count += 1,
+            }
+            println!("{}", finding);
+        }
+    }
+    println!("{} errors, {} warnings", error_count, warning_count);
+    if error_count > 0 {
+        return Err(JoatError::Config(format!(
+            "{} errors in the joat config files",
+            error_count
+        )));
+    }
+    Ok(())
+}
+
+pub fn execute_auto_complete(mut app: App, app_name: &str, context: Context) -> error::Result<()> {
+    let selected_shell = context["args"]["SHELL"].as_str().unwrap_or_default();
+    let shell = match selected_shell.to_lowercase().as_str() {
+        "zsh" => Shell::Zsh,
+        "bash" => Shell::Bash,
+        "fish" => Shell::Fish,
+        "powershell" => Shell::PowerShell,
+        "elvish" => Shell::Elvish,
         shell => {
-            eprintln!("Shell not supported {}. Options are:", shell);
-            eprintln!("zsh");
-            eprintln!("bash");
-            eprintln!("fish");
-            eprintln!("powershell");
-            eprintln!("elvish");
-            ::std::process::exit(1);
+            return Err(JoatError::Config(format!(
+                "Shell not supported {}. Options are: zsh, bash, fish, powershell, elvish",
+                shell
+            )))
         }
     };
-    app.gen_completions(app_name, shell, ".")
+    app.gen_completions(app_name, shell, ".");
+    Ok(())
+}
+
+pub fn execute_auth(
+    app_name: &str,
+    yaml: &Yaml,
+    auth_cmd_name: Option<&str>,
+    context: Context,
+) -> error::Result<()> {
+    match auth_cmd_name {
+        Some("logout") => oauth::logout(app_name),
+        Some("status") => oauth::print_status(app_name),
+        Some(cmd) => {
+            let oauth_config = oauth::get_oauth_config(app_name, &yaml["oauth"], &context)?;
+            match cmd {
+                "login" => oauth::login(app_name, &oauth_config),
+                _ => {
+                    println!("{}", oauth::get_oauth_token(app_name, &oauth_config)?);
+                    Ok(())
+                }
+            }
+        }
+        None => Ok(()),
+    }
+}
+
+pub fn execute_secrets(secrets_cmd: (&str, Option<&ArgMatches>)) -> error::Result<()> {
+    let (secrets_cmd_name, secrets_args) = secrets_cmd;
+    let name = secrets_args.and_then(|a| a.value_of("NAME")).unwrap_or("");
+    let not_found = || JoatError::Config(format!("Secret {} not found", name));
+    match secrets_cmd_name {
+        "set" => {
+            let value = match secrets_args.and_then(|a| a.value_of("VALUE")) {
+                Some(v) => v.to_string(),
+                None => rpassword::read_password_from_tty(Some(&format!("{}: ", name)))
+                    .map_err(|e| JoatError::Io(format!("Could not read from tty: {}", e)))?,
+            };
+            secrets::set_secret(name, &value)?;
+        }
+        "get" => println!("{}", secrets::get_secret(name)?.ok_or_else(not_found)?),
+        "list" => {
+            for secret_name in secrets::list_secrets()? {
+                println!("{}", secret_name);
+            }
+        }
+        "rm" => match secrets::remove_secret(name)? {
+            true => (),
+            false => return Err(not_found()),
+        },
+        _ => (),
+    }
+    Ok(())
+}
+
+pub fn execute_config(
+    config_cmd: (&str, Option<&ArgMatches>),
+    yaml: &Yaml,
+    context: &Context,
+) -> error::Result<()> {
+    match config_cmd {
+        ("layers", _) => {
+            for (i, layer) in layers::get_loaded_layers().iter().enumerate() {
+                match layer.kind {
+                    LayerKind::BuiltIn => println!("{}. {}", i + 1, layer.kind),
+                    _ => println!(
+                        "{}. {:<9} {}",
+                        i + 1,
+                        layer.kind,
+                        layer.path.to_string_lossy()
+                    ),
+                }
+            }
+        }
+        ("explain", Some(explain_args)) => {
+            let scmd_names: Vec<&str> = explain_args
+                .values_of("SUBCOMMAND")
+                .map(|v| v.collect())
+                .unwrap_or_default();
+            let context = match explain_args.is_present("resolved") {
+                true => Some(context),
+                false => None,
+            };
+            println!("{}", explain::explain(yaml, &scmd_names, context)?);
+        }
+        _ => (),
+    }
+    Ok(())
 }
@@ -0,0 +1,395 @@
+extern crate jsonwebtoken;
+
+use chrono::DateTime;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use log::debug;
+use reqwest::header::{ACCEPT, USER_AGENT};
+use serde::{Deserialize, Serialize};
+use serde_json::value::Value;
+use serde_json::Map;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use yaml_rust::Yaml;
+
+use crate::error::{self, JoatError};
+use crate::{oauth, profile, secrets};
+use crate::{template, yaml, Context};
+
+const JWT_BEARER_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+// GitHub Apps reject JWTs that expire more than 10 minutes after they're issued
+const DEFAULT_EXPIRES_IN_SECS: u64 = 600;
+const EXPIRY_MARGIN_SECS: u64 = 60;
+
+// Backdate iat so a server clock slightly behind ours doesn't reject the JWT
+const ISSUED_AT_LEEWAY_SECS: u64 = 60;
+
+///
+/// How the signed JWT is exchanged for an access token.
+///
+/// `Assertion` posts it as an RFC 7523 form (Google and most OAuth servers), `Bearer` sends it in
+/// the Authorization header of an empty POST (GitHub Apps installation tokens).
+///
+pub enum TokenRequest {
+    Assertion,
+    Bearer,
+}
+
+pub struct JwtBearerConfig {
+    algorithm: Algorithm,
+    key: Vec<u8>,
+    key_id: Option<String>,
+    claims: Map<String, Value>,
+    expires_in: u64,
+    token_url: Option<String>,
+    token_request: TokenRequest,
+}
+
+///
+/// Fields of a service account JSON key file (as downloaded from Google Cloud).
+///
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    private_key: String,
+    private_key_id: Option<String>,
+    client_email: Option<String>,
+    token_uri: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CachedToken {
+    token: String,
+    expires_at: Option<u64>,
+}
+
+fn get_optional_key(
+    auth_yaml: &Yaml,
+    key: &str,
+    context: &Context,
+) -> error::Result<Option<String>> {
+    let raw_value = match auth_yaml[key].as_str() {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let value = template::get_compiled_template_str_with_context(&raw_value.to_string(), context)?;
+    match value.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(value)),
+    }
+}
+
+fn get_algorithm(algorithm: &str) -> error::Result<Algorithm> {
+    match algorithm {
+        "RS256" => Ok(Algorithm::RS256),
+        "ES256" => Ok(Algorithm::ES256),
+        "HS256" => Ok(Algorithm::HS256),
+        a => Err(JoatError::Config(format!(
+            "Unsupported jwt algorithm {}, use RS256, ES256 or HS256",
+            a
+        ))),
+    }
+}
+
+fn read_key_file(key_file: &str) -> error::Result<String> {
+    let path = match key_file.starts_with("~/") {
+        true => {
+            let home_dir_path =
+                dirs::home_dir().ok_or(JoatError::Io(String::from("Could not find home dir")))?;
+            home_dir_path.join(&key_file[2..])
+        }
+        false => Path::new(key_file).to_path_buf(),
+    };
+    fs::read_to_string(&path)
+        .map_err(|e| JoatError::Auth(format!("Could not read key file {:?}: {}", path, e)))
+}
+
+pub fn get_config(auth_yaml: &Yaml, context: &Context) -> error::Result<JwtBearerConfig> {
+    let algorithm = get_algorithm(
+        &get_optional_key(auth_yaml, "algorithm", context)?.unwrap_or(String::from("RS256")),
+    )?;
+    let key_content = match get_optional_key(auth_yaml, "key_file", context)? {
+        Some(f) => read_key_file(&f)?,
+        None => get_optional_key(auth_yaml, "key", context)?.ok_or(JoatError::Config(
+            String::from("Missing key_file (or key) for jwt_bearer auth"),
+        ))?,
+    };
+    let service_account: Option<ServiceAccountKey> = serde_json::from_str(&key_content).ok();
+
+    let mut claims = Map::new();
+    if let Some(email) = service_account
+        .as_ref()
+        .and_then(|s| s.client_email.clone())
+    {
+        claims.insert(String::from("iss"), Value::from(email.clone()));
+        claims.insert(String::from("sub"), Value::from(email));
+    }
+    for (name, value) in yaml::get_hash_from_yaml(&auth_yaml["claims"], context, true)? {
+        claims.insert(name, value);
+    }
+
+    let token_url = get_optional_key(auth_yaml, "token_url", context)?
+        .or(service_account.as_ref().and_then(|s| s.token_uri.clone()));
+    if let (Some(url), false) = (&token_url, claims.contains_key("aud")) {
+        // The token endpoint is the audience of the assertion (RFC 7523)
+        claims.insert(String::from("aud"), Value::from(url.clone()));
+    }
+    let token_request = match get_optional_key(auth_yaml, "token_request", context)?.as_deref() {
+        None | Some("assertion") => TokenRequest::Assertion,
+        Some("bearer") => TokenRequest::Bearer,
+        Some(t) => {
+            return Err(JoatError::Config(format!(
+                "Unknown token_request {}, use assertion or bearer",
+                t
+            )))
+        }
+    };
+    let expires_in = match auth_yaml["expires_in"].as_i64() {
+        Some(e) => e as u64,
+        None => DEFAULT_EXPIRES_IN_SECS,
+    };
+
+    Ok(JwtBearerConfig {
+        algorithm,
+        key_id: get_optional_key(auth_yaml, "key_id", context)?.or(service_account
+            .as_ref()
+            .and_then(|s| s.private_key_id.clone())),
+        key: match service_account {
+            Some(s) => s.private_key.into_bytes(),
+            None => key_content.into_bytes(),
+        },
+        claims,
+        expires_in,
+        token_url,
+        token_request,
+    })
+}
+
+fn get_claims(config: &JwtBearerConfig, now: u64) -> Map<String, Value> {
+    let mut claims = config.claims.clone();
+    if !claims.contains_key("iat") {
+        claims.insert(
+            String::from("iat"),
+            Value::from(now.saturating_sub(ISSUED_AT_LEEWAY_SECS)),
+        );
+    }
+    if !claims.contains_key("exp") {
+        claims.insert(String::from("exp"), Value::from(now + config.expires_in));
+    }
+    claims
+}
+
+fn get_assertion(config: &JwtBearerConfig, claims: &Map<String, Value>) -> error::Result<String> {
+    let key_error =
+        |e: jsonwebtoken::errors::Error| JoatError::Auth(format!("Invalid jwt_bearer key: {}", e));
+    let encoding_key = match config.algorithm {
+        Algorithm::RS256 => EncodingKey::from_rsa_pem(&config.key).map_err(key_error)?,
+        Algorithm::ES256 => EncodingKey::from_ec_pem(&config.key).map_err(key_error)?,
+        _ => EncodingKey::from_secret(&config.key),
+    };
+    let mut header = Header::new(config.algorithm);
+    header.kid = config.key_id.clone();
+    jsonwebtoken::encode(&header, claims, &encoding_key)
+        .map_err(|e| JoatError::Auth(format!("Could not sign jwt: {}", e)))
+}
+
+///
+/// Reads the issued token from an exchange response.
+///
+/// OAuth servers answer with `access_token` and `expires_in`, GitHub with `token` and an
+/// RFC 3339 `expires_at`.
+///
+fn parse_exchange_response(response: &Value, now: u64) -> error::Result<CachedToken> {
+    let token = response["access_token"]
+        .as_str()
+        .or(response["token"].as_str())
+        .ok_or(JoatError::Auth(String::from(
+            "No access_token in token exchange response",
+        )))?
+        .to_string();
+    let expires_at = match (
+        response["expires_in"].as_u64(),
+        response["expires_at"].as_str(),
+    ) {
+        (Some(e), _) => Some(now + e),
+        (None, Some(e)) => DateTime::parse_from_rfc3339(e)
+            .ok()
+            .map(|d| d.timestamp() as u64),
+        (None, None) => None,
+    };
+    Ok(CachedToken { token, expires_at })
+}
+
+fn exchange_assertion(
+    config: &JwtBearerConfig,
+    token_url: &str,
+    assertion: &str,
+) -> error::Result<CachedToken> {
+    debug!("Exchanging jwt assertion at {}", token_url);
+    let client = reqwest::Client::new();
+    let request = match config.token_request {
+        TokenRequest::Assertion => client.post(token_url).form(&[
+            ("grant_type", JWT_BEARER_GRANT_TYPE),
+            ("assertion", assertion),
+        ]),
+        TokenRequest::Bearer => client.post(token_url).bearer_auth(assertion),
+    };
+    let mut response = request
+        .header(ACCEPT, "application/json")
+        .header(USER_AGENT, "joat")
+        .send()
+        .map_err(|e| JoatError::Network(format!("Could not reach {}: {}", token_url, e)))?;
+    let body: Value = response
+        .json()
+        .map_err(|e| JoatError::Auth(format!("Token exchange response is not json: {}", e)))?;
+    if !response.status().is_success() {
+        return Err(JoatError::Auth(format!(
+            "Token exchange failed with {}: {}",
+            response.status(),
+            body
+        )));
+    }
+    parse_exchange_response(&body, oauth::now_secs()?)
+}
+
+///
+/// Identifies the configuration a cached token was issued for, so subcommands with different
+/// claims or keys don't share tokens.
+///
+fn get_cache_key(config: &JwtBearerConfig) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(&config.key);
+    hasher.input(Value::Object(config.claims.clone()).to_string().as_bytes());
+    hasher.input(config.token_url.clone().unwrap_or_default().as_bytes());
+    hasher
+        .result()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn get_cache_file_path(app_name: &str) -> error::Result<String> {
+    profile::get_profile_file_path(app_name, "jwt.json")
+}
+
+fn get_cached_tokens(app_name: &str) -> error::Result<HashMap<String, CachedToken>> {
+    let content = match fs::read_to_string(get_cache_file_path(app_name)?) {
+        Ok(c) => c,
+        Err(_e) => return Ok(HashMap::new()),
+    };
+    Ok(serde_json::from_str(&content).unwrap_or(HashMap::new()))
+}
+
+fn write_cached_tokens(app_name: &str, tokens: &HashMap<String, CachedToken>) -> error::Result<()> {
+    let content = serde_json::to_string(tokens)
+        .map_err(|e| JoatError::Internal(format!("Could not serialize jwt cache: {}", e)))?;
+    fs::create_dir_all(oauth::get_app_home_path(app_name)?)
+        .map_err(|e| JoatError::Io(format!("Could not create config dir: {}", e)))?;
+    secrets::write_private_file(&get_cache_file_path(app_name)?, &content)
+}
+
+///
+/// Returns the token to send as bearer, signing and exchanging a new assertion when the cached
+/// one is about to expire.
+///
+pub fn get_token(app_name: &str, config: &JwtBearerConfig) -> error::Result<String> {
+    let now = oauth::now_secs()?;
+    let cache_key = get_cache_key(config);
+    let mut cached_tokens = get_cached_tokens(app_name)?;
+    if let Some(cached) = cached_tokens.get(&cache_key) {
+        match cached.expires_at {
+            Some(e) if now + EXPIRY_MARGIN_SECS < e => return Ok(cached.token.clone()),
+            _ => (),
+        }
+    }
+
+    let claims = get_claims(config, now);
+    let assertion = get_assertion(config, &claims)?;
+    let issued = match &config.token_url {
+        Some(url) => exchange_assertion(config, url, &assertion)?,
+        // Without an exchange endpoint the signed jwt is the bearer token
+        None => CachedToken {
+            token: assertion,
+            expires_at: claims["exp"].as_u64(),
+        },
+    };
+    // Drop expired entries so the cache doesn't grow forever
+    cached_tokens.retain(|_, t| t.expires_at.is_some_and(|e| e > now));
+    cached_tokens.insert(cache_key, issued.clone());
+    write_cached_tokens(app_name, &cached_tokens)?;
+    Ok(issued.token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{DecodingKey, Validation};
+    use serde_json::json;
+
+    fn hs256_config() -> JwtBearerConfig {
+        let mut claims = Map::new();
+        claims.insert(String::from("iss"), Value::from("joat"));
+        JwtBearerConfig {
+            algorithm: Algorithm::HS256,
+            key: b"secret".to_vec(),
+            key_id: None,
+            claims,
+            expires_in: 600,
+            token_url: None,
+            token_request: TokenRequest::Assertion,
+        }
+    }
+
+    #[test]
+    fn test_get_assertion_hs256() {
+        // Arrange
+        let config = hs256_config();
+        let now = oauth::now_secs().unwrap();
+        let claims = get_claims(&config, now);
+
+        // Act
+        let assertion = get_assertion(&config, &claims).unwrap();
+
+        // Assert
+        let decoded = jsonwebtoken::decode::<Value>(
+            &assertion,
+            &DecodingKey::from_secret(b"secret"),
+            &Validation::new(Algorithm::HS256),
+        )
+        .unwrap();
+        assert_eq!(decoded.claims["iss"], "joat");
+        assert_eq!(decoded.claims["exp"], now + 600);
+    }
+
+    #[test]
+    fn test_get_claims_keeps_explicit_exp() {
+        // Arrange
+        let mut config = hs256_config();
+        config.claims.insert(String::from("exp"), Value::from(42));
+
+        // Act
+        let claims = get_claims(&config, 100);
+
+        // Assert
+        assert_eq!(claims["exp"], 42);
+        assert_eq!(claims["iat"], 40);
+    }
+
+    #[test]
+    fn test_parse_exchange_response() {
+        // Act
+        let oauth_token =
+            parse_exchange_response(&json!({"access_token": "a", "expires_in": 5}), 10).unwrap();
+        let github_token = parse_exchange_response(
+            &json!({"token": "b", "expires_at": "2016-07-11T22:14:10Z"}),
+            10,
+        )
+        .unwrap();
+
+        // Assert
+        assert_eq!(oauth_token.token, "a");
+        assert_eq!(oauth_token.expires_at, Some(15));
+        assert_eq!(github_token.token, "b");
+        assert_eq!(github_token.expires_at, Some(1468275250));
+    }
+}
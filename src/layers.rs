@@ -0,0 +1,256 @@
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::{self, JoatError};
+
+const CONFIG_ENV: &str = "JOAT_CONFIG";
+const CONFIG_ARG: &str = "--config";
+// Top level args taking a separate value, skipped looking for the first subcommand name
+const GLOBAL_VALUE_ARGS: [&str; 2] = ["--profile", "--error-format"];
+// Not a file, the built-in subcommands are compiled in joat
+const BUILT_IN_PATH: &str = "<built-in>";
+const SYSTEM_CONFIG_DIR: &str = "/etc/joat";
+// joat's own config is embedded in the binary
+const BUILT_IN_CONFIG: &str = include_str!("../joat.yml");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayerKind {
+    BuiltIn,
+    System,
+    Xdg,
+    Home,
+    Project,
+    Explicit,
+}
+
+impl fmt::Display for LayerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LayerKind::BuiltIn => "built-in",
+            LayerKind::System => "system",
+            LayerKind::Xdg => "xdg",
+            LayerKind::Home => "home",
+            LayerKind::Project => "project",
+            LayerKind::Explicit => "explicit",
+        };
+        f.pad(name)
+    }
+}
+
+///
+/// A config file merged into the app config, layers later in the list override the earlier ones.
+///
+#[derive(Clone, Debug)]
+pub struct ConfigLayer {
+    pub kind: LayerKind,
+    pub path: PathBuf,
+    // Templates and includes are relative to it
+    pub base_path: PathBuf,
+}
+
+impl ConfigLayer {
+    fn new(kind: LayerKind, path: PathBuf) -> ConfigLayer {
+        let base_path = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        ConfigLayer {
+            kind,
+            path,
+            base_path,
+        }
+    }
+
+    ///
+    /// Only configs found in the project ancestors (e.g. a cloned repo) need `joat trust`, the
+    /// other layers are installed or selected by the user.
+    ///
+    pub fn needs_trust(&self) -> bool {
+        self.kind == LayerKind::Project
+    }
+
+    pub fn get_built_in_content(&self) -> Option<&'static str> {
+        match self.kind {
+            LayerKind::BuiltIn => Some(BUILT_IN_CONFIG),
+            _ => None,
+        }
+    }
+}
+
+// Set by yaml::get_yaml_config with the layers it could load, for `config layers`
+static LOADED_LAYERS: Mutex<Vec<ConfigLayer>> = Mutex::new(Vec::new());
+
+pub fn set_loaded_layers(layers: Vec<ConfigLayer>) {
+    *LOADED_LAYERS.lock().unwrap() = layers;
+}
+
+pub fn get_loaded_layers() -> Vec<ConfigLayer> {
+    LOADED_LAYERS.lock().unwrap().clone()
+}
+
+///
+/// The config file passed with `--config FILE` (read before clap parses the args, the config
+/// defines them) or `JOAT_CONFIG`. Only the args before the first subcommand name count, the
+/// ones after it belong to the subcommand.
+///
+pub fn get_explicit_config(args: &[String]) -> Option<String> {
+    let mut config = None;
+    let mut args_iter = args.iter().skip(1);
+    while let Some(arg) = args_iter.next() {
+        if arg == CONFIG_ARG {
+            config = args_iter.next().cloned();
+        } else if let Some(c) = arg.strip_prefix(&format!("{}=", CONFIG_ARG)) {
+            config = Some(c.to_string());
+        } else if GLOBAL_VALUE_ARGS.contains(&arg.as_str()) {
+            args_iter.next();
+        } else if arg == "--" || !arg.starts_with('-') {
+            break;
+        }
+    }
+    config.or(env::var(CONFIG_ENV).ok())
+}
+
+///
+/// `.<app>.joat/<app>.yml` in a dir, or `<app>.yml` to ease development.
+///
+fn get_config_file_in_dir(app_name: &str, dir: &Path) -> Option<PathBuf> {
+    let config_file = dir
+        .join(format!(".{}.joat", app_name))
+        .join(format!("{}.yml", app_name));
+    if config_file.is_file() {
+        return Some(config_file);
+    }
+    let alternative_file = dir.join(format!("{}.yml", app_name));
+    match alternative_file.is_file() {
+        true => Some(alternative_file),
+        false => None,
+    }
+}
+
+///
+/// Ancestors of the current dir, the farthest first, the home dir is a layer of its own.
+///
+fn get_project_dirs(current_dir: &Path, home_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = current_dir
+        .ancestors()
+        .filter(|d| *d != home_dir)
+        .map(|d| d.to_path_buf())
+        .collect();
+    dirs.reverse();
+    dirs
+}
+
+fn get_xdg_config_dir(home_dir: &Path) -> PathBuf {
+    match env::var("XDG_CONFIG_HOME") {
+        Ok(d) if !d.is_empty() => PathBuf::from(d),
+        _ => home_dir.join(".config"),
+    }
+}
+
+fn get_file_layer(kind: LayerKind, path: PathBuf) -> Option<ConfigLayer> {
+    match path.is_file() {
+        true => Some(ConfigLayer::new(kind, path)),
+        false => None,
+    }
+}
+
+///
+/// Config files of an app, from the lowest to the highest priority: built-in (joat only),
+/// `/etc/joat/<app>/<app>.yml`, `$XDG_CONFIG_HOME/<app>/<app>.yml`, `~/.<app>.joat/<app>.yml`,
+/// the ancestors of the current dir and `--config FILE` or `JOAT_CONFIG`.
+///
+pub fn get_config_layers(
+    app_name: &str,
+    explicit_config: Option<String>,
+) -> error::Result<Vec<ConfigLayer>> {
+    let home_dir =
+        dirs::home_dir().ok_or(JoatError::Io(String::from("Could not find home dir")))?;
+    let current_dir = env::current_dir()
+        .map_err(|e| JoatError::Io(format!("Could not find current dir: {}", e)))?;
+    let mut layers = Vec::new();
+    if app_name == env!("CARGO_PKG_NAME") {
+        layers.push(ConfigLayer {
+            kind: LayerKind::BuiltIn,
+            base_path: home_dir.join(".joat.joat"),
+            path: PathBuf::from(BUILT_IN_PATH),
+        });
+    }
+    let app_dir_file = |dir: PathBuf| dir.join(app_name).join(format!("{}.yml", app_name));
+    layers.extend(get_file_layer(
+        LayerKind::System,
+        app_dir_file(PathBuf::from(SYSTEM_CONFIG_DIR)),
+    ));
+    layers.extend(get_file_layer(
+        LayerKind::Xdg,
+        app_dir_file(get_xdg_config_dir(&home_dir)),
+    ));
+    if let Some(f) = get_config_file_in_dir(app_name, &home_dir) {
+        layers.push(ConfigLayer::new(LayerKind::Home, f));
+    }
+    for dir in get_project_dirs(&current_dir, &home_dir) {
+        if let Some(f) = get_config_file_in_dir(app_name, &dir) {
+            layers.push(ConfigLayer::new(LayerKind::Project, f));
+        }
+    }
+    if let Some(c) = explicit_config {
+        let path = current_dir.join(&c);
+        if !path.is_file() {
+            return Err(JoatError::Config(format!("Config file {} not found", c)));
+        }
+        layers.push(ConfigLayer::new(LayerKind::Explicit, path));
+    }
+    Ok(layers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_project_dirs() {
+        // Act
+        let dirs = get_project_dirs(Path::new("/home/u/work/repo"), Path::new("/home/u"));
+
+        // Assert
+        assert_eq!(
+            dirs,
+            vec![
+                PathBuf::from("/"),
+                PathBuf::from("/home"),
+                PathBuf::from("/home/u/work"),
+                PathBuf::from("/home/u/work/repo"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_explicit_config() {
+        // Arrange
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+
+        // Act, Assert
+        assert_eq!(
+            get_explicit_config(&args(&["app", "--config", "a.yml", "show"])),
+            Some(String::from("a.yml"))
+        );
+        assert_eq!(
+            get_explicit_config(&args(&[
+                "app",
+                "--profile",
+                "dev",
+                "--config=b.yml",
+                "show"
+            ])),
+            Some(String::from("b.yml"))
+        );
+        assert_eq!(
+            get_explicit_config(&args(&["app", "deploy", "--config", "prod.yml"])),
+            None
+        );
+        assert_eq!(
+            get_explicit_config(&args(&[
+                "app", "--config", "a.yml", "curl", "--", "--config", "c"
+            ])),
+            Some(String::from("a.yml"))
+        );
+    }
+}
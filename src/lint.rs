@@ -0,0 +1,867 @@
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use tera::Tera;
+use yaml_rust::parser::{MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+use yaml_rust::{Event, Yaml, YamlLoader};
+
+use crate::error::{self, JoatError};
+use crate::yaml;
+
+const TOP_LEVEL_KEYS: [&str; 37] = [
+    "name",
+    "version",
+    "author",
+    "about",
+    "long_about",
+    "bin_name",
+    "before_help",
+    "after_help",
+    "usage",
+    "settings",
+    "setting",
+    "global_settings",
+    "global_setting",
+    "groups",
+    "alias",
+    "aliases",
+    "version_short",
+    "help_short",
+    "args",
+    "subcommands",
+    "base_endpoint",
+    "vars",
+    "headers",
+    "query_params",
+    "files",
+    "oauth",
+    "auth",
+    "netrc",
+    "profiles",
+    "default_profile",
+    "env_allow",
+    "env_deny",
+    "redact",
+    "max_recursion_count",
+    "scmd_config_base_path",
+    "template",
+    "include",
+];
+const SUBCOMMAND_KEYS: [&str; 32] = [
+    "about",
+    "long_about",
+    "author",
+    "version",
+    "args",
+    "settings",
+    "setting",
+    "groups",
+    "alias",
+    "aliases",
+    "visible_alias",
+    "visible_aliases",
+    "before_help",
+    "after_help",
+    "usage",
+    "display_order",
+    "path",
+    "method",
+    "base_endpoint",
+    "headers",
+    "body",
+    "form",
+    "query_params",
+    "response_template",
+    "script",
+    "timeout",
+    "auth",
+    "netrc",
+    "vars",
+    "disabled",
+    "subcommands",
+    "scmd_config_base_path",
+];
+// Profiles override top level keys, except the ones selecting profiles
+const PROFILE_EXCLUDED_KEYS: [&str; 4] = ["profiles", "default_profile", "subcommands", "include"];
+const HTTP_METHODS: [&str; 7] = ["get", "post", "put", "patch", "delete", "head", "options"];
+// Options joat adds to every subcommand, see yaml::add_default_options
+const RESERVED_OPTIONS: [(&str, &str); 3] =
+    [("t", "template"), ("q", "quiet"), ("R", "raw_response")];
+// Args read before clap parses the command line, reserved by name at any level
+const RESERVED_ARGS: [(&str, &str); 2] = [("config", "config"), ("error_format", "error-format")];
+
+#[derive(Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub file: String,
+    pub line: Option<usize>,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match self.line {
+            Some(l) => write!(f, "{}:{}: {}: {}", self.file, l, severity, self.message)?,
+            None => write!(f, "{}: {}: {}", self.file, severity, self.message)?,
+        }
+        if let Some(h) = &self.hint {
+            write!(f, "\n    hint: {}", h)?;
+        }
+        Ok(())
+    }
+}
+
+enum Frame {
+    Map { path: String, key: Option<String> },
+    Seq { path: String, index: usize },
+}
+
+///
+/// Records the line of every key and sequence item, yaml_rust::Yaml doesn't keep positions.
+///
+/// Paths look like `subcommands[0].show.path`.
+///
+#[derive(Default)]
+struct LineRecorder {
+    stack: Vec<Frame>,
+    lines: HashMap<String, usize>,
+}
+
+fn get_marker_line(mark: &Marker) -> usize {
+    // Marker fields are private in yaml-rust 0.3, its Debug output is the only way to read them
+    let debug_mark = format!("{:?}", mark);
+    let re = Regex::new(r"line: (\d+)").unwrap();
+    re.captures(&debug_mark)
+        .and_then(|c| c[1].parse().ok())
+        .unwrap_or(0)
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    }
+}
+
+impl LineRecorder {
+    fn start_value(&mut self, line: usize) -> String {
+        match self.stack.last() {
+            Some(Frame::Map { path, key }) => {
+                join_path(path, key.as_ref().unwrap_or(&String::new()))
+            }
+            Some(Frame::Seq { path, index }) => {
+                let item_path = format!("{}[{}]", path, index);
+                self.lines.insert(item_path.clone(), line);
+                item_path
+            }
+            None => String::new(),
+        }
+    }
+
+    fn end_value(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Map { key, .. }) => *key = None,
+            Some(Frame::Seq { index, .. }) => *index += 1,
+            None => (),
+        }
+    }
+}
+
+impl MarkedEventReceiver for LineRecorder {
+    fn on_event(&mut self, ev: &Event, mark: Marker) {
+        let line = get_marker_line(&mark);
+        match ev {
+            Event::Scalar(value, ..) => {
+                if let Some(Frame::Map {
+                    path,
+                    key: key @ None,
+                }) = self.stack.last_mut()
+                {
+                    let key_path = join_path(path, value);
+                    *key = Some(value.clone());
+                    self.lines.insert(key_path, line);
+                    return;
+                }
+                self.start_value(line);
+                self.end_value();
+            }
+            Event::Alias(_) => {
+                self.start_value(line);
+                self.end_value();
+            }
+            Event::MappingStart(_) => {
+                let path = self.start_value(line);
+                self.stack.push(Frame::Map { path, key: None });
+            }
+            Event::SequenceStart(_) => {
+                let path = self.start_value(line);
+                self.stack.push(Frame::Seq { path, index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.end_value();
+            }
+            _ => (),
+        }
+    }
+}
+
+fn get_type_name(value: &Yaml) -> &'static str {
+    match value {
+        Yaml::Hash(_) => "a map",
+        Yaml::Array(_) => "a list",
+        Yaml::String(_) => "a string",
+        Yaml::Integer(_) => "an integer",
+        Yaml::Real(_) => "a number",
+        Yaml::Boolean(_) => "a boolean",
+        Yaml::Null => "empty",
+        _ => "invalid",
+    }
+}
+
+fn get_edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b_chars.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b_chars.iter().enumerate() {
+            let cost = if a_char == *b_char { 0 } else { 1 };
+            let value = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+            current.push(value);
+        }
+        previous = current;
+    }
+    previous[b_chars.len()]
+}
+
+fn get_unknown_key_hint(key: &str, known_keys: &[&str]) -> Option<String> {
+    known_keys
+        .iter()
+        .map(|k| (get_edit_distance(key, k), k))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+        .map(|(_, k)| format!("did you mean `{}`?", k))
+}
+
+fn get_template_error(e: &tera::Error) -> String {
+    // Tera wraps the parse error, the useful message is the innermost one
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(s) = source {
+        message = s.to_string();
+        source = s.source();
+    }
+    // Parse errors draw the template with a caret, keep the position and the expectation
+    let position = message
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("--> "))
+        .map(|p| p.to_string());
+    let expected = message
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("= "))
+        .map(|e| e.to_string());
+    match (position, expected) {
+        (Some(p), Some(e)) => format!("{} at {}", e, p),
+        _ => message.trim().replace('\n', " "),
+    }
+}
+
+struct Linter {
+    file: String,
+    lines: HashMap<String, usize>,
+    findings: Vec<Finding>,
+    // joat's own subcommands without path or script are executed by the binary
+    is_joat_config: bool,
+    // Included files only hold part of a config, e.g. a list of subcommands
+    is_include: bool,
+}
+
+impl Linter {
+    fn get_line(&self, path: &str) -> Option<usize> {
+        let mut current = path.to_string();
+        loop {
+            if let Some(l) = self.lines.get(&current) {
+                return Some(*l);
+            }
+            match current.rfind(['.', '[']) {
+                Some(i) => current.truncate(i),
+                None => return None,
+            }
+        }
+    }
+
+    fn add(&mut self, severity: Severity, path: &str, message: String, hint: Option<String>) {
+        let line = self.get_line(path);
+        self.findings.push(Finding {
+            severity,
+            file: self.file.clone(),
+            line,
+            message,
+            hint,
+        });
+    }
+
+    fn error(&mut self, path: &str, message: String, hint: Option<&str>) {
+        self.add(Severity::Error, path, message, hint.map(|h| h.to_string()));
+    }
+
+    fn check_type(&mut self, path: &str, value: &Yaml, expected: &str) -> bool {
+        let type_name = get_type_name(value);
+        let matches = match expected {
+            "a string" => value.as_str().is_some(),
+            "a map" => value.as_hash().is_some(),
+            "a list" => value.as_vec().is_some(),
+            "a boolean" => value.as_bool().is_some(),
+            "an integer" => value.as_i64().is_some(),
+            _ => true,
+        };
+        if !matches {
+            self.error(
+                path,
+                format!("`{}` should be {}, found {}", path, expected, type_name),
+                None,
+            );
+        }
+        matches
+    }
+
+    fn check_unknown_keys(&mut self, path: &str, hash: &Yaml, known_keys: &[&str]) {
+        for key in hash
+            .as_hash()
+            .map(|h| h.keys().collect::<Vec<&Yaml>>())
+            .unwrap_or_default()
+        {
+            let key_str = yaml::get_key_str(key);
+            if !known_keys.contains(&key_str.as_str()) {
+                let hint = get_unknown_key_hint(&key_str, known_keys);
+                self.add(
+                    Severity::Warning,
+                    &join_path(path, &key_str),
+                    format!("Unknown key `{}`", join_path(path, &key_str)),
+                    hint,
+                );
+            }
+        }
+    }
+
+    fn check_string_map(&mut self, path: &str, value: &Yaml) {
+        if value.is_badvalue() || !self.check_type(path, value, "a map") {
+            return;
+        }
+        for (k, v) in value.as_hash().unwrap().iter() {
+            let entry_path = join_path(path, &yaml::get_key_str(k));
+            if v.as_str().is_none() {
+                self.error(
+                    &entry_path,
+                    format!(
+                        "`{}` should be a string, found {}",
+                        entry_path,
+                        get_type_name(v)
+                    ),
+                    Some("quote the value, e.g. \"123\""),
+                );
+            }
+        }
+    }
+
+    fn check_string_list(&mut self, path: &str, value: &Yaml) {
+        if value.is_badvalue() || !self.check_type(path, value, "a list") {
+            return;
+        }
+        for (i, v) in value.as_vec().unwrap().iter().enumerate() {
+            if v.as_str().is_none() {
+                let item_path = format!("{}[{}]", path, i);
+                self.error(
+                    &item_path,
+                    format!("`{}` should be a string", item_path),
+                    None,
+                );
+            }
+        }
+    }
+
+    fn check_optional(&mut self, path: &str, value: &Yaml, expected: &str) {
+        if !value.is_badvalue() {
+            self.check_type(path, value, expected);
+        }
+    }
+
+    fn check_include(&mut self, path: &str, value: &Yaml) {
+        if value.as_str().is_none() {
+            self.check_string_list(path, value);
+        }
+    }
+
+    fn check_auth(&mut self, path: &str, value: &Yaml) {
+        if value.is_badvalue() || value.as_str().is_some() {
+            return;
+        }
+        if self.check_type(path, value, "a map") && value["type"].as_str().is_none() {
+            self.error(
+                path,
+                format!("`{}` needs a type", path),
+                Some("e.g. type: basic, see the Authentication section of the README"),
+            );
+        }
+    }
+
+    /// Keys shared by the top level and profiles.
+    fn check_common_keys(&mut self, path: &str, config: &Yaml) {
+        let key = |k: &str| join_path(path, k);
+        self.check_optional(&key("name"), &config["name"], "a string");
+        self.check_optional(&key("version"), &config["version"], "a string");
+        self.check_optional(&key("base_endpoint"), &config["base_endpoint"], "a string");
+        self.check_optional(&key("vars"), &config["vars"], "a map");
+        self.check_string_map(&key("headers"), &config["headers"]);
+        self.check_string_map(&key("query_params"), &config["query_params"]);
+        self.check_string_map(&key("files"), &config["files"]);
+        self.check_optional(&key("oauth"), &config["oauth"], "a map");
+        self.check_auth(&key("auth"), &config["auth"]);
+        self.check_optional(&key("netrc"), &config["netrc"], "a boolean");
+        self.check_string_list(&key("env_allow"), &config["env_allow"]);
+        self.check_string_list(&key("env_deny"), &config["env_deny"]);
+        self.check_string_list(&key("redact"), &config["redact"]);
+        self.check_optional(
+            &key("max_recursion_count"),
+            &config["max_recursion_count"],
+            "an integer",
+        );
+    }
+
+    fn check_profiles(&mut self, config: &Yaml) {
+        let profiles = &config["profiles"];
+        if !profiles.is_badvalue() && self.check_type("profiles", profiles, "a map") {
+            let profile_keys: Vec<&str> = TOP_LEVEL_KEYS
+                .iter()
+                .cloned()
+                .filter(|k| !PROFILE_EXCLUDED_KEYS.contains(k))
+                .collect();
+            for (name, profile) in profiles.as_hash().unwrap().iter() {
+                let path = join_path("profiles", &yaml::get_key_str(name));
+                if self.check_type(&path, profile, "a map") {
+                    self.check_unknown_keys(&path, profile, &profile_keys);
+                    self.check_common_keys(&path, profile);
+                }
+            }
+        }
+        if let Some(default_profile) = config["default_profile"].as_str() {
+            if !profiles.is_badvalue() && profiles[default_profile].is_badvalue() {
+                self.error(
+                    "default_profile",
+                    format!("default_profile `{}` is not in profiles", default_profile),
+                    None,
+                );
+            }
+        }
+    }
+
+    fn check_args(&mut self, path: &str, args: &Yaml, is_script: bool) {
+        if args.is_badvalue() || !self.check_type(path, args, "a list") {
+            return;
+        }
+        for (i, arg) in args.as_vec().unwrap().iter().enumerate() {
+            let item_path = format!("{}[{}]", path, i);
+            let arg_hash = match arg.as_hash() {
+                Some(h) if h.len() == 1 => h,
+                _ => {
+                    self.error(
+                        &item_path,
+                        format!(
+                            "`{}` should be a map with the arg name as its only key",
+                            item_path
+                        ),
+                        Some("e.g. - ISSUE_ID: {help: Id of the issue, index: 1}"),
+                    );
+                    continue;
+                }
+            };
+            let (name, options) = arg_hash.iter().next().unwrap();
+            let arg_path = join_path(&item_path, &yaml::get_key_str(name));
+            for (short, long) in RESERVED_OPTIONS.iter() {
+                // Script subcommands don't get the template option
+                if is_script && *long == "template" {
+                    continue;
+                }
+                let clashes = options["short"].as_str() == Some(short)
+                    || options["long"].as_str() == Some(long);
+                if clashes {
+                    self.error(
+                        &arg_path,
+                        format!(
+                            "Arg `{}` clashes with joat's -{}/--{}",
+                            arg_path, short, long
+                        ),
+                        Some("-t/--template, -q/--quiet and -R/--raw_response are reserved"),
+                    );
+                }
+            }
+            for (reserved_name, long) in RESERVED_ARGS.iter() {
+                if name.as_str() == Some(reserved_name) || options["long"].as_str() == Some(long) {
+                    self.error(
+                        &arg_path,
+                        format!("Arg `{}` clashes with joat's --{}", arg_path, long),
+                        Some("config and error_format are reserved, rename the arg"),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_subcommand(&mut self, path: &str, scmd: &Yaml) {
+        let scmd_hash = match scmd.as_hash() {
+            Some(h) if h.len() == 1 => h,
+            _ => {
+                self.error(
+                    path,
+                    format!(
+                        "`{}` should be a map with the subcommand name as its only key",
+                        path
+                    ),
+                    Some("e.g. - show: {about: Show an issue, path: /issues/{{args.ID}}}"),
+                );
+                return;
+            }
+        };
+        let (name, options) = scmd_hash.iter().next().unwrap();
+        let name_str = yaml::get_key_str(name);
+        let scmd_path = join_path(path, &name_str);
+        if !self.check_type(&scmd_path, options, "a map") {
+            return;
+        }
+        self.check_unknown_keys(&scmd_path, options, &SUBCOMMAND_KEYS);
+        let key = |k: &str| join_path(&scmd_path, k);
+        let is_script = !options["script"].is_badvalue();
+        let is_disabled = options["disabled"].as_bool() == Some(true);
+        self.check_optional(&key("disabled"), &options["disabled"], "a boolean");
+        if is_script {
+            self.check_type(&key("script"), &options["script"], "a string");
+        } else if options["subcommands"].is_badvalue() && !self.is_joat_config && !is_disabled {
+            if options["path"].is_badvalue() {
+                // Fine when it patches a subcommand of another config layer
+                self.add(
+                    Severity::Warning,
+                    &scmd_path,
+                    format!("Request subcommand `{}` has no path", name_str),
+                    Some(String::from(
+                        "add a `path:` (appended to base_endpoint) or a `script:`, unless it patches a subcommand of another config",
+                    )),
+                );
+            } else {
+                self.check_type(&key("path"), &options["path"], "a string");
+            }
+        }
+        if let Some(method) = options["method"].as_str() {
+            let is_template = method.contains("{{");
+            if !is_template && !HTTP_METHODS.contains(&method.to_lowercase().as_str()) {
+                self.error(
+                    &key("method"),
+                    format!("Unknown http method `{}`", method),
+                    Some("use get, post, put, patch, delete, head or options"),
+                );
+            }
+        }
+        self.check_optional(&key("base_endpoint"), &options["base_endpoint"], "a string");
+        self.check_optional(&key("vars"), &options["vars"], "a map");
+        self.check_string_map(&key("headers"), &options["headers"]);
+        self.check_string_map(&key("query_params"), &options["query_params"]);
+        self.check_optional(&key("body"), &options["body"], "a map");
+        self.check_optional(&key("form"), &options["form"], "a map");
+        self.check_optional(
+            &key("response_template"),
+            &options["response_template"],
+            "a string",
+        );
+        self.check_auth(&key("auth"), &options["auth"]);
+        self.check_optional(&key("netrc"), &options["netrc"], "a boolean");
+        if !options["timeout"].is_badvalue() && options["timeout"].as_str().is_none() {
+            self.check_type(&key("timeout"), &options["timeout"], "an integer");
+        }
+        self.check_args(&key("args"), &options["args"], is_script);
+        if !options["subcommands"].is_badvalue() {
+            self.check_subcommands(&key("subcommands"), &options["subcommands"]);
+        }
+    }
+
+    fn check_subcommands(&mut self, path: &str, scmds: &Yaml) {
+        if !self.check_type(path, scmds, "a list") {
+            return;
+        }
+        let mut names = HashSet::new();
+        for (i, scmd) in scmds.as_vec().unwrap().iter().enumerate() {
+            let item_path = format!("{}[{}]", path, i);
+            // A list with a non string path is a broken include, not a subcommand named include
+            let include = match &scmd["include"] {
+                Yaml::Array(_) if scmd.as_hash().map(|h| h.len()) == Some(1) => &scmd["include"],
+                _ => yaml::get_include_directive(scmd),
+            };
+            if !include.is_badvalue() {
+                self.check_include(&join_path(&item_path, "include"), include);
+                continue;
+            }
+            self.check_subcommand(&item_path, scmd);
+            if let Some(name) = scmd.as_hash().and_then(|h| h.keys().next()) {
+                let name_str = yaml::get_key_str(name);
+                if !names.insert(name_str.clone()) {
+                    self.add(
+                        Severity::Warning,
+                        &item_path,
+                        format!(
+                            "Subcommand `{}` is defined twice, the first one wins",
+                            name_str
+                        ),
+                        None,
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_templates(&mut self, path: &str, value: &Yaml) {
+        match value {
+            Yaml::String(s) if s.contains("{{") || s.contains("{%") => {
+                let mut tera = Tera::default();
+                if let Err(e) = tera.add_raw_template(path, s) {
+                    self.error(
+                        path,
+                        format!("Invalid template in `{}`: {}", path, get_template_error(&e)),
+                        Some("templates use the Tera syntax, e.g. {{args.NAME}}"),
+                    );
+                }
+            }
+            Yaml::Hash(h) => {
+                for (k, v) in h.iter() {
+                    self.check_templates(&join_path(path, &yaml::get_key_str(k)), v);
+                }
+            }
+            Yaml::Array(a) => {
+                for (i, v) in a.iter().enumerate() {
+                    self.check_templates(&format!("{}[{}]", path, i), v);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn check_config(&mut self, config: &Yaml) {
+        if self.is_include && config.as_vec().is_some() {
+            self.check_subcommands("", config);
+            self.check_templates("", config);
+            return;
+        }
+        if !self.check_type("", config, "a map") {
+            return;
+        }
+        self.is_joat_config = config["name"].as_str() == Some("joat");
+        self.check_unknown_keys("", config, &TOP_LEVEL_KEYS);
+        self.check_common_keys("", config);
+        self.check_profiles(config);
+        self.check_args("args", &config["args"], true);
+        self.check_include("include", &config["include"]);
+        // A layer can only set options, the merged config is checked for subcommands
+        if !config["subcommands"].is_badvalue() {
+            self.check_subcommands("subcommands", &config["subcommands"]);
+        }
+        self.check_templates("", config);
+    }
+}
+
+///
+/// Validates a config file content, findings carry the file name and line.
+///
+/// Included files (`is_include`) may be a list of subcommands or a config without `subcommands`.
+///
+pub fn lint_str(file: &str, content: &str, is_include: bool) -> Vec<Finding> {
+    let mut linter = Linter {
+        file: file.to_string(),
+        lines: HashMap::new(),
+        findings: Vec::new(),
+        is_joat_config: false,
+        is_include,
+    };
+    let config = match YamlLoader::load_from_str(content) {
+        Ok(docs) => docs.into_iter().next().unwrap_or(Yaml::Null),
+        Err(e) => {
+            linter.error(
+                "",
+                format!("Invalid YAML: {}", e),
+                Some("check the indentation and quote values starting with { or *"),
+            );
+            return linter.findings;
+        }
+    };
+    let mut recorder = LineRecorder::default();
+    if Parser::new(content.chars())
+        .load(&mut recorder, false)
+        .is_ok()
+    {
+        linter.lines = recorder.lines;
+    }
+    linter.check_config(&config);
+    linter.findings
+}
+
+///
+/// Lints a config file, or a file it includes when `is_include` is set.
+///
+pub fn lint_file(path: &str, is_include: bool) -> error::Result<Vec<Finding>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| JoatError::Io(format!("Could not read {}: {}", path, e)))?;
+    Ok(lint_str(path, &content, is_include))
+}
+
+///
+/// Startup validation: fails with the errors of a config file, warnings are left to `joat lint`.
+///
+pub fn check_config(file: &str, content: &str, is_include: bool) -> error::Result<()> {
+    let errors: Vec<String> = lint_str(file, content, is_include)
+        .into_iter()
+        .filter(|f| f.severity == Severity::Error)
+        .map(|f| f.to_string())
+        .collect();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(JoatError::Config(format!(
+        "{}\nRun `joat lint {}` for details",
+        errors.join("\n"),
+        file
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "name: demo
+version: \"0.1\"
+base_endpont: http://example.com
+headers:
+    X-Count: 1
+subcommands:
+    - show:
+        about: Show
+        args:
+            - TEMPLATE:
+                short: t
+    - open:
+        about: Open
+        script: open {{ args.ID
+";
+
+    fn get_messages(findings: &Vec<Finding>) -> Vec<String> {
+        findings.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn test_lint_str_findings_have_lines() {
+        // Act
+        let findings = lint_str("demo.yml", CONFIG, false);
+
+        // Assert
+        let messages = get_messages(&findings);
+        assert_eq!(
+            messages[0],
+            "demo.yml:3: warning: Unknown key `base_endpont`\n    hint: did you mean `base_endpoint`?"
+        );
+        assert_eq!(
+            messages[1],
+            "demo.yml:5: error: `headers.X-Count` should be a string, found an integer\n    hint: quote the value, e.g. \"123\""
+        );
+        assert!(
+            messages[2].starts_with("demo.yml:7: warning: Request subcommand `show` has no path")
+        );
+        assert!(messages[3].starts_with("demo.yml:10: error: Arg `subcommands[0].show.args[0].TEMPLATE` clashes with joat's -t/--template"));
+        assert!(messages[4]
+            .starts_with("demo.yml:14: error: Invalid template in `subcommands[1].open.script`"));
+        assert_eq!(findings.len(), 5);
+    }
+
+    #[test]
+    fn test_lint_str_invalid_yaml() {
+        // Act
+        let findings = lint_str("demo.yml", "subcommands: [\n", false);
+
+        // Assert
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert!(findings[0].message.starts_with("Invalid YAML"));
+    }
+
+    #[test]
+    fn test_lint_str_valid_config() {
+        // Act
+        let findings = lint_str(
+            "demo.yml",
+            "name: demo\nsubcommands:\n    - show:\n        path: /x/{{args.ID}}\n",
+            false,
+        );
+
+        // Assert
+        assert!(findings.is_empty(), "{:?}", get_messages(&findings));
+    }
+
+    #[test]
+    fn test_lint_str_reserved_arg_names() {
+        // Act
+        let findings = lint_str(
+            "demo.yml",
+            "subcommands:\n    - deploy:\n        path: /d\n        args:\n            - config: {long: file}\n",
+            false,
+        );
+
+        // Assert
+        let messages = get_messages(&findings);
+        assert_eq!(messages.len(), 1, "{:?}", messages);
+        assert!(messages[0].contains("clashes with joat's --config"));
+    }
+
+    #[test]
+    fn test_lint_str_overlay_without_subcommands() {
+        // Act
+        let findings = lint_str(
+            "project.yml",
+            "vars: {team: core}\nheaders: {X-Team: core}\n",
+            false,
+        );
+
+        // Assert
+        assert!(findings.is_empty(), "{:?}", get_messages(&findings));
+    }
+
+    #[test]
+    fn test_lint_str_included_file() {
+        // Arrange
+        let content = "- include: [issues/*.yml, 3]\n- show:\n    path: /x\n";
+
+        // Act
+        let findings = lint_str("resources.yml", content, true);
+        let config_findings = lint_str("demo.yml", "name: demo\ninclude: resources.yml\n", false);
+
+        // Assert
+        assert_eq!(
+            get_messages(&findings),
+            vec!["resources.yml:1: error: `[0].include[1]` should be a string"]
+        );
+        assert!(
+            config_findings.is_empty(),
+            "{:?}",
+            get_messages(&config_findings)
+        );
+    }
+}
@@ -20,12 +20,35 @@ use std::fs::File;
 use std::path::Path;
 use yaml_rust::Yaml;
 
+use crate::error::JoatError;
+
+mod auth;
+mod collection;
+mod curl;
+mod error;
+mod explain;
+mod har;
 mod http;
+mod import;
+mod insomnia;
 mod joat_scmds;
+mod jwt_bearer;
+mod layers;
+mod lint;
+mod merge;
+mod netrc;
 mod oauth;
+mod oidc;
+mod openapi;
+mod postman;
+mod profile;
+mod redact;
 mod request_scmd;
 mod script_scmd;
+mod secrets;
+mod sigv4;
 mod template;
+mod trust;
 mod yaml;
 
 type Context = HashMap<String, Value>;
@@ -59,39 +82,109 @@ fn get_args_context(args: &ArgMatches, subcmd_yaml: &Yaml) -> Value {
     return Value::Object(args_context);
 }
 
-fn get_vars_context(yaml: &Yaml, context: &Context) -> Value {
+///
+/// Values of the args of every subcommand level, a subcommand overrides the args of its groups.
+///
+fn get_levels_args_context(levels: &[(Yaml, &ArgMatches)]) -> Value {
+    let mut args_context = Map::new();
+    for (scmd_yaml, args) in levels {
+        if let Value::Object(level_args) = get_args_context(args, scmd_yaml) {
+            args_context.extend(level_args);
+        }
+    }
+    Value::Object(args_context)
+}
+
+///
+/// Follows the matched subcommands down the subcommand groups, from the top level subcommand to
+/// the one to execute, with the matches of each level.
+///
+fn get_subcommand_levels<'a>(
+    subcmd_yaml: Yaml,
+    args: &'a ArgMatches<'a>,
+) -> error::Result<Vec<(Yaml, &'a ArgMatches<'a>)>> {
+    let mut levels = vec![(subcmd_yaml, args)];
+    loop {
+        let (scmd_yaml, scmd_args) = levels.last().unwrap();
+        if scmd_yaml["subcommands"].is_badvalue() {
+            break;
+        }
+        let level = match scmd_args.subcommand() {
+            (name, Some(child_args)) => {
+                (yaml::get_subcommand_from_yaml(name, scmd_yaml)?, child_args)
+            }
+            _ => break,
+        };
+        levels.push(level);
+    }
+    Ok(levels)
+}
+
+fn get_vars_context(yaml: &Yaml, context: &Context) -> error::Result<Value> {
     let vars_yaml = &yaml["vars"];
     if !vars_yaml.is_badvalue() {
-        let r = yaml::get_hash_from_yaml(vars_yaml, context, true);
+        let r = yaml::get_hash_from_yaml(vars_yaml, context, true)?;
         let mut m = Map::new();
         for (key, value) in r {
             m.insert(key, value);
         }
-        return Value::Object(m);
+        return Ok(Value::Object(m));
     }
-    return Value::Object(Map::new());
+    return Ok(Value::Object(Map::new()));
 }
 
-fn get_env_context() -> Value {
+fn get_env_patterns(yaml: &Yaml) -> error::Result<Vec<Regex>> {
+    yaml.clone()
+        .into_iter()
+        .map(|p| {
+            let pattern = yaml::get_string_from_yaml(&p)?;
+            let regex_str = format!("^{}$", regex::escape(&pattern).replace("\\*", ".*"));
+            Regex::new(&regex_str)
+                .map_err(|e| JoatError::Config(format!("Invalid env pattern {}: {}", pattern, e)))
+        })
+        .collect()
+}
+
+///
+/// Environment variables visible to templates, filtered by `env_allow` and `env_deny` (names with
+/// `*` wildcards).
+///
+pub fn get_env_context(yaml: &Yaml) -> error::Result<Value> {
+    let allow_patterns = get_env_patterns(&yaml["env_allow"])?;
+    let deny_patterns = get_env_patterns(&yaml["env_deny"])?;
     let mut env_vars = Map::new();
     for (key, value) in env::vars() {
+        let explicitly_allowed = allow_patterns.iter().any(|p| p.is_match(&key));
+        let allowed = yaml["env_allow"].is_badvalue() || explicitly_allowed;
+        if !allowed || deny_patterns.iter().any(|p| p.is_match(&key)) {
+            continue;
+        }
+        // Only env vars the config points at, the whole environment has too many false positives
+        if redact::is_redacted_name(&key) || (explicitly_allowed && redact::is_sensitive_name(&key))
+        {
+            redact::add_secret(&value);
+        }
         env_vars.insert(key, Value::from(value));
     }
-    return Value::Object(env_vars);
+    return Ok(Value::Object(env_vars));
 }
 
-fn add_file_variables_to_context(yaml: &Yaml, mut context: Context) -> Context {
+fn add_file_variables_to_context(yaml: &Yaml, mut context: Context) -> error::Result<Context> {
     let files_yaml = &yaml["files"];
     let mut files = Map::new();
     if !files_yaml.is_badvalue() {
-        let files_vec = files_yaml.as_hash().expect("Files should be a hash");
+        let files_vec = files_yaml
+            .as_hash()
+            .ok_or(JoatError::Config(String::from("files should be a map")))?;
         for (filealias, filepath) in files_vec {
-            let filepath_str = filepath.as_str().expect("Filepath should be strings");
+            let filepath_str = filepath.as_str().ok_or(JoatError::Config(format!(
+                "The path of file {:?} should be a string",
+                filealias
+            )))?;
             let compiled_filepath = template::get_compiled_template_str_with_context(
                 &filepath_str.to_string(),
                 &context,
-            )
-            .expect("Could not parse file template");
+            )?;
 
             let path = Path::new(&compiled_filepath);
             let file_content = match File::open(path) {
@@ -105,16 +198,17 @@ fn add_file_variables_to_context(yaml: &Yaml, mut context: Context) -> Context {
             let file_context: Value = match serde_json::from_reader(file_content) {
                 Ok(v) => v,
                 Err(_e) => {
-                    let file_string = fs::read_to_string(path).expect("Could not read file");
+                    let file_string = fs::read_to_string(path)
+                        .map_err(|e| JoatError::Io(format!("Could not read {:?}: {}", path, e)))?;
                     Value::String(file_string)
                 }
             };
-            let filealias_str = filealias.as_str().expect("File alias should be a string");
-            files.insert(filealias_str.to_string(), file_context);
+            let filealias_str = yaml::get_string_from_yaml(filealias)?;
+            files.insert(filealias_str, file_context);
         }
     }
     context.insert(String::from("files"), Value::Object(files));
-    return context;
+    return Ok(context);
 }
 
 fn get_scmd_context(scmd_yaml: &Yaml) -> Value {
@@ -128,65 +222,114 @@ fn get_scmd_context(scmd_yaml: &Yaml) -> Value {
     return Value::Object(scmd_vars);
 }
 
-fn execute(app: App, app_name: &String, cmd_name: &str, args: &ArgMatches, yaml: &Yaml) {
-    let subcmd_yaml = yaml::get_subcommand_from_yaml(cmd_name, yaml);
+fn execute(
+    app: App,
+    app_name: &String,
+    cmd_name: &str,
+    args: &ArgMatches,
+    yaml: &Yaml,
+) -> error::Result<()> {
+    let subcmd_yaml = yaml::get_subcommand_from_yaml(cmd_name, yaml)?;
+    // Builtin groups dispatch their subcommands themselves
+    let is_builtin_group = yaml::is_builtin_group(&subcmd_yaml);
+    let levels = match is_builtin_group {
+        true => vec![(subcmd_yaml.clone(), args)],
+        false => get_subcommand_levels(subcmd_yaml.clone(), args)?,
+    };
+    let leaf_yaml = &levels.last().unwrap().0;
 
     let mut context: Context = HashMap::new();
 
-    let env_context = get_env_context();
+    let env_context = get_env_context(yaml)?;
     context.insert(String::from("env"), env_context);
 
-    context = add_file_variables_to_context(&yaml, context);
+    context = add_file_variables_to_context(&yaml, context)?;
 
-    let vars_context = get_vars_context(yaml, &context);
-    let args_context = get_args_context(&args, &subcmd_yaml);
-    let scmd_context = get_scmd_context(&subcmd_yaml);
+    let mut vars_context = get_vars_context(yaml, &context)?;
+    // Subcommands get the vars of their groups, see yaml::inherit_group_options
+    if let (Value::Object(vars), Value::Object(scmd_vars)) =
+        (&mut vars_context, get_vars_context(leaf_yaml, &context)?)
+    {
+        vars.extend(scmd_vars);
+    }
+    if let Some(vars) = vars_context.as_object() {
+        redact::add_sensitive_map_values(vars);
+    }
+    let args_context = get_levels_args_context(&levels);
+    let scmd_context = get_scmd_context(leaf_yaml);
     context.insert(String::from("vars"), vars_context);
     context.insert(String::from("args"), args_context);
     context.insert(String::from("scmd"), scmd_context);
+    let profile_name = profile::get_active_profile().unwrap_or(String::new());
+    context.insert(String::from("profile"), Value::from(profile_name));
+    if !yaml["oauth"].is_badvalue() {
+        context.insert(String::from("auth"), oauth::get_auth_context(app_name)?);
+    }
 
     if app_name == "joat" && cmd_name == "init" {
-        joat_scmds::execute_init(context);
-        return;
+        return joat_scmds::execute_init(context);
+    }
+
+    if app_name == "joat" && cmd_name == "trust" {
+        return joat_scmds::execute_trust(context);
+    }
+
+    if app_name == "joat" && cmd_name == "import" {
+        return joat_scmds::execute_import(args.subcommand_name(), context);
+    }
+
+    if app_name == "joat" && cmd_name == "add-from-curl" {
+        return joat_scmds::execute_add_from_curl(context);
+    }
+
+    if app_name == "joat" && cmd_name == "lint" {
+        return joat_scmds::execute_lint(context);
+    }
+
+    if cmd_name == "auth" && is_builtin_group {
+        return joat_scmds::execute_auth(app_name, yaml, args.subcommand_name(), context);
+    }
+
+    if cmd_name == "secrets" && is_builtin_group {
+        return joat_scmds::execute_secrets(args.subcommand());
+    }
+
+    if cmd_name == "config" && is_builtin_group {
+        return joat_scmds::execute_config(args.subcommand(), yaml, &context);
     }
 
     if cmd_name == "auto_complete" {
-        joat_scmds::execute_auto_complete(app, app_name, context);
-        return;
+        return joat_scmds::execute_auto_complete(app, app_name, context);
     }
 
-    let script = &subcmd_yaml["script"];
+    let script = &leaf_yaml["script"];
     if !script.is_badvalue() {
-        script_scmd::execute_script(context, &subcmd_yaml, &yaml);
+        script_scmd::execute_script(context, leaf_yaml, &yaml)
     } else {
-        request_scmd::execute_request(&app_name, &yaml, &subcmd_yaml, context);
+        request_scmd::execute_request(&app_name, &yaml, leaf_yaml, context)
     }
 }
 
 fn format_cmd_name(cmd_name: &String) -> String {
-    let re = Regex::new("[^/]*$").unwrap();
-    String::from(
-        re.find(cmd_name)
-            .expect("Failed to parse main cmd name")
-            .as_str(),
-    )
+    // The binary or symlink name, without the dirs
+    cmd_name.rsplit('/').next().unwrap_or(cmd_name).to_string()
 }
 
-fn main() {
-    env_logger::init();
-    debug!("Joat started");
-    let args: Vec<String> = env::args().collect();
+fn run(args: &[String]) -> error::Result<()> {
     let app_name = format_cmd_name(&args[0]);
-    let config_yaml = yaml::get_yaml_config(&app_name);
+    secrets::init(&app_name);
+    let config_yaml = yaml::get_yaml_config(&app_name, layers::get_explicit_config(args))?;
 
     let mut app = App::from_yaml(&config_yaml);
 
     let matches = app.clone().get_matches();
+    let profile_yaml = profile::select_profile(&config_yaml, &matches)?;
+    redact::init(&profile_yaml);
 
     match matches.subcommand() {
         (name, sub_cmd_option) => {
             match sub_cmd_option {
-                Some(sub_cmd) => execute(app, &app_name, name, sub_cmd, &config_yaml),
+                Some(sub_cmd) => execute(app, &app_name, name, sub_cmd, &profile_yaml),
                 _ => {
                     // Could not find command, just print help
                     app.print_help().unwrap();
@@ -197,3 +340,14 @@ fn main() {
         }
     }
 }
+
+fn main() {
+    env_logger::init();
+    debug!("Joat started");
+    let args: Vec<String> = env::args().collect();
+    error::init(&args);
+    error::install_panic_hook();
+    if let Err(e) = run(&args) {
+        error::exit(e);
+    }
+}
@@ -0,0 +1,495 @@
+use std::collections::BTreeMap;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, ScanError, Scanner, Token, TokenType};
+use yaml_rust::{Yaml, YamlLoader};
+
+const SUBCOMMANDS_KEY: &str = "subcommands";
+const DISABLED_KEY: &str = "disabled";
+
+///
+/// Directives set with a YAML tag on a map or a list, they tell how a config layer combines
+/// with the layers below it.
+///
+/// Until the layers are merged a tagged value is kept wrapped in a map with the directive as its
+/// only key, e.g. `{"!replace": {...}}`.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Directive {
+    // Drop the value of the lower layers
+    Replace,
+    // Add the items of a list after the ones of the lower layers
+    Append,
+}
+
+impl Directive {
+    fn from_tag(handle: &str, suffix: &str) -> Option<Directive> {
+        match (handle, suffix) {
+            ("!", "replace") => Some(Directive::Replace),
+            ("!", "append") => Some(Directive::Append),
+            _ => None,
+        }
+    }
+
+    pub fn key(self) -> &'static str {
+        match self {
+            Directive::Replace => "!replace",
+            Directive::Append => "!append",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum PathElem {
+    Key(String),
+    Index(usize),
+}
+
+enum Frame {
+    Map {
+        path: Vec<PathElem>,
+        key: Option<String>,
+    },
+    Seq {
+        path: Vec<PathElem>,
+        index: usize,
+    },
+}
+
+///
+/// Finds the maps and lists starting at the tagged positions, yaml_rust::Yaml drops their tags.
+///
+struct DirectiveRecorder {
+    tagged: Vec<(Marker, Directive)>,
+    stack: Vec<Frame>,
+    directives: Vec<(Vec<PathElem>, Directive)>,
+}
+
+impl DirectiveRecorder {
+    fn start_value(&mut self) -> Vec<PathElem> {
+        let mut value_path = Vec::new();
+        match self.stack.last() {
+            Some(Frame::Map { path, key: Some(k) }) => {
+                value_path.extend(path.iter().cloned());
+                value_path.push(PathElem::Key(k.clone()));
+            }
+            Some(Frame::Seq { path, index }) => {
+                value_path.extend(path.iter().cloned());
+                value_path.push(PathElem::Index(*index));
+            }
+            _ => (),
+        }
+        value_path
+    }
+
+    fn end_value(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Map { key, .. }) => *key = None,
+            Some(Frame::Seq { index, .. }) => *index += 1,
+            None => (),
+        }
+    }
+
+    fn start_collection(&mut self, mark: Marker, is_map: bool) {
+        let path = self.start_value();
+        if let Some((_, d)) = self.tagged.iter().find(|(m, _)| *m == mark) {
+            self.directives.push((path.clone(), *d));
+        }
+        match is_map {
+            true => self.stack.push(Frame::Map { path, key: None }),
+            false => self.stack.push(Frame::Seq { path, index: 0 }),
+        }
+    }
+}
+
+impl MarkedEventReceiver for DirectiveRecorder {
+    fn on_event(&mut self, ev: &Event, mark: Marker) {
+        match ev {
+            Event::Scalar(value, ..) => {
+                if let Some(Frame::Map {
+                    key: key @ None, ..
+                }) = self.stack.last_mut()
+                {
+                    *key = Some(value.clone());
+                    return;
+                }
+                self.start_value();
+                self.end_value();
+            }
+            Event::Alias(_) => {
+                self.start_value();
+                self.end_value();
+            }
+            Event::MappingStart(_) => self.start_collection(mark, true),
+            Event::SequenceStart(_) => self.start_collection(mark, false),
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.end_value();
+            }
+            _ => (),
+        }
+    }
+}
+
+///
+/// Positions of the nodes following a directive tag, an anchor can sit between them.
+///
+fn get_tagged_markers(content: &str) -> Vec<(Marker, Directive)> {
+    let mut tagged = Vec::new();
+    let mut pending = None;
+    for Token(mark, token) in Scanner::new(content.chars()) {
+        match (pending, token) {
+            (_, TokenType::Tag(handle, suffix)) => pending = Directive::from_tag(&handle, &suffix),
+            (Some(_), TokenType::Anchor(_)) => (),
+            (Some(d), _) => {
+                tagged.push((mark, d));
+                pending = None;
+            }
+            _ => (),
+        }
+    }
+    tagged
+}
+
+fn key_matches(key: &Yaml, path_key: &str) -> bool {
+    *key == Yaml::from_str(path_key) || *key == Yaml::String(path_key.to_string())
+}
+
+fn wrap(directive: Option<Directive>, value: Yaml) -> Yaml {
+    match directive {
+        Some(d) => {
+            let mut wrapper = BTreeMap::new();
+            wrapper.insert(Yaml::String(d.key().to_string()), value);
+            Yaml::Hash(wrapper)
+        }
+        None => value,
+    }
+}
+
+fn wrap_at(yaml: &mut Yaml, path: &[PathElem], directive: Directive) {
+    let value = match path.split_first() {
+        None => {
+            let value = ::std::mem::replace(yaml, Yaml::Null);
+            *yaml = wrap(Some(directive), value);
+            return;
+        }
+        Some((PathElem::Key(k), _)) => yaml
+            .as_hash()
+            .and_then(|h| h.keys().find(|key| key_matches(key, k)).cloned())
+            .and_then(|key| match yaml {
+                Yaml::Hash(h) => h.get_mut(&key),
+                _ => None,
+            }),
+        Some((PathElem::Index(i), _)) => match yaml {
+            Yaml::Array(a) => a.get_mut(*i),
+            _ => None,
+        },
+    };
+    if let Some(v) = value {
+        wrap_at(v, &path[1..], directive);
+    }
+}
+
+///
+/// Loads a config layer, keeping the `!replace` and `!append` directives for `merge_layers`.
+///
+pub fn load_str(content: &str) -> Result<Yaml, ScanError> {
+    let mut yaml = YamlLoader::load_from_str(content)?
+        .into_iter()
+        .next()
+        .unwrap_or(Yaml::Null);
+    let tagged = get_tagged_markers(content);
+    if tagged.is_empty() {
+        return Ok(yaml);
+    }
+    let mut recorder = DirectiveRecorder {
+        tagged,
+        stack: Vec::new(),
+        directives: Vec::new(),
+    };
+    Parser::new(content.chars()).load(&mut recorder, false)?;
+    // Nested values first, wrapping a value changes the path of its children
+    for (path, directive) in recorder.directives.iter().rev() {
+        wrap_at(&mut yaml, path, *directive);
+    }
+    Ok(yaml)
+}
+
+///
+/// The directive of a config value, if any, and the value it wraps.
+///
+pub fn split(yaml: &Yaml) -> (Option<Directive>, &Yaml) {
+    if let Some(h) = yaml.as_hash() {
+        if h.len() == 1 {
+            for d in [Directive::Replace, Directive::Append].iter() {
+                if let Some(v) = h.get(&Yaml::String(d.key().to_string())) {
+                    return (Some(*d), v);
+                }
+            }
+        }
+    }
+    (None, yaml)
+}
+
+///
+/// The value of a config key, without the directive wrapping it.
+///
+pub fn get_value(yaml: &Yaml) -> &Yaml {
+    split(yaml).1
+}
+
+pub fn get_value_mut(yaml: &mut Yaml) -> &mut Yaml {
+    if split(yaml).0.is_none() {
+        return yaml;
+    }
+    match yaml {
+        Yaml::Hash(h) => h.values_mut().next().unwrap(),
+        other => other,
+    }
+}
+
+fn get_scmd_name(scmd: &Yaml) -> Option<&Yaml> {
+    scmd.as_hash().and_then(|h| h.keys().next())
+}
+
+fn get_scmd_options(scmd: &Yaml) -> Option<&Yaml> {
+    scmd.as_hash().and_then(|h| h.values().next())
+}
+
+fn merge_scmd(overrider: &Yaml, overriden: &Yaml) -> Yaml {
+    let (name, options, lower_options) = match (
+        get_scmd_name(overrider),
+        get_scmd_options(overrider),
+        get_scmd_options(overriden),
+    ) {
+        (Some(n), Some(o), Some(l)) => (n, o, l),
+        _ => return overrider.clone(),
+    };
+    let mut merged = BTreeMap::new();
+    merged.insert(name.clone(), merge_value(options, lower_options, true));
+    Yaml::Hash(merged)
+}
+
+///
+/// Subcommands are merged by name, the ones of the upper layer come first.
+///
+fn merge_scmds(overrider: &Yaml, overriden: &Yaml) -> Yaml {
+    let (directive, upper) = split(overrider);
+    let (lower_directive, lower) = split(overriden);
+    let (upper_scmds, lower_scmds) = match (directive, upper, lower) {
+        (Some(Directive::Replace), ..) => return overrider.clone(),
+        (_, Yaml::Array(u), Yaml::Array(l)) => (u, l),
+        _ => return overrider.clone(),
+    };
+    let mut scmds = Vec::new();
+    for scmd in upper_scmds {
+        let lower_scmd = lower_scmds
+            .iter()
+            .find(|s| get_scmd_name(s).is_some() && get_scmd_name(s) == get_scmd_name(scmd));
+        match lower_scmd {
+            Some(l) => scmds.push(merge_scmd(scmd, l)),
+            None => scmds.push(scmd.clone()),
+        }
+    }
+    for scmd in lower_scmds {
+        let is_overriden = upper_scmds
+            .iter()
+            .any(|s| get_scmd_name(s).is_some() && get_scmd_name(s) == get_scmd_name(scmd));
+        if !is_overriden {
+            scmds.push(scmd.clone());
+        }
+    }
+    wrap(lower_directive, Yaml::Array(scmds))
+}
+
+fn merge_hashes(
+    overrider: &BTreeMap<Yaml, Yaml>,
+    overriden: &BTreeMap<Yaml, Yaml>,
+    is_config: bool,
+) -> BTreeMap<Yaml, Yaml> {
+    let mut result = BTreeMap::new();
+    for (k, v) in overrider.iter() {
+        let merged = match overriden.get(k) {
+            Some(l) if is_config && k.as_str() == Some(SUBCOMMANDS_KEY) => merge_scmds(v, l),
+            Some(l) => merge_value(v, l, false),
+            None => v.clone(),
+        };
+        result.insert(k.clone(), merged);
+    }
+    for (k, v) in overriden.iter() {
+        if !result.contains_key(k) {
+            result.insert(k.clone(), v.clone());
+        }
+    }
+    result
+}
+
+///
+/// Directives are kept in the result for the layers merged later: a `!replace` keeps replacing,
+/// a value merged over a `!replace` still replaces the layers below it and an `!append` keeps
+/// appending until it meets a list without directive.
+///
+fn merge_value(overrider: &Yaml, overriden: &Yaml, is_config: bool) -> Yaml {
+    let (directive, upper) = split(overrider);
+    let (lower_directive, lower) = split(overriden);
+    match (directive, upper, lower) {
+        (Some(Directive::Replace), ..) => overrider.clone(),
+        (Some(Directive::Append), Yaml::Array(u), Yaml::Array(l)) => {
+            let mut items = l.clone();
+            items.extend(u.iter().cloned());
+            // A list without directive already replaces the layers below it
+            wrap(
+                Some(lower_directive.unwrap_or(Directive::Replace)),
+                Yaml::Array(items),
+            )
+        }
+        (_, Yaml::Hash(u), Yaml::Hash(l)) => {
+            wrap(lower_directive, Yaml::Hash(merge_hashes(u, l, is_config)))
+        }
+        _ => overrider.clone(),
+    }
+}
+
+///
+/// Merges a config layer over a lower one: maps are merged deeply, subcommands by name, and
+/// other values (lists included) replace the lower ones. `!replace` drops the lower value of a
+/// map or a subcommand and `!append` adds the items of a list to the lower ones.
+///
+pub fn merge_layers(overrider: &Yaml, overriden: &Yaml) -> Yaml {
+    merge_value(overrider, overriden, true)
+}
+
+fn strip_directives(yaml: Yaml) -> Yaml {
+    let yaml = match split(&yaml) {
+        (Some(_), v) => v.clone(),
+        (None, _) => yaml,
+    };
+    match yaml {
+        Yaml::Hash(h) => Yaml::Hash(
+            h.into_iter()
+                .map(|(k, v)| (k, strip_directives(v)))
+                .collect(),
+        ),
+        Yaml::Array(a) => Yaml::Array(a.into_iter().map(strip_directives).collect()),
+        v => v,
+    }
+}
+
+fn is_disabled(scmd: &Yaml) -> bool {
+    get_scmd_options(scmd)
+        .and_then(|options| options[DISABLED_KEY].as_bool())
+        .unwrap_or(false)
+}
+
+fn remove_disabled_scmds(config: &mut Yaml) {
+    let scmds = match config {
+        Yaml::Hash(h) => match h.get_mut(&Yaml::String(SUBCOMMANDS_KEY.to_string())) {
+            Some(Yaml::Array(scmds)) => scmds,
+            _ => return,
+        },
+        _ => return,
+    };
+    scmds.retain(|s| !is_disabled(s));
+    for scmd in scmds.iter_mut() {
+        if let Yaml::Hash(h) = scmd {
+            for options in h.values_mut() {
+                remove_disabled_scmds(options);
+            }
+        }
+    }
+}
+
+///
+/// The config once every layer is merged: directives are dropped and so are the subcommands
+/// with `disabled: true`.
+///
+pub fn resolve(config: Yaml) -> Yaml {
+    let mut config = strip_directives(config);
+    remove_disabled_scmds(&mut config);
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge_str(layers: &[&str]) -> Yaml {
+        let mut config = load_str(layers[0]).unwrap();
+        for layer in layers[1..].iter() {
+            config = merge_layers(&config, &load_str(layer).unwrap());
+        }
+        resolve(config)
+    }
+
+    #[test]
+    fn test_merge_layers_deep_merges_maps_and_subcommands() {
+        // Arrange
+        let home = "vars: {a: 1, b: 2}
+headers: {X-A: a}
+subcommands:
+    - show: {path: /show, response_template: show.j2}
+    - list: {path: /list}
+";
+        let project = "vars: {b: 3}
+subcommands:
+    - show: {response_template: other.j2}
+    - list: {disabled: true}
+    - new: {path: /new}
+";
+
+        // Act
+        let config = merge_str(&[project, home]);
+
+        // Assert
+        let scmd_names: Vec<&str> = config["subcommands"]
+            .as_vec()
+            .unwrap()
+            .iter()
+            .map(|s| get_scmd_name(s).unwrap().as_str().unwrap())
+            .collect();
+        assert_eq!(config["vars"]["a"].as_i64(), Some(1));
+        assert_eq!(config["vars"]["b"].as_i64(), Some(3));
+        assert_eq!(config["headers"]["X-A"].as_str(), Some("a"));
+        assert_eq!(scmd_names, vec!["show", "new"]);
+        assert_eq!(
+            config["subcommands"][0]["show"]["path"].as_str(),
+            Some("/show")
+        );
+        assert_eq!(
+            config["subcommands"][0]["show"]["response_template"].as_str(),
+            Some("other.j2")
+        );
+    }
+
+    #[test]
+    fn test_merge_layers_directives() {
+        // Arrange
+        let home = "vars: {a: 1}\nredact: [x]\nsubcommands:\n    - show: {path: /a, method: put}\n";
+        let team = "vars: {b: 2}\nredact: [y]\n";
+        let project = "vars: !replace
+    c: 3
+redact: !append [z]
+subcommands:
+    - show: !replace {path: /b}
+";
+
+        // Act
+        let config = merge_str(&[project, team, home]);
+
+        // Assert
+        assert_eq!(config["vars"]["c"].as_i64(), Some(3));
+        assert!(config["vars"]["a"].is_badvalue());
+        assert!(config["vars"]["b"].is_badvalue());
+        assert_eq!(
+            config["redact"],
+            Yaml::Array(vec![
+                Yaml::String(String::from("y")),
+                Yaml::String(String::from("z"))
+            ])
+        );
+        assert_eq!(
+            config["subcommands"][0]["show"]["path"].as_str(),
+            Some("/b")
+        );
+        assert!(config["subcommands"][0]["show"]["method"].is_badvalue());
+    }
+}
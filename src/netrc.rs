@@ -0,0 +1,172 @@
+use log::debug;
+use std::env;
+use std::fs;
+use std::path::Path;
+use url::Url;
+
+use crate::error::{self, JoatError};
+
+#[derive(Debug, PartialEq)]
+pub struct NetrcCredentials {
+    pub login: String,
+    pub password: String,
+}
+
+fn get_netrc_file_path() -> error::Result<String> {
+    match env::var("NETRC") {
+        Ok(p) => Ok(p),
+        Err(_) => {
+            let home_dir_path =
+                dirs::home_dir().ok_or(JoatError::Io(String::from("Could not find home dir")))?;
+            Ok(format!("{}/.netrc", home_dir_path.to_string_lossy()))
+        }
+    }
+}
+
+fn get_tokens(content: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut in_macdef = false;
+    for line in content.lines() {
+        // Macro definitions run until the next empty line
+        if in_macdef {
+            in_macdef = !line.trim().is_empty();
+            continue;
+        }
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        for token in line.split_whitespace() {
+            if token == "macdef" {
+                in_macdef = true;
+                break;
+            }
+            tokens.push(token.to_string());
+        }
+    }
+    tokens
+}
+
+#[derive(Default)]
+struct NetrcEntry {
+    // None for the default entry
+    machine: Option<String>,
+    login: Option<String>,
+    password: Option<String>,
+}
+
+fn get_entries(content: &str) -> Vec<NetrcEntry> {
+    let tokens = get_tokens(content);
+    let mut entries: Vec<NetrcEntry> = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let value = tokens.get(i + 1).cloned();
+        match tokens[i].as_str() {
+            "machine" => {
+                entries.push(NetrcEntry {
+                    machine: value,
+                    ..Default::default()
+                });
+                i += 2;
+            }
+            "default" => {
+                entries.push(NetrcEntry::default());
+                i += 1;
+            }
+            key @ "login" | key @ "password" | key @ "account" => {
+                if let Some(entry) = entries.last_mut() {
+                    match key {
+                        "login" => entry.login = value,
+                        "password" => entry.password = value,
+                        _ => (),
+                    }
+                }
+                i += 2;
+            }
+            _ => i += 1,
+        }
+    }
+    entries
+}
+
+///
+/// Finds the credentials of a host the way curl does: the first `machine` entry matching the host,
+/// falling back to `default`.
+///
+fn find_credentials(content: &str, host: &str) -> Option<NetrcCredentials> {
+    let entries = get_entries(content);
+    let entry = entries
+        .iter()
+        .find(|e| e.machine.as_ref().map(|m| m.as_str()) == Some(host))
+        .or(entries.iter().find(|e| e.machine.is_none()))?;
+    Some(NetrcCredentials {
+        login: entry.login.clone()?,
+        password: entry.password.clone()?,
+    })
+}
+
+///
+/// Looks up the credentials for the endpoint host in `~/.netrc` or the file named by `NETRC`.
+///
+pub fn get_credentials(endpoint: &str) -> error::Result<Option<NetrcCredentials>> {
+    let url = Url::parse(endpoint)
+        .map_err(|e| JoatError::Config(format!("Invalid endpoint {}: {}", endpoint, e)))?;
+    let host = match url.host_str() {
+        Some(h) => h,
+        None => return Ok(None),
+    };
+    let netrc_path = get_netrc_file_path()?;
+    if !Path::new(&netrc_path).exists() {
+        debug!("No netrc file in {}", netrc_path);
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&netrc_path)
+        .map_err(|e| JoatError::Io(format!("Could not read {}: {}", netrc_path, e)))?;
+    Ok(find_credentials(&content, host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NETRC: &str = "machine api.example.com\n  login alice\n  password s3cret\n\nmacdef init\ncd /tmp\n\nmachine gitlab.com login bob password pw\ndefault login anonymous password guest\n";
+
+    #[test]
+    fn test_find_credentials() {
+        // Act
+        let credentials = find_credentials(NETRC, "api.example.com");
+
+        // Assert
+        assert_eq!(
+            credentials,
+            Some(NetrcCredentials {
+                login: String::from("alice"),
+                password: String::from("s3cret"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_find_credentials_falls_back_to_default() {
+        // Act
+        let credentials = find_credentials(NETRC, "other.com").unwrap();
+
+        // Assert
+        assert_eq!(credentials.login, "anonymous");
+        assert_eq!(credentials.password, "guest");
+    }
+
+    #[test]
+    fn test_find_credentials_without_match() {
+        // Act, Assert
+        assert_eq!(
+            find_credentials("machine a.com login x password y", "b.com"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_get_credentials_invalid_endpoint() {
+        // Act, Assert
+        assert!(get_credentials("not an endpoint").is_err());
+    }
+}
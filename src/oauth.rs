@@ -7,27 +7,61 @@ use oauth2::basic::{BasicErrorResponse, BasicTokenType};
 use oauth2::helpers;
 use oauth2::reqwest::http_client;
 use oauth2::{
-    AccessToken, Client, EmptyExtraTokenFields, ExtraTokenFields, RefreshToken, Scope,
-    TokenResponse, TokenType,
+    AccessToken, Client, ExtraTokenFields, RefreshToken, Scope, TokenResponse, TokenType,
 };
 use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, TokenUrl,
+    AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
+    RedirectUrl, TokenUrl,
 };
 
+use chrono::{TimeZone, Utc};
+use log::{debug, info};
 use serde::{Deserialize, Serialize};
+use serde_json::value::Value;
+use serde_json::Map;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
 use std::fs;
-use std::io::{BufRead, BufReader, Write};
-use std::net::TcpListener;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
 use std::path::Path;
-use std::process::Command;
-use std::time::Duration;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use url::Url;
+use yaml_rust::Yaml;
+
+use crate::error::{self, JoatError};
+use crate::oidc::{self, ProviderMetadata};
+use crate::{profile, redact, secrets};
+use crate::{template, yaml, Context};
+
+const DEFAULT_REDIRECT_PORT: u16 = 8080;
+const DEFAULT_LOGIN_TIMEOUT_SECS: u64 = 300;
+const CALLBACK_READ_TIMEOUT_SECS: u64 = 5;
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+// Refresh tokens a bit before they expire so they don't expire mid request
+const EXPIRY_MARGIN_SECS: u64 = 60;
 
 //
 // Custom oauth client implementation for non standard oauth providers
 //
 
-type SpecialTokenResponse = NonStandardTokenResponse<EmptyExtraTokenFields>;
+///
+/// OpenID Connect providers return an ID token alongside the access token.
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IdTokenFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    id_token: Option<String>,
+}
+
+impl ExtraTokenFields for IdTokenFields {}
+
+type SpecialTokenResponse = NonStandardTokenResponse<IdTokenFields>;
 type SpecialClient = Client<BasicErrorResponse, SpecialTokenResponse, BasicTokenType>;
 
 fn default_token_type() -> Option<BasicTokenType> {
@@ -114,133 +148,1172 @@ where
     }
 }
 
-fn start_callback_server(
-    client_id_str: String,
-    client_secret_str: String,
-    client: SpecialClient,
-    _csrf_state: CsrfToken,
-) -> Option<String> {
-    // TODO: is there a non naive way of doing this?
-    let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
-    for stream in listener.incoming() {
-        if let Ok(mut stream) = stream {
-            let code;
-            {
-                let mut reader = BufReader::new(&stream);
-
-                let mut request_line = String::new();
-                reader.read_line(&mut request_line).unwrap();
-
-                let redirect_url = request_line.split_whitespace().nth(1).unwrap();
-                let url = Url::parse(&("http://localhost".to_string() + redirect_url)).unwrap();
-
-                let code_pair = url
-                    .query_pairs()
-                    .find(|pair| {
-                        let &(ref key, _) = pair;
-                        key == "code"
-                    })
-                    .unwrap();
-
-                let (_, value) = code_pair;
-                code = AuthorizationCode::new(value.into_owned());
+enum CallbackResult {
+    Code(AuthorizationCode),
+    Failed(String),
+    Rejected(String),
+}
+
+fn get_callback_result(
+    request_line: &str,
+    redirect_path: &str,
+    csrf_state: &CsrfToken,
+) -> CallbackResult {
+    let redirect_url = match request_line.split_whitespace().nth(1) {
+        Some(u) => u,
+        None => return CallbackResult::Rejected(String::from("Malformed request")),
+    };
+    let url = match Url::parse(&("http://localhost".to_string() + redirect_url)) {
+        Ok(u) => u,
+        Err(_e) => return CallbackResult::Rejected(String::from("Malformed redirect url")),
+    };
+    let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+
+    if url.path() != redirect_path
+        || (!params.contains_key("code") && !params.contains_key("error"))
+    {
+        // Browsers also ask for things like favicon.ico
+        return CallbackResult::Rejected(String::from("Not an oauth callback"));
+    }
+    match params.get("state") {
+        Some(state) if state == csrf_state.secret() => (),
+        _ => {
+            return CallbackResult::Rejected(String::from(
+                "State does not match the authorization request",
+            ))
+        }
+    }
+    if let Some(error) = params.get("error") {
+        let message = match params.get("error_description") {
+            Some(d) => format!("{}: {}", error, d),
+            None => error.to_string(),
+        };
+        return CallbackResult::Failed(message);
+    }
+    CallbackResult::Code(AuthorizationCode::new(params["code"].clone()))
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_callback_response(mut stream: &TcpStream, status: &str, message: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\ncontent-type: text/html\r\ncontent-length: {}\r\n\r\n{}",
+        status,
+        message.len(),
+        message
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        debug!("Could not answer oauth callback {:?}", e);
+    }
+}
+
+fn bind_callback_listener(config: &OAuthConfig) -> error::Result<TcpListener> {
+    // localhost is what users register as redirect, but only bind the loopback interface
+    let bind_host = match config.redirect_host.as_str() {
+        "localhost" => "127.0.0.1",
+        h => h,
+    };
+    let address = format!("{}:{}", bind_host, config.redirect_port);
+    TcpListener::bind(&address).map_err(|e| {
+        JoatError::Network(format!(
+            "Could not listen on {} for the oauth redirect: {}, set oauth.redirect_port to a free port, or 0 to pick any free port",
+            address, e
+        ))
+    })
+}
+
+fn get_redirect_url(config: &OAuthConfig, listener: &TcpListener) -> error::Result<Url> {
+    let port = listener
+        .local_addr()
+        .map_err(|e| JoatError::Network(format!("Could not get callback server address: {}", e)))?
+        .port();
+    let redirect = format!(
+        "http://{}:{}{}",
+        config.redirect_host, port, config.redirect_path
+    );
+    Url::parse(&redirect)
+        .map_err(|e| JoatError::Config(format!("Invalid redirect URL {}: {}", redirect, e)))
+}
+
+fn open_browser(url: &str) -> bool {
+    let mut browsers: Vec<String> = match env::var("BROWSER") {
+        Ok(b) => b.split(':').map(|s| s.to_string()).collect(),
+        Err(_e) => Vec::new(),
+    };
+    let default_browser = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(target_os = "windows") {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    browsers.push(default_browser.to_string());
+    for browser in browsers.iter().filter(|b| !b.is_empty()) {
+        let launched = Command::new(browser)
+            .arg(url)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match launched {
+            Ok(_c) => return true,
+            Err(e) => debug!("Could not launch browser {}: {:?}", browser, e),
+        }
+    }
+    false
+}
+
+///
+/// Waits for the authorization server to redirect the browser back with a code.
+///
+/// Callbacks whose `state` doesn't match the one sent in the authorization request are
+/// rejected and the server keeps waiting for a valid one, until the login timeout.
+///
+fn wait_authorization_code(
+    listener: TcpListener,
+    config: &OAuthConfig,
+    csrf_state: &CsrfToken,
+) -> Result<AuthorizationCode, String> {
+    listener
+        .set_nonblocking(true)
+        .map_err(|e| format!("Could not configure callback server: {}", e))?;
+    let now = || now_secs().map_err(|e| e.to_string());
+    let deadline = now()? + config.login_timeout;
+    while now()? < deadline {
+        let stream = match listener.accept() {
+            Ok((s, _addr)) => s,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(100));
+                continue;
             }
+            Err(_e) => continue,
+        };
+        if stream.set_nonblocking(false).is_err()
+            || stream
+                .set_read_timeout(Some(Duration::from_secs(CALLBACK_READ_TIMEOUT_SECS)))
+                .is_err()
+        {
+            continue;
+        }
+        let mut request_line = String::new();
+        if BufReader::new(&stream)
+            .read_line(&mut request_line)
+            .is_err()
+        {
+            continue;
+        }
+        match get_callback_result(&request_line, &config.redirect_path, csrf_state) {
+            CallbackResult::Code(code) => {
+                let message = include_str!("../templates/oauth_success.html");
+                write_callback_response(&stream, "200 OK", message);
+                return Ok(code);
+            }
+            CallbackResult::Failed(error) => {
+                let message = include_str!("../templates/oauth_error.html")
+                    .replace("{{error}}", &escape_html(&error));
+                write_callback_response(&stream, "200 OK", &message);
+                return Err(error);
+            }
+            CallbackResult::Rejected(reason) => {
+                debug!("Rejected oauth callback {:?}: {}", request_line, reason);
+                write_callback_response(&stream, "400 Bad Request", &escape_html(&reason));
+            }
+        }
+    }
+    Err(format!(
+        "Login was not completed in {} seconds",
+        config.login_timeout
+    ))
+}
+
+pub enum Grant {
+    AuthorizationCode,
+    ClientCredentials,
+    DeviceCode,
+}
+
+pub enum TokenEndpointAuth {
+    // Basic auth header and credentials in the body, what some non standard providers need
+    ClientSecretBasicAndPost,
+    ClientSecretBasic,
+    ClientSecretPost,
+    NoAuth,
+}
+
+pub struct OAuthConfig {
+    pub grant: Grant,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub auth_url: Option<String>,
+    pub token_url: String,
+    pub device_auth_url: Option<String>,
+    pub pkce: bool,
+    pub scopes: Vec<String>,
+    pub extra_auth_params: Vec<(String, String)>,
+    pub token_endpoint_auth: TokenEndpointAuth,
+    pub redirect_host: String,
+    pub redirect_port: u16,
+    pub redirect_path: String,
+    pub login_timeout: u64,
+}
+
+fn get_parsed_oauth_key(oauth_yaml: &Yaml, key: &str, context: &Context) -> error::Result<String> {
+    let raw_value = oauth_yaml[key]
+        .clone()
+        .into_string()
+        .ok_or(JoatError::Config(format!("Missing oauth {}", key)))?;
+    Ok(template::get_compiled_template_str_with_context(
+        &raw_value, context,
+    )?)
+}
+
+fn get_optional_oauth_key(
+    oauth_yaml: &Yaml,
+    key: &str,
+    context: &Context,
+) -> error::Result<Option<String>> {
+    match oauth_yaml[key].is_badvalue() {
+        true => Ok(None),
+        false => get_parsed_oauth_key(oauth_yaml, key, context).map(Some),
+    }
+}
+
+fn get_scopes(oauth_yaml: &Yaml, context: &Context) -> error::Result<Vec<String>> {
+    let render = |raw: &str| -> error::Result<String> {
+        Ok(template::get_compiled_template_str_with_context(
+            &raw.to_string(),
+            context,
+        )?)
+    };
+    let scopes_error = || {
+        JoatError::Config(String::from(
+            "Scopes should be a list or a space separated string",
+        ))
+    };
+    match &oauth_yaml["scopes"] {
+        Yaml::BadValue => Ok(Vec::new()),
+        Yaml::Array(scopes) => {
+            let mut rendered_scopes = Vec::new();
+            for scope in scopes.iter() {
+                let rendered_scope = render(scope.as_str().ok_or_else(scopes_error)?)?;
+                if !rendered_scope.is_empty() {
+                    rendered_scopes.push(rendered_scope);
+                }
+            }
+            Ok(rendered_scopes)
+        }
+        Yaml::String(scopes) => Ok(render(scopes)?
+            .split_whitespace()
+            .map(|s| s.to_string())
+            .collect()),
+        _ => Err(scopes_error()),
+    }
+}
+
+fn get_extra_auth_params(
+    oauth_yaml: &Yaml,
+    context: &Context,
+) -> error::Result<Vec<(String, String)>> {
+    let extra_params_yaml = &oauth_yaml["extra_auth_params"];
+    let mut extra_params: Vec<(String, String)> =
+        yaml::get_hash_from_yaml(extra_params_yaml, context, false)?
+            .into_iter()
+            .map(|(k, v)| (k, v.as_str().unwrap_or("").to_string()))
+            .collect();
+    extra_params.sort();
+    Ok(extra_params)
+}
+
+fn parse_token_endpoint_auth(method: &str) -> error::Result<TokenEndpointAuth> {
+    match method {
+        "client_secret_basic" => Ok(TokenEndpointAuth::ClientSecretBasic),
+        "client_secret_post" => Ok(TokenEndpointAuth::ClientSecretPost),
+        "none" => Ok(TokenEndpointAuth::NoAuth),
+        m => Err(JoatError::Config(format!(
+            "Unknown token_endpoint_auth {}",
+            m
+        ))),
+    }
+}
+
+fn get_discovered_token_endpoint_auth(
+    metadata: &ProviderMetadata,
+    has_secret: bool,
+) -> TokenEndpointAuth {
+    let supported = &metadata.token_endpoint_auth_methods_supported;
+    if !has_secret {
+        return TokenEndpointAuth::NoAuth;
+    }
+    // client_secret_basic is the default when the provider doesn't list its methods
+    if supported.is_empty() || supported.iter().any(|m| m == "client_secret_basic") {
+        return TokenEndpointAuth::ClientSecretBasic;
+    }
+    if supported.iter().any(|m| m == "client_secret_post") {
+        return TokenEndpointAuth::ClientSecretPost;
+    }
+    TokenEndpointAuth::ClientSecretBasic
+}
 
-            let message = include_str!("../templates/oauth_success.html");
-            let response = format!(
-                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
-                message.len(),
-                message
-            );
-            stream.write_all(response.as_bytes()).unwrap();
+fn get_discovered_scopes(metadata: &ProviderMetadata) -> Vec<String> {
+    let mut scopes = vec![String::from("openid")];
+    for scope in ["email", "profile"].iter() {
+        if metadata.scopes_supported.iter().any(|s| s == scope) {
+            scopes.push(scope.to_string());
+        }
+    }
+    scopes
+}
 
-            // Exchange the code with a token.
-            let code_token_request = client
-                .exchange_code(code)
-                .add_extra_param("client_id", client_id_str)
-                .add_extra_param("client_secret", client_secret_str);
-            let token = code_token_request.request(http_client);
+fn get_grant(oauth_yaml: &Yaml) -> error::Result<Grant> {
+    match oauth_yaml["grant"].as_str() {
+        None | Some("authorization_code") => Ok(Grant::AuthorizationCode),
+        Some("client_credentials") => Ok(Grant::ClientCredentials),
+        Some("device_code") => Ok(Grant::DeviceCode),
+        Some(g) => Err(JoatError::Config(format!("Unknown oauth grant {}", g))),
+    }
+}
+
+pub fn get_oauth_config(
+    app_name: &str,
+    oauth_yaml: &Yaml,
+    context: &Context,
+) -> error::Result<OAuthConfig> {
+    let grant = get_grant(oauth_yaml)?;
+    let metadata = match get_optional_oauth_key(oauth_yaml, "issuer", context)? {
+        Some(issuer) => Some(oidc::get_provider_metadata(app_name, &issuer)?),
+        None => None,
+    };
+
+    let configured_secret = get_optional_oauth_key(oauth_yaml, "client_secret", context)?;
+    let token_endpoint_auth =
+        match get_optional_oauth_key(oauth_yaml, "token_endpoint_auth", context)? {
+            Some(m) => parse_token_endpoint_auth(&m)?,
+            None => match &metadata {
+                Some(m) => get_discovered_token_endpoint_auth(m, configured_secret.is_some()),
+                None => TokenEndpointAuth::ClientSecretBasicAndPost,
+            },
+        };
+    let client_secret = match token_endpoint_auth {
+        TokenEndpointAuth::NoAuth => None,
+        _ => configured_secret,
+    };
+    // Public clients (no secret) always use PKCE
+    let pkce = oauth_yaml["pkce"]
+        .as_bool()
+        .unwrap_or(client_secret.is_none());
+
+    // Explicit endpoints take precedence over the discovered ones
+    let discovered = |get: fn(&ProviderMetadata) -> Option<String>| metadata.as_ref().and_then(get);
+    let auth_url = get_optional_oauth_key(oauth_yaml, "auth_url", context)?
+        .or_else(|| discovered(|m| m.authorization_endpoint.clone()));
+    if let (Grant::AuthorizationCode, None) = (&grant, &auth_url) {
+        return Err(JoatError::Config(String::from("Missing oauth auth_url")));
+    }
+    let token_url = get_optional_oauth_key(oauth_yaml, "token_url", context)?
+        .or_else(|| discovered(|m| m.token_endpoint.clone()))
+        .ok_or(JoatError::Config(String::from("Missing oauth token_url")))?;
+    let device_auth_url = get_optional_oauth_key(oauth_yaml, "device_auth_url", context)?
+        .or_else(|| discovered(|m| m.device_authorization_endpoint.clone()));
+    if let (Grant::DeviceCode, None) = (&grant, &device_auth_url) {
+        return Err(JoatError::Config(String::from(
+            "Missing oauth device_auth_url for device_code grant",
+        )));
+    }
+    let mut scopes = get_scopes(oauth_yaml, context)?;
+    if scopes.is_empty() {
+        if let Some(m) = &metadata {
+            scopes = get_discovered_scopes(m);
+        }
+    }
+    let invalid_login_timeout =
+        || JoatError::Config(String::from("login_timeout should be a number of seconds"));
+    let login_timeout = match &oauth_yaml["login_timeout"] {
+        Yaml::BadValue => DEFAULT_LOGIN_TIMEOUT_SECS,
+        Yaml::Integer(t) => u64::try_from(*t).map_err(|_e| invalid_login_timeout())?,
+        _ => get_parsed_oauth_key(oauth_yaml, "login_timeout", context)?
+            .parse()
+            .map_err(|_e| invalid_login_timeout())?,
+    };
+    let redirect_port = match &oauth_yaml["redirect_port"] {
+        Yaml::BadValue => DEFAULT_REDIRECT_PORT,
+        Yaml::Integer(p) => u16::try_from(*p).map_err(|_e| {
+            JoatError::Config(String::from("redirect_port should be a port number"))
+        })?,
+        _ => get_parsed_oauth_key(oauth_yaml, "redirect_port", context)?
+            .parse()
+            .map_err(|_e| {
+                JoatError::Config(String::from("redirect_port should be a port number"))
+            })?,
+    };
+    let mut redirect_path =
+        get_optional_oauth_key(oauth_yaml, "redirect_path", context)?.unwrap_or(String::from("/"));
+    if !redirect_path.starts_with('/') {
+        redirect_path.insert(0, '/');
+    }
+    Ok(OAuthConfig {
+        grant,
+        client_id: get_parsed_oauth_key(oauth_yaml, "client_id", context)?,
+        client_secret,
+        auth_url,
+        token_url,
+        device_auth_url,
+        pkce,
+        scopes,
+        extra_auth_params: get_extra_auth_params(oauth_yaml, context)?,
+        token_endpoint_auth,
+        redirect_host: get_optional_oauth_key(oauth_yaml, "redirect_host", context)?
+            .unwrap_or(String::from("localhost")),
+        redirect_port,
+        redirect_path,
+        login_timeout,
+    })
+}
+
+///
+/// Token response as persisted in the token file.
+///
+/// `expires_in` is relative to when the token was issued, so the absolute expiry is stored
+/// alongside it.
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct StoredToken {
+    #[serde(flatten)]
+    token: SpecialTokenResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    expires_at: Option<u64>,
+}
+
+impl StoredToken {
+    fn new(token: SpecialTokenResponse, previous: Option<&StoredToken>, now: u64) -> StoredToken {
+        let mut token = token;
+        // Refresh responses may omit the refresh token, keep using the previous one
+        if token.refresh_token.is_none() {
+            token.refresh_token = previous.and_then(|p| p.token.refresh_token.clone());
+        }
+        let expires_at = token.expires_in.map(|e| now + e);
+        StoredToken { token, expires_at }
+    }
+
+    fn from_legacy(access_token: &str) -> StoredToken {
+        let token = SpecialTokenResponse {
+            access_token: AccessToken::new(access_token.trim().to_string()),
+            token_type: default_token_type(),
+            expires_in: None,
+            refresh_token: None,
+            scopes: None,
+            extra_fields: IdTokenFields { id_token: None },
+        };
+        StoredToken {
+            token,
+            expires_at: None,
+        }
+    }
 
-            return Some(
-                token
-                    .expect("Could not get token")
-                    .access_token()
-                    .clone()
-                    .secret()
-                    .to_string(),
-            );
+    fn is_expiring(&self, now: u64) -> bool {
+        match self.expires_at {
+            Some(e) => now + EXPIRY_MARGIN_SECS >= e,
+            None => false,
         }
     }
-    return None;
+
+    fn access_token(&self) -> String {
+        self.token.access_token().secret().to_string()
+    }
+}
+
+pub fn now_secs() -> error::Result<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .map_err(|e| JoatError::Internal(format!("System time before unix epoch: {}", e)))
 }
 
-fn get_token_file_path(app_name: &str) -> String {
-    let home_dir_path = dirs::home_dir().expect("Could not find home dir");
-    let home_dir_str = home_dir_path.into_os_string().into_string().unwrap();
-    let home_path_str = String::from(format!("{}/.{}.joat/", home_dir_str, app_name));
-    format!("{}.{}.token", home_path_str, app_name)
+pub fn get_app_home_path(app_name: &str) -> error::Result<String> {
+    let home_dir_path =
+        dirs::home_dir().ok_or(JoatError::Io(String::from("Could not find home dir")))?;
+    Ok(format!(
+        "{}/.{}.joat/",
+        home_dir_path.to_string_lossy(),
+        app_name
+    ))
 }
 
-fn get_token_from_file(app_name: &str) -> Option<String> {
-    let token_path = get_token_file_path(app_name);
-    if Path::new(&token_path).exists() {
-        let access_token = fs::read_to_string(token_path).expect("Could not read token file");
-        return Some(access_token);
+fn get_token_file_path(app_name: &str) -> error::Result<String> {
+    profile::get_profile_file_path(app_name, "token")
+}
+
+fn get_token_from_file(app_name: &str) -> error::Result<Option<StoredToken>> {
+    let token_path = get_token_file_path(app_name)?;
+    if !Path::new(&token_path).exists() {
+        return Ok(None);
     }
-    return None;
+    let content = fs::read_to_string(&token_path)
+        .map_err(|e| JoatError::Io(format!("Could not read {}: {}", token_path, e)))?;
+    let stored_token = match serde_json::from_str(&content) {
+        Ok(t) => t,
+        // Older versions stored the bare access token
+        Err(_e) => StoredToken::from_legacy(&content),
+    };
+    Ok(Some(stored_token))
 }
 
-fn write_access_token(app_name: &str, access_token: &String) {
-    let token_path = get_token_file_path(app_name);
-    fs::write(token_path, access_token).expect("Unable to write token file");
+fn write_token(app_name: &str, stored_token: &StoredToken) -> error::Result<()> {
+    let token_path = get_token_file_path(app_name)?;
+    let content = serde_json::to_string(stored_token)
+        .map_err(|e| JoatError::Internal(format!("Could not serialize token: {}", e)))?;
+    secrets::write_private_file(&token_path, &content)
 }
 
-fn oauth_flow(
-    client_id_str: String,
-    client_secret_str: String,
-    auth_url_str: String,
-    token_url_str: String,
-) -> String {
-    let client_id = ClientId::new(client_id_str.clone());
-    let client_secret = ClientSecret::new(client_secret_str.clone());
-    let auth_url =
-        AuthUrl::new(Url::parse(&auth_url_str).expect("Invalid authorization endpoint URL"));
-    let token_url = TokenUrl::new(Url::parse(&token_url_str).expect("Invalid token endpoint URL"));
+fn parse_url(url: &str, name: &str) -> error::Result<Url> {
+    Url::parse(url).map_err(|e| JoatError::Config(format!("Invalid {} {}: {}", name, url, e)))
+}
 
-    let client = SpecialClient::new(client_id, Some(client_secret), auth_url, Some(token_url))
-        .set_redirect_url(RedirectUrl::new(
-            Url::parse("http://localhost:8080").expect("Invalid redirect URL"),
-        ));
+fn get_client(config: &OAuthConfig) -> error::Result<SpecialClient> {
+    let client_id = ClientId::new(config.client_id.clone());
+    let client_secret = config.client_secret.clone().map(ClientSecret::new);
+    // Grants without a browser step never use the authorization endpoint
+    let auth_url_str = config.auth_url.as_ref().unwrap_or(&config.token_url);
+    let auth_url = AuthUrl::new(parse_url(auth_url_str, "oauth auth_url")?);
+    let token_url = TokenUrl::new(parse_url(&config.token_url, "oauth token_url")?);
 
-    let (authorize_url, csrf_state) = client.authorize_url(CsrfToken::new_random).url();
-    Command::new("open")
-        .arg(authorize_url.to_string())
-        .output()
-        .expect("failed to execute script");
+    let auth_type = match config.token_endpoint_auth {
+        TokenEndpointAuth::ClientSecretPost | TokenEndpointAuth::NoAuth => AuthType::RequestBody,
+        _ => AuthType::BasicAuth,
+    };
 
-    return start_callback_server(client_id_str, client_secret_str, client, csrf_state)
-        .expect("Could not get access_token with oauth_flow");
+    Ok(
+        SpecialClient::new(client_id, client_secret, auth_url, Some(token_url))
+            .set_auth_type(auth_type),
+    )
 }
 
-pub fn get_oauth_token(
+fn authorization_code_flow(config: &OAuthConfig) -> error::Result<SpecialTokenResponse> {
+    let listener = bind_callback_listener(config)?;
+    let redirect_url = get_redirect_url(config, &listener)?;
+    let client = get_client(config)?.set_redirect_url(RedirectUrl::new(redirect_url));
+
+    let mut authorize_request = client.authorize_url(CsrfToken::new_random);
+    for scope in config.scopes.iter() {
+        authorize_request = authorize_request.add_scope(Scope::new(scope.clone()));
+    }
+    for (name, value) in config.extra_auth_params.iter() {
+        authorize_request = authorize_request.add_extra_param(name.as_str(), value.as_str());
+    }
+    let mut pkce_verifier = None;
+    if config.pkce {
+        let (pkce_challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+        authorize_request = authorize_request.set_pkce_challenge(pkce_challenge);
+        pkce_verifier = Some(verifier);
+    }
+    let (authorize_url, csrf_state) = authorize_request.url();
+    if open_browser(authorize_url.as_str()) {
+        eprintln!("Waiting for the login to complete in your browser");
+    } else {
+        eprintln!("Open this URL in your browser to log in:");
+        eprintln!("{}", authorize_url);
+    }
+
+    let code = wait_authorization_code(listener, config, &csrf_state)
+        .map_err(|e| JoatError::Auth(format!("Authorization failed: {}", e)))?;
+
+    // Exchange the code with a token.
+    let mut code_token_request = client.exchange_code(code);
+    for (name, value) in get_extra_client_params(config) {
+        code_token_request = code_token_request.add_extra_param(name, value);
+    }
+    if let Some(verifier) = pkce_verifier {
+        code_token_request = code_token_request.set_pkce_verifier(verifier);
+    }
+    code_token_request
+        .request(http_client)
+        .map_err(|e| JoatError::Auth(format!("Could not get access_token: {}", e)))
+}
+
+///
+/// Client credentials to send in the body of requests made without the oauth2 client.
+///
+fn get_client_params(config: &OAuthConfig) -> Vec<(String, String)> {
+    let mut params = vec![(String::from("client_id"), config.client_id.clone())];
+    match config.token_endpoint_auth {
+        TokenEndpointAuth::ClientSecretBasicAndPost | TokenEndpointAuth::ClientSecretPost => {
+            if let Some(secret) = &config.client_secret {
+                params.push((String::from("client_secret"), secret.clone()));
+            }
+        }
+        _ => (),
+    }
+    params
+}
+
+///
+/// Client credentials to add to requests made with the oauth2 client.
+///
+/// The oauth2 client already authenticates according to its auth type, only the non standard
+/// default also sends them in the body.
+///
+fn get_extra_client_params(config: &OAuthConfig) -> Vec<(String, String)> {
+    match config.token_endpoint_auth {
+        TokenEndpointAuth::ClientSecretBasicAndPost => get_client_params(config),
+        _ => Vec::new(),
+    }
+}
+
+fn post_form(
+    client: &reqwest::Client,
+    url: &str,
+    params: &Vec<(String, String)>,
+    config: &OAuthConfig,
+) -> reqwest::Result<reqwest::Response> {
+    let mut request = client.post(url).form(params);
+    match config.token_endpoint_auth {
+        TokenEndpointAuth::ClientSecretBasicAndPost | TokenEndpointAuth::ClientSecretBasic => {
+            request = request.basic_auth(&config.client_id, config.client_secret.as_ref());
+        }
+        _ => (),
+    }
+    request.send()
+}
+
+fn client_credentials_flow(config: &OAuthConfig) -> error::Result<SpecialTokenResponse> {
+    let client = get_client(config)?;
+    let mut token_request = client.exchange_client_credentials();
+    for scope in config.scopes.iter() {
+        token_request = token_request.add_scope(Scope::new(scope.clone()));
+    }
+    // There's no authorization request in this grant, so parameters like audience go here
+    for (name, value) in config.extra_auth_params.iter() {
+        token_request = token_request.add_extra_param(name.as_str(), value.as_str());
+    }
+    for (name, value) in get_extra_client_params(config) {
+        token_request = token_request.add_extra_param(name, value);
+    }
+    token_request.request(http_client).map_err(|e| {
+        JoatError::Auth(format!(
+            "Could not get access_token with client credentials: {}",
+            e
+        ))
+    })
+}
+
+///
+/// Device authorization response as described in
+/// [Section 3.2 of RFC 8628](https://tools.ietf.org/html/rfc8628#section-3.2).
+///
+#[derive(Debug, Deserialize)]
+struct DeviceAuthorizationResponse {
+    device_code: String,
+    user_code: String,
+    // Some providers (Google) use verification_url
+    #[serde(alias = "verification_url")]
+    verification_uri: String,
+    verification_uri_complete: Option<String>,
+    expires_in: u64,
+    interval: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceTokenError {
+    error: String,
+    error_description: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum PollAction {
+    Wait(u64),
+    Fail(String),
+}
+
+fn get_poll_action(error: &DeviceTokenError, interval: u64) -> PollAction {
+    match error.error.as_str() {
+        "authorization_pending" => PollAction::Wait(interval),
+        // RFC 8628 asks clients to add 5 seconds to the interval
+        "slow_down" => PollAction::Wait(interval + 5),
+        e => PollAction::Fail(match &error.error_description {
+            Some(d) => format!("{}: {}", e, d),
+            None => e.to_string(),
+        }),
+    }
+}
+
+fn device_code_flow(config: &OAuthConfig) -> error::Result<SpecialTokenResponse> {
+    let device_auth_url = config.device_auth_url.as_ref().unwrap();
+    let client = reqwest::Client::new();
+    let client_params = get_client_params(config);
+    let mut device_params = client_params.clone();
+    if !config.scopes.is_empty() {
+        device_params.push((String::from("scope"), config.scopes.join(" ")));
+    }
+    device_params.extend(config.extra_auth_params.iter().cloned());
+    let mut response = post_form(&client, device_auth_url, &device_params, config)?;
+    let device: DeviceAuthorizationResponse = response
+        .json()
+        .map_err(|e| JoatError::Auth(format!("Invalid device authorization response: {}", e)))?;
+    debug!(
+        "Device authorization {}",
+        redact::redact(&format!("{:?}", device))
+    );
+
+    eprintln!(
+        "To authenticate open {} and enter the code {}",
+        device.verification_uri, device.user_code
+    );
+    if let Some(uri) = &device.verification_uri_complete {
+        eprintln!("Or open {}", uri);
+    }
+
+    let deadline = now_secs()? + device.expires_in;
+    let mut interval = device.interval.unwrap_or(5);
+    let mut poll_params = client_params.clone();
+    poll_params.push((
+        String::from("grant_type"),
+        String::from(DEVICE_CODE_GRANT_TYPE),
+    ));
+    poll_params.push((String::from("device_code"), device.device_code.clone()));
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+        if now_secs()? > deadline {
+            return Err(JoatError::Auth(String::from(
+                "Device code expired before the authorization was completed",
+            )));
+        }
+        let mut token_response = post_form(&client, &config.token_url, &poll_params, config)?;
+        if token_response.status().is_success() {
+            return token_response
+                .json()
+                .map_err(|e| JoatError::Auth(format!("Could not parse token response: {}", e)));
+        }
+        let error: DeviceTokenError = token_response
+            .json()
+            .map_err(|e| JoatError::Auth(format!("Could not parse token error response: {}", e)))?;
+        match get_poll_action(&error, interval) {
+            PollAction::Wait(i) => interval = i,
+            PollAction::Fail(e) => {
+                return Err(JoatError::Auth(format!("Authorization failed: {}", e)));
+            }
+        }
+    }
+}
+
+fn oauth_flow(config: &OAuthConfig) -> error::Result<SpecialTokenResponse> {
+    match config.grant {
+        Grant::AuthorizationCode => authorization_code_flow(config),
+        Grant::ClientCredentials => client_credentials_flow(config),
+        Grant::DeviceCode => device_code_flow(config),
+    }
+}
+
+fn refresh_token(
+    config: &OAuthConfig,
+    stored_token: &StoredToken,
+) -> error::Result<Option<SpecialTokenResponse>> {
+    let refresh_token = match stored_token.token.refresh_token() {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    info!("Refreshing oauth token");
+    let client = get_client(config)?;
+    let mut refresh_request = client.exchange_refresh_token(refresh_token);
+    for (name, value) in get_extra_client_params(config) {
+        refresh_request = refresh_request.add_extra_param(name, value);
+    }
+    match refresh_request.request(http_client) {
+        Ok(t) => Ok(Some(t)),
+        Err(e) => {
+            debug!("Could not refresh token {:?}", e);
+            Ok(None)
+        }
+    }
+}
+
+fn renew_token(
     app_name: &str,
-    client_id_str: String,
-    client_secret_str: String,
-    auth_url_str: String,
-    token_url_str: String,
-) -> String {
-    let access_token = get_token_from_file(app_name);
-    match access_token {
+    config: &OAuthConfig,
+    stored_token: Option<StoredToken>,
+) -> error::Result<String> {
+    let refreshed_token = match &stored_token {
+        Some(t) => refresh_token(config, t)?,
+        None => None,
+    };
+    let token = match refreshed_token {
+        Some(t) => t,
+        None => oauth_flow(config)?,
+    };
+    let new_token = StoredToken::new(token, stored_token.as_ref(), now_secs()?);
+    write_token(app_name, &new_token)?;
+    Ok(new_token.access_token())
+}
+
+///
+/// Returns a valid access token, refreshing it or running the oauth flow when needed.
+///
+pub fn get_oauth_token(app_name: &str, config: &OAuthConfig) -> error::Result<String> {
+    match get_token_from_file(app_name)? {
+        Some(t) => {
+            if t.is_expiring(now_secs()?) {
+                return renew_token(app_name, config, Some(t));
+            }
+            Ok(t.access_token())
+        }
+        None => renew_token(app_name, config, None),
+    }
+}
+
+///
+/// Template context with what's known about the stored token, never the token itself.
+///
+pub fn get_auth_context(app_name: &str) -> error::Result<Value> {
+    let mut auth_context = Map::new();
+    let stored_token = match get_token_from_file(app_name)? {
+        Some(t) => t,
+        None => return Ok(Value::Object(auth_context)),
+    };
+    if let Some(expires_at) = stored_token.expires_at {
+        auth_context.insert(String::from("expires_at"), Value::from(expires_at));
+    }
+    if let Some(scopes) = stored_token.token.scopes() {
+        let scopes: Vec<Value> = scopes.iter().map(|s| Value::from(s.to_string())).collect();
+        auth_context.insert(String::from("scopes"), Value::Array(scopes));
+    }
+    if let Some(id_token) = &stored_token.token.extra_fields.id_token {
+        let mut id_token_context = Map::new();
+        id_token_context.insert(String::from("raw"), Value::from(id_token.clone()));
+        let claims = oidc::get_id_token_claims(id_token).unwrap_or(Value::Object(Map::new()));
+        id_token_context.insert(String::from("claims"), claims);
+        auth_context.insert(String::from("id_token"), Value::Object(id_token_context));
+    }
+    Ok(Value::Object(auth_context))
+}
+
+///
+/// Runs the oauth flow even when there's a valid token, replacing the stored one.
+///
+pub fn login(app_name: &str, config: &OAuthConfig) -> error::Result<()> {
+    let previous = get_token_from_file(app_name)?;
+    let new_token = StoredToken::new(oauth_flow(config)?, previous.as_ref(), now_secs()?);
+    fs::create_dir_all(get_app_home_path(app_name)?)
+        .map_err(|e| JoatError::Io(format!("Could not create config dir: {}", e)))?;
+    write_token(app_name, &new_token)?;
+    println!("Logged in");
+    Ok(())
+}
+
+pub fn logout(app_name: &str) -> error::Result<()> {
+    let token_path = get_token_file_path(app_name)?;
+    if !Path::new(&token_path).exists() {
+        println!("Not logged in");
+        return Ok(());
+    }
+    fs::remove_file(&token_path)
+        .map_err(|e| JoatError::Io(format!("Could not remove {}: {}", token_path, e)))?;
+    println!("Logged out");
+    Ok(())
+}
+
+fn get_status_lines(stored_token: &StoredToken, now: u64) -> Vec<String> {
+    let mut lines = Vec::new();
+    match stored_token.expires_at {
+        Some(e) if e <= now => {
+            lines.push(format!("Token expired at {}", Utc.timestamp(e as i64, 0)))
+        }
+        Some(e) => lines.push(format!(
+            "Token expires at {} (in {} minutes)",
+            Utc.timestamp(e as i64, 0),
+            (e - now) / 60
+        )),
+        None => lines.push(String::from("Token without expiry")),
+    }
+    if stored_token.token.refresh_token.is_some() {
+        lines.push(String::from("Refresh token available"));
+    }
+    if let Some(scopes) = stored_token.token.scopes() {
+        let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+        lines.push(format!("Scopes: {}", scopes.join(" ")));
+    }
+    let claims = stored_token
+        .token
+        .extra_fields
+        .id_token
+        .as_ref()
+        .and_then(|t| oidc::get_id_token_claims(t));
+    if let Some(c) = claims {
+        if let Some(identity) = c["email"].as_str().or(c["sub"].as_str()) {
+            lines.push(format!("Identity: {}", identity));
+        }
+    }
+    lines
+}
+
+///
+/// Prints the stored token expiry and scopes, exits with 1 when not logged in.
+///
+pub fn print_status(app_name: &str) -> error::Result<()> {
+    let stored_token = match get_token_from_file(app_name)? {
         Some(t) => t,
         None => {
-            let access_token = oauth_flow(
-                client_id_str,
-                client_secret_str,
-                auth_url_str,
-                token_url_str,
-            );
-            write_access_token(app_name, &access_token);
-            access_token
+            println!("Not logged in");
+            ::std::process::exit(1);
         }
+    };
+    for line in get_status_lines(&stored_token, now_secs()?) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+///
+/// Gets a new access token after the API rejected the current one.
+///
+pub fn renew_oauth_token(app_name: &str, config: &OAuthConfig) -> error::Result<String> {
+    let stored_token = get_token_from_file(app_name)?;
+    renew_token(app_name, config, stored_token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_stored_token_expiry() {
+        // Arrange
+        let token: SpecialTokenResponse =
+            serde_json::from_str(r#"{"access_token": "abc", "expires_in": 3600}"#).unwrap();
+
+        // Act
+        let stored_token = StoredToken::new(token, None, now_secs().unwrap());
+        let expires_at = stored_token.expires_at.unwrap();
+
+        // Assert
+        assert!(!stored_token.is_expiring(expires_at - EXPIRY_MARGIN_SECS - 1));
+        assert!(stored_token.is_expiring(expires_at - EXPIRY_MARGIN_SECS));
+    }
+
+    #[test]
+    fn test_stored_token_keeps_refresh_token() {
+        // Arrange
+        let first: SpecialTokenResponse =
+            serde_json::from_str(r#"{"access_token": "abc", "refresh_token": "r1"}"#).unwrap();
+        let refreshed: SpecialTokenResponse =
+            serde_json::from_str(r#"{"access_token": "def"}"#).unwrap();
+        let previous = StoredToken::new(first, None, 0);
+
+        // Act
+        let stored_token = StoredToken::new(refreshed, Some(&previous), 0);
+
+        // Assert
+        assert_eq!(stored_token.access_token(), "def");
+        assert_eq!(
+            stored_token.token.refresh_token().unwrap().secret(),
+            &String::from("r1")
+        );
+    }
+
+    #[test]
+    fn test_stored_token_serialization_roundtrip() {
+        // Arrange
+        let token: SpecialTokenResponse = serde_json::from_str(
+            r#"{"access_token": "abc", "token_type": "bearer", "expires_in": 10, "scope": "a b"}"#,
+        )
+        .unwrap();
+        let stored_token = StoredToken::new(token, None, 0);
+
+        // Act
+        let content = serde_json::to_string(&stored_token).unwrap();
+        let parsed: StoredToken = serde_json::from_str(&content).unwrap();
+
+        // Assert
+        assert_eq!(parsed.access_token(), "abc");
+        assert_eq!(parsed.expires_at, stored_token.expires_at);
+        assert_eq!(parsed.token.scopes().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_get_callback_result_code() {
+        // Arrange
+        let state = CsrfToken::new(String::from("xyz"));
+
+        // Act
+        let result = get_callback_result("GET /?code=abc&state=xyz HTTP/1.1\r\n", "/", &state);
+
+        // Assert
+        match result {
+            CallbackResult::Code(c) => assert_eq!(c.secret(), "abc"),
+            _ => panic!("Expected a code"),
+        }
+    }
+
+    #[test]
+    fn test_get_callback_result_rejects_state_mismatch() {
+        // Arrange
+        let state = CsrfToken::new(String::from("xyz"));
+
+        // Act
+        let result = get_callback_result("GET /?code=abc&state=other HTTP/1.1\r\n", "/", &state);
+
+        // Assert
+        match result {
+            CallbackResult::Rejected(_) => (),
+            _ => panic!("Expected the callback to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_get_callback_result_rejects_other_paths() {
+        // Arrange
+        let state = CsrfToken::new(String::from("xyz"));
+
+        // Act
+        let result = get_callback_result(
+            "GET /other?code=abc&state=xyz HTTP/1.1\r\n",
+            "/callback",
+            &state,
+        );
+
+        // Assert
+        match result {
+            CallbackResult::Rejected(_) => (),
+            _ => panic!("Expected the callback to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_get_callback_result_error() {
+        // Arrange
+        let state = CsrfToken::new(String::from("xyz"));
+
+        // Act
+        let result = get_callback_result(
+            "GET /?error=access_denied&error_description=User+denied&state=xyz HTTP/1.1\r\n",
+            "/",
+            &state,
+        );
+
+        // Assert
+        match result {
+            CallbackResult::Failed(e) => assert_eq!(e, "access_denied: User denied"),
+            _ => panic!("Expected an error"),
+        }
+    }
+
+    #[test]
+    fn test_get_poll_action() {
+        // Arrange
+        let pending = DeviceTokenError {
+            error: String::from("authorization_pending"),
+            error_description: None,
+        };
+        let slow_down = DeviceTokenError {
+            error: String::from("slow_down"),
+            error_description: None,
+        };
+        let denied = DeviceTokenError {
+            error: String::from("access_denied"),
+            error_description: Some(String::from("User denied")),
+        };
+
+        // Act, Assert
+        assert_eq!(get_poll_action(&pending, 5), PollAction::Wait(5));
+        assert_eq!(get_poll_action(&slow_down, 5), PollAction::Wait(10));
+        assert_eq!(
+            get_poll_action(&denied, 5),
+            PollAction::Fail(String::from("access_denied: User denied"))
+        );
+    }
+
+    #[test]
+    fn test_get_scopes() {
+        // Arrange
+        let list_yaml = &YamlLoader::load_from_str("scopes: [openid, '{{vars.s}}']").unwrap()[0];
+        let str_yaml = &YamlLoader::load_from_str("scopes: openid  email").unwrap()[0];
+        let mut context = HashMap::new();
+        context.insert(String::from("vars"), serde_json::json!({"s": "profile"}));
+
+        // Act, Assert
+        assert_eq!(
+            get_scopes(list_yaml, &context).unwrap(),
+            vec!["openid", "profile"]
+        );
+        assert_eq!(
+            get_scopes(str_yaml, &context).unwrap(),
+            vec!["openid", "email"]
+        );
+    }
+
+    #[test]
+    fn test_get_oauth_config_redirect_port() {
+        // Arrange
+        let load = |port: &str| {
+            YamlLoader::load_from_str(&format!(
+                "client_id: a\nauth_url: https://a.test/auth\ntoken_url: https://a.test/token\nredirect_port: {}",
+                port
+            ))
+            .unwrap()
+            .remove(0)
+        };
+        let context = HashMap::new();
+
+        // Act, Assert
+        let config = get_oauth_config("app", &load("8081"), &context).unwrap();
+        assert_eq!(config.redirect_port, 8081);
+        assert!(get_oauth_config("app", &load("70000"), &context).is_err());
+        assert!(get_oauth_config("app", &load("-1"), &context).is_err());
+    }
+
+    #[test]
+    fn test_get_oauth_config_login_timeout() {
+        // Arrange
+        let load = |timeout: &str| {
+            YamlLoader::load_from_str(&format!(
+                "client_id: a\nauth_url: https://a.test/auth\ntoken_url: https://a.test/token\nlogin_timeout: {}",
+                timeout
+            ))
+            .unwrap()
+            .remove(0)
+        };
+        let context = HashMap::new();
+
+        // Act, Assert
+        let config = get_oauth_config("app", &load("30"), &context).unwrap();
+        assert_eq!(config.login_timeout, 30);
+        assert!(get_oauth_config("app", &load("-1"), &context).is_err());
+        assert!(get_oauth_config("app", &load("soon"), &context).is_err());
+    }
+
+    #[test]
+    fn test_get_status_lines() {
+        // Arrange
+        let mut stored_token = StoredToken::from_legacy("abc");
+        stored_token.expires_at = Some(1_600_000_000);
+        stored_token.token.scopes = Some(vec![Scope::new(String::from("read"))]);
+
+        // Act
+        let valid = get_status_lines(&stored_token, 1_600_000_000 - 600);
+        let expired = get_status_lines(&stored_token, 1_600_000_000);
+
+        // Assert
+        assert_eq!(
+            valid,
+            vec![
+                String::from("Token expires at 2020-09-13 12:26:40 UTC (in 10 minutes)"),
+                String::from("Scopes: read"),
+            ]
+        );
+        assert_eq!(expired[0], "Token expired at 2020-09-13 12:26:40 UTC");
+    }
+
+    #[test]
+    fn test_stored_token_from_legacy() {
+        // Act
+        let stored_token = StoredToken::from_legacy("abc\n");
+
+        // Assert
+        assert_eq!(stored_token.access_token(), "abc");
+        assert!(!stored_token.is_expiring(now_secs().unwrap()));
     }
 }
@@ -0,0 +1,154 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::value::Value;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{self, JoatError};
+use crate::{oauth, profile};
+
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+const DISCOVERY_CACHE_TTL_SECS: u64 = 24 * 60 * 60;
+
+///
+/// Subset of the OpenID Provider Metadata joat uses, as described in
+/// [OpenID Connect Discovery](https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata).
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: Option<String>,
+    pub token_endpoint: Option<String>,
+    pub device_authorization_endpoint: Option<String>,
+    #[serde(default)]
+    pub scopes_supported: Vec<String>,
+    #[serde(default)]
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CachedMetadata {
+    fetched_at: u64,
+    metadata: ProviderMetadata,
+}
+
+fn get_cache_file_path(app_name: &str) -> error::Result<String> {
+    profile::get_profile_file_path(app_name, "oidc.json")
+}
+
+fn get_cached_metadata(app_name: &str, issuer: &str) -> Option<ProviderMetadata> {
+    let cache_path = get_cache_file_path(app_name).ok()?;
+    if !Path::new(&cache_path).exists() {
+        return None;
+    }
+    let content = fs::read_to_string(&cache_path).ok()?;
+    let cached: CachedMetadata = serde_json::from_str(&content).ok()?;
+    let is_fresh = cached.fetched_at + DISCOVERY_CACHE_TTL_SECS > oauth::now_secs().ok()?;
+    if is_fresh && cached.metadata.issuer.trim_end_matches('/') == issuer {
+        return Some(cached.metadata);
+    }
+    None
+}
+
+fn write_cached_metadata(app_name: &str, metadata: &ProviderMetadata) -> error::Result<()> {
+    let cached = CachedMetadata {
+        fetched_at: oauth::now_secs()?,
+        metadata: metadata.clone(),
+    };
+    let content = serde_json::to_string(&cached)
+        .map_err(|e| JoatError::Internal(format!("Could not serialize oidc metadata: {}", e)))?;
+    fs::create_dir_all(oauth::get_app_home_path(app_name)?)
+        .map_err(|e| JoatError::Io(format!("Could not create config dir: {}", e)))?;
+    fs::write(get_cache_file_path(app_name)?, content)
+        .map_err(|e| JoatError::Io(format!("Unable to write oidc cache: {}", e)))
+}
+
+///
+/// The issuer of the document has to be the configured one, as required by OpenID Connect
+/// Discovery, or its endpoints could belong to another provider.
+///
+fn check_issuer(metadata: &ProviderMetadata, issuer: &str) -> error::Result<()> {
+    if metadata.issuer.trim_end_matches('/') == issuer {
+        return Ok(());
+    }
+    Err(JoatError::Auth(format!(
+        "The openid configuration of {} is for the issuer {}",
+        issuer, metadata.issuer
+    )))
+}
+
+///
+/// Fetches the provider metadata from the issuer, cached on disk for a day.
+///
+pub fn get_provider_metadata(app_name: &str, issuer: &str) -> error::Result<ProviderMetadata> {
+    let issuer = issuer.trim_end_matches('/');
+    if let Some(m) = get_cached_metadata(app_name, issuer) {
+        return Ok(m);
+    }
+    let discovery_url = format!("{}{}", issuer, DISCOVERY_PATH);
+    debug!("Fetching oidc metadata from {}", discovery_url);
+    let mut response = reqwest::get(&discovery_url)
+        .map_err(|e| JoatError::Network(format!("Could not fetch {}: {}", discovery_url, e)))?;
+    if !response.status().is_success() {
+        return Err(JoatError::Network(format!(
+            "Could not fetch {}, status {}",
+            discovery_url,
+            response.status()
+        )));
+    }
+    let metadata: ProviderMetadata = response
+        .json()
+        .map_err(|e| JoatError::Auth(format!("Invalid openid configuration document: {}", e)))?;
+    check_issuer(&metadata, issuer)?;
+    write_cached_metadata(app_name, &metadata)?;
+    Ok(metadata)
+}
+
+///
+/// Decodes the claims of an ID token.
+///
+/// The signature isn't validated: the token comes straight from the token endpoint over TLS,
+/// which the OpenID Connect spec accepts as enough, and it's only used to fill templates.
+///
+pub fn get_id_token_claims(id_token: &str) -> Option<Value> {
+    let payload = id_token.split('.').nth(1)?;
+    let decoded =
+        base64::decode_config(payload.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()?;
+    serde_json::from_slice(&decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_id_token_claims() {
+        // Arrange
+        let claims = r#"{"sub":"123","email":"me@example.com"}"#;
+        let payload = base64::encode_config(claims, base64::URL_SAFE_NO_PAD);
+        let id_token = format!("eyJhbGciOiJub25lIn0.{}.", payload);
+
+        // Act
+        let decoded = get_id_token_claims(&id_token).unwrap();
+
+        // Assert
+        assert_eq!(decoded["email"], "me@example.com");
+    }
+
+    #[test]
+    fn test_check_issuer() {
+        // Arrange
+        let metadata: ProviderMetadata =
+            serde_json::from_str(r#"{"issuer": "https://login.example.com/"}"#).unwrap();
+
+        // Act, Assert
+        assert!(check_issuer(&metadata, "https://login.example.com").is_ok());
+        assert!(check_issuer(&metadata, "https://evil.example.com").is_err());
+    }
+
+    #[test]
+    fn test_get_id_token_claims_invalid_token() {
+        // Act, Assert
+        assert_eq!(get_id_token_claims("not a jwt"), None);
+    }
+}
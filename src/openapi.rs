@@ -0,0 +1,442 @@
+use regex::Regex;
+use std::collections::BTreeMap;
+use yaml_rust::Yaml;
+
+use crate::error::{self, JoatError};
+use crate::import::{self, get_yaml_string, ImportReport};
+
+const HTTP_METHODS: [&str; 7] = ["get", "put", "post", "delete", "options", "head", "patch"];
+const DEFAULT_BASE_ENDPOINT: &str = "http://localhost";
+// Also guards against recursive schemas and refs
+const MAX_SCHEMA_DEPTH: usize = 8;
+const FORM_CONTENT_TYPES: [&str; 2] = ["application/x-www-form-urlencoded", "multipart/form-data"];
+
+static BAD_VALUE: Yaml = Yaml::BadValue;
+
+///
+/// Follows a local `$ref` (e.g. `#/components/schemas/Pet`), BadValue for the other refs.
+///
+fn resolve_ref<'a>(spec: &'a Yaml, value: &'a Yaml) -> &'a Yaml {
+    let mut value = value;
+    for _ in 0..MAX_SCHEMA_DEPTH {
+        let reference = match value["$ref"].as_str() {
+            Some(r) => r,
+            None => return value,
+        };
+        let pointer = match reference.strip_prefix("#/") {
+            Some(p) => p,
+            None => return &BAD_VALUE,
+        };
+        value = pointer.split('/').fold(spec, |v, part| {
+            &v[part.replace("~1", "/").replace("~0", "~").as_str()]
+        });
+    }
+    value
+}
+
+fn get_first_line(text: &Yaml) -> Option<String> {
+    text.as_str()
+        .and_then(|t| t.lines().map(|l| l.trim()).find(|l| !l.is_empty()))
+        .map(|l| l.to_string())
+}
+
+///
+/// The first server of OpenAPI 3 specs (with the default values of its variables), or the host
+/// and base path of Swagger 2 ones.
+///
+fn get_base_endpoint(spec: &Yaml) -> String {
+    let server = &spec["servers"][0];
+    if let Some(url) = server["url"].as_str() {
+        let mut url = url.to_string();
+        for (name, variable) in server["variables"].as_hash().into_iter().flatten() {
+            if let (Some(n), Some(d)) = (name.as_str(), variable["default"].as_str()) {
+                url = url.replace(&format!("{{{}}}", n), d);
+            }
+        }
+        return url.trim_end_matches('/').to_string();
+    }
+    match spec["host"].as_str() {
+        Some(host) => format!(
+            "{}://{}{}",
+            spec["schemes"][0].as_str().unwrap_or("https"),
+            host,
+            spec["basePath"]
+                .as_str()
+                .unwrap_or("")
+                .trim_end_matches('/')
+        ),
+        None => String::from(DEFAULT_BASE_ENDPOINT),
+    }
+}
+
+///
+/// Parameters of the path item and of the operation, the latter override the former.
+///
+fn get_parameters<'a>(spec: &'a Yaml, path_item: &'a Yaml, operation: &'a Yaml) -> Vec<&'a Yaml> {
+    let mut parameters: Vec<&Yaml> = Vec::new();
+    let path_parameters = path_item["parameters"].as_vec().into_iter().flatten();
+    let operation_parameters = operation["parameters"].as_vec().into_iter().flatten();
+    for parameter in path_parameters.chain(operation_parameters) {
+        let parameter = resolve_ref(spec, parameter);
+        parameters.retain(|p| p["name"] != parameter["name"] || p["in"] != parameter["in"]);
+        parameters.push(parameter);
+    }
+    parameters
+}
+
+///
+/// A sample value of a schema: its example or default, or an empty value of its type.
+///
+fn get_schema_skeleton(spec: &Yaml, schema: &Yaml, depth: usize) -> Yaml {
+    let schema = resolve_ref(spec, schema);
+    if depth > MAX_SCHEMA_DEPTH {
+        return Yaml::Null;
+    }
+    for key in ["example", "default"].iter() {
+        if !schema[*key].is_badvalue() {
+            return schema[*key].clone();
+        }
+    }
+    if let Some(schemas) = schema["allOf"].as_vec() {
+        let mut properties = BTreeMap::new();
+        for s in schemas {
+            if let Yaml::Hash(h) = get_schema_skeleton(spec, s, depth + 1) {
+                properties.extend(h);
+            }
+        }
+        return Yaml::Hash(properties);
+    }
+    let first_alternative = schema["oneOf"]
+        .as_vec()
+        .or(schema["anyOf"].as_vec())
+        .and_then(|s| s.first());
+    if let Some(s) = first_alternative {
+        return get_schema_skeleton(spec, s, depth + 1);
+    }
+    if !schema["enum"][0].is_badvalue() {
+        return schema["enum"][0].clone();
+    }
+    match (schema["type"].as_str(), schema["properties"].as_hash()) {
+        (_, Some(properties)) => Yaml::Hash(
+            properties
+                .iter()
+                .map(|(k, v)| (k.clone(), get_schema_skeleton(spec, v, depth + 1)))
+                .collect(),
+        ),
+        (Some("object"), None) => Yaml::Hash(BTreeMap::new()),
+        (Some("array"), _) => {
+            Yaml::Array(vec![get_schema_skeleton(spec, &schema["items"], depth + 1)])
+        }
+        (Some("integer"), _) => Yaml::Integer(0),
+        (Some("number"), _) => Yaml::Real(String::from("0.0")),
+        (Some("boolean"), _) => Yaml::Boolean(false),
+        (Some("string"), _) => get_yaml_string(""),
+        _ => Yaml::Null,
+    }
+}
+
+///
+/// The `body` or `form` skeleton of an OpenAPI 3 request body or a Swagger 2 body parameter.
+///
+fn get_request_body(
+    spec: &Yaml,
+    operation_name: &str,
+    operation: &Yaml,
+    parameters: &[&Yaml],
+    report: &mut ImportReport,
+) -> Option<(&'static str, Yaml)> {
+    let request_body = resolve_ref(spec, &operation["requestBody"]);
+    let body_parameter = parameters.iter().find(|p| p["in"].as_str() == Some("body"));
+    let (key, schema) = match (request_body["content"].as_hash(), body_parameter) {
+        (Some(content), _) => {
+            let json_media = content
+                .iter()
+                .find(|(t, _)| t.as_str().is_some_and(|t| t.contains("json")));
+            let form_media = content
+                .iter()
+                .find(|(t, _)| t.as_str().is_some_and(|t| FORM_CONTENT_TYPES.contains(&t)));
+            match (json_media, form_media) {
+                (Some((_, m)), _) => ("body", &m["schema"]),
+                (None, Some((_, m))) => ("form", &m["schema"]),
+                _ => return None,
+            }
+        }
+        (None, Some(p)) => ("body", &p["schema"]),
+        _ => return None,
+    };
+    match get_schema_skeleton(spec, schema, 0) {
+        skeleton @ Yaml::Hash(_) => Some((key, skeleton)),
+        _ => {
+            report.add_unsupported(
+                operation_name,
+                &format!("{} is not a map, only map bodies are supported", key),
+            );
+            None
+        }
+    }
+}
+
+fn get_operation_name(path: &str, method: &str, operation: &Yaml) -> String {
+    match operation["operationId"].as_str() {
+        Some(id) => import::to_snake_case(id),
+        None => import::to_snake_case(&format!("{} {}", method, path)),
+    }
+}
+
+///
+/// A subcommand from an operation: path params become positional args, query, header and form
+/// params become options and the request body a `body` skeleton.
+///
+fn get_operation_scmd(
+    spec: &Yaml,
+    path: &str,
+    method: &str,
+    path_item: &Yaml,
+    operation: &Yaml,
+    report: &mut ImportReport,
+) -> Yaml {
+    let operation_name = format!("{} {}", method.to_uppercase(), path);
+    let parameters = get_parameters(spec, path_item, operation);
+    let mut options = BTreeMap::new();
+    let about = get_first_line(&operation["summary"])
+        .or(get_first_line(&operation["description"]))
+        .unwrap_or(operation_name.clone());
+    options.insert(get_yaml_string("about"), get_yaml_string(&about));
+    if method != "get" {
+        options.insert(get_yaml_string("method"), get_yaml_string(method));
+    }
+
+    let mut args = Vec::new();
+    let mut used_names = Vec::new();
+    let mut scmd_path = path.to_string();
+    let path_param_re = Regex::new(r"\{([^}]+)\}").unwrap();
+    for (i, capture) in path_param_re.captures_iter(path).enumerate() {
+        let name = &capture[1];
+        let parameter = parameters
+            .iter()
+            .find(|p| p["in"].as_str() == Some("path") && p["name"].as_str() == Some(name));
+        let help = parameter
+            .and_then(|p| get_first_line(&p["description"]))
+            .unwrap_or(name.to_string());
+        let arg_name =
+            import::get_unique_name(&import::to_snake_case(name).to_uppercase(), &mut used_names);
+        scmd_path = scmd_path.replace(&capture[0], &format!("{{{{args.{}}}}}", arg_name));
+        args.push(import::get_positional_arg(&arg_name, &help, i as i64 + 1));
+    }
+    options.insert(get_yaml_string("path"), get_yaml_string(&scmd_path));
+
+    let mut param_maps: BTreeMap<&str, BTreeMap<Yaml, Yaml>> = BTreeMap::new();
+    for parameter in parameters.iter() {
+        let (location, name) = match (parameter["in"].as_str(), parameter["name"].as_str()) {
+            (Some(l), Some(n)) => (l, n),
+            _ => continue,
+        };
+        let map_key = match location {
+            "query" => "query_params",
+            "header" => "headers",
+            "formData" => "form",
+            "path" | "body" => continue,
+            _ => {
+                report.add_unsupported(&operation_name, &format!("{} param {}", location, name));
+                continue;
+            }
+        };
+        let arg_name = import::get_option_arg_name(name, &mut used_names);
+        let help = get_first_line(&parameter["description"]).unwrap_or(name.to_string());
+        let required = parameter["required"].as_bool() == Some(true);
+        args.push(import::get_option_arg(&arg_name, &help, required));
+        param_maps.entry(map_key).or_default().insert(
+            get_yaml_string(name),
+            get_yaml_string(&format!("{{{{args.{}}}}}", arg_name)),
+        );
+    }
+    if !args.is_empty() {
+        options.insert(get_yaml_string("args"), Yaml::Array(args));
+    }
+    for (key, map) in param_maps {
+        options.insert(get_yaml_string(key), Yaml::Hash(map));
+    }
+    if let Some((key, skeleton)) =
+        get_request_body(spec, &operation_name, operation, &parameters, report)
+    {
+        options.insert(get_yaml_string(key), skeleton);
+    }
+    Yaml::Hash(options)
+}
+
+fn push_scmd(scmds: &mut Vec<Yaml>, used_names: &mut Vec<String>, name: &str, options: Yaml) {
+    let mut scmd = BTreeMap::new();
+    let name = import::get_unique_name(name, used_names);
+    scmd.insert(get_yaml_string(&name), options);
+    scmds.push(Yaml::Hash(scmd));
+}
+
+///
+/// A subcommand group per tag, with the operations having it as first tag.
+///
+fn get_tag_groups(spec: &Yaml, tagged_scmds: Vec<(String, Vec<Yaml>)>) -> Vec<(String, Yaml)> {
+    let mut groups = Vec::new();
+    for (tag, scmds) in tagged_scmds {
+        let tag_spec = spec["tags"]
+            .as_vec()
+            .into_iter()
+            .flatten()
+            .find(|t| t["name"].as_str() == Some(tag.as_str()));
+        let about = tag_spec
+            .and_then(|t| get_first_line(&t["description"]))
+            .unwrap_or(format!("Operations on {}", tag));
+        let mut options = BTreeMap::new();
+        options.insert(get_yaml_string("about"), get_yaml_string(&about));
+        options.insert(get_yaml_string("subcommands"), Yaml::Array(scmds));
+        groups.push((import::to_snake_case(&tag), Yaml::Hash(options)));
+    }
+    groups
+}
+
+///
+/// An extension config from an OpenAPI 3 or Swagger 2 spec, with a subcommand per operation,
+/// grouped by their first tag.
+///
+pub fn get_extension_config(
+    spec: &Yaml,
+    name: &str,
+    report: &mut ImportReport,
+) -> error::Result<Yaml> {
+    if spec["openapi"].is_badvalue() && spec["swagger"].is_badvalue() {
+        return Err(JoatError::Config(String::from(
+            "Not an OpenAPI or Swagger spec, the openapi or swagger key is missing",
+        )));
+    }
+    let mut scmds = Vec::new();
+    let mut used_names = Vec::new();
+    let mut tagged_scmds: Vec<(String, Vec<Yaml>)> = Vec::new();
+    let mut tagged_names: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (path, path_item) in spec["paths"].as_hash().into_iter().flatten() {
+        let path_str = match path.as_str() {
+            Some(p) => p,
+            None => continue,
+        };
+        let path_item = resolve_ref(spec, path_item);
+        for method in HTTP_METHODS.iter() {
+            let operation = &path_item[*method];
+            if operation.is_badvalue() {
+                continue;
+            }
+            let scmd_name = get_operation_name(path_str, method, operation);
+            let options = get_operation_scmd(spec, path_str, method, path_item, operation, report);
+            match operation["tags"][0].as_str() {
+                Some(tag) => {
+                    let names = tagged_names.entry(tag.to_string()).or_default();
+                    if !tagged_scmds.iter().any(|(t, _)| t == tag) {
+                        tagged_scmds.push((tag.to_string(), Vec::new()));
+                    }
+                    let tag_scmds = tagged_scmds.iter_mut().find(|(t, _)| t == tag).unwrap();
+                    push_scmd(&mut tag_scmds.1, names, &scmd_name, options);
+                }
+                None => push_scmd(&mut scmds, &mut used_names, &scmd_name, options),
+            }
+        }
+    }
+    for (group_name, group) in get_tag_groups(spec, tagged_scmds) {
+        push_scmd(&mut scmds, &mut used_names, &group_name, group);
+    }
+
+    let info = &spec["info"];
+    let version = match &info["version"] {
+        Yaml::String(v) => v.clone(),
+        Yaml::Real(v) => v.clone(),
+        Yaml::Integer(v) => v.to_string(),
+        _ => String::from("0.0.0"),
+    };
+    let about = get_first_line(&info["title"]).unwrap_or(name.to_string());
+    let mut config = BTreeMap::new();
+    config.insert(get_yaml_string("name"), get_yaml_string(name));
+    config.insert(get_yaml_string("version"), get_yaml_string(&version));
+    config.insert(get_yaml_string("about"), get_yaml_string(&about));
+    config.insert(
+        get_yaml_string("base_endpoint"),
+        get_yaml_string(&get_base_endpoint(spec)),
+    );
+    config.insert(get_yaml_string("subcommands"), Yaml::Array(scmds));
+    Ok(Yaml::Hash(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    const SPEC: &str = r##"
+openapi: 3.0.0
+info:
+  title: Pet store
+  version: 1.0.0
+servers:
+  - url: https://{env}.example.com/v1/
+    variables:
+      env:
+        default: api
+paths:
+  /pets/{petId}:
+    parameters:
+      - $ref: "#/components/parameters/PetId"
+    get:
+      operationId: showPetById
+      tags: [pets]
+      parameters:
+        - name: fields
+          in: query
+          description: Fields to return
+    post:
+      summary: Update a pet
+      tags: [pets]
+      requestBody:
+        content:
+          application/json:
+            schema:
+              $ref: "#/components/schemas/Pet"
+components:
+  parameters:
+    PetId:
+      name: petId
+      in: path
+      required: true
+      description: The id of the pet
+  schemas:
+    Pet:
+      properties:
+        name:
+          type: string
+        age:
+          type: integer
+"##;
+
+    #[test]
+    fn test_get_extension_config() {
+        // Arrange
+        let spec = YamlLoader::load_from_str(SPEC).unwrap().remove(0);
+
+        // Act
+        let config = get_extension_config(&spec, "pets", &mut ImportReport::default()).unwrap();
+
+        // Assert
+        assert_eq!(
+            config["base_endpoint"].as_str(),
+            Some("https://api.example.com/v1")
+        );
+        let group = &config["subcommands"][0]["pets"];
+        let show = &group["subcommands"][0]["show_pet_by_id"];
+        assert_eq!(show["path"].as_str(), Some("/pets/{{args.PET_ID}}"));
+        assert_eq!(
+            show["args"][0]["PET_ID"]["help"].as_str(),
+            Some("The id of the pet")
+        );
+        assert_eq!(
+            show["query_params"]["fields"].as_str(),
+            Some("{{args.fields}}")
+        );
+        let update = &group["subcommands"][1]["post_pets_pet_id"];
+        assert_eq!(update["method"].as_str(), Some("post"));
+        assert_eq!(update["body"]["age"], Yaml::Integer(0));
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::BTreeMap;
+use yaml_rust::Yaml;
+
+use crate::collection::{self, AuthMapping, Folder, SavedRequest, Variables};
+use crate::error::{self, JoatError};
+use crate::import::{get_scalar_string, get_yaml_string, ImportReport};
+
+///
+/// The enabled `key`/`value` entries of a list, e.g. headers or url encoded params.
+///
+fn get_pairs(
+    list: &Yaml,
+    variables: &Variables,
+    source: &str,
+    report: &mut ImportReport,
+) -> Vec<(String, String)> {
+    list.as_vec()
+        .into_iter()
+        .flatten()
+        .filter(|p| p["disabled"].as_bool() != Some(true))
+        .filter_map(|p| {
+            let key = get_scalar_string(&p["key"])?;
+            let value = get_scalar_string(&p["value"]).unwrap_or_default();
+            Some((
+                variables.convert(&key, source, report),
+                variables.convert(&value, source, report),
+            ))
+        })
+        .collect()
+}
+
+///
+/// Auth params are a list of `key`/`value` entries in v2.1 collections and a map in v2.0 ones.
+///
+fn get_auth_params(auth: &Yaml, auth_type: &str) -> BTreeMap<String, String> {
+    let params = &auth[auth_type];
+    match params {
+        Yaml::Array(a) => a
+            .iter()
+            .filter_map(|p| {
+                Some((
+                    p["key"].as_str()?.to_string(),
+                    get_scalar_string(&p["value"])?,
+                ))
+            })
+            .collect(),
+        Yaml::Hash(h) => h
+            .iter()
+            .filter_map(|(k, v)| Some((k.as_str()?.to_string(), get_scalar_string(v)?)))
+            .collect(),
+        _ => BTreeMap::new(),
+    }
+}
+
+fn get_auth(
+    auth: &Yaml,
+    variables: &Variables,
+    source: &str,
+    report: &mut ImportReport,
+) -> Option<AuthMapping> {
+    let auth_type = auth["type"].as_str()?;
+    let params = get_auth_params(auth, auth_type);
+    let mut param = |key: &str| {
+        params
+            .get(key)
+            .map(|v| variables.convert(v, source, report))
+    };
+    let mapping = match auth_type {
+        "noauth" => AuthMapping::none(),
+        "basic" | "digest" => {
+            AuthMapping::credentials(auth_type, param("username"), param("password"))
+        }
+        "bearer" => AuthMapping::bearer(param("token")),
+        "apikey" => AuthMapping::api_key(
+            param("key").unwrap_or(String::from("X-API-Key")),
+            param("value").unwrap_or_default(),
+            param("in").as_deref() == Some("query"),
+        ),
+        "awsv4" => AuthMapping::aws_sigv4(param("service"), param("region")),
+        "oauth2" => {
+            let grant = param("grant_type").unwrap_or(String::from("authorization_code"));
+            let oauth = AuthMapping::oauth(
+                &grant,
+                param("clientId"),
+                param("clientSecret"),
+                param("authUrl"),
+                param("accessTokenUrl"),
+                param("scope"),
+            );
+            match oauth {
+                Some(o) => o,
+                None => {
+                    report.add_unsupported(source, &format!("oauth2 grant {}", grant));
+                    return None;
+                }
+            }
+        }
+        t => {
+            report.add_unsupported(source, &format!("{} auth", t));
+            return None;
+        }
+    };
+    Some(mapping)
+}
+
+fn add_events(item: &Yaml, source: &str, report: &mut ImportReport) {
+    for event in item["event"].as_vec().into_iter().flatten() {
+        let has_script = match &event["script"]["exec"] {
+            Yaml::Array(lines) => lines
+                .iter()
+                .any(|l| l.as_str().is_some_and(|l| !l.trim().is_empty())),
+            Yaml::String(s) => !s.trim().is_empty(),
+            _ => false,
+        };
+        if has_script {
+            let listen = event["listen"].as_str().unwrap_or("event");
+            report.add_unsupported(source, &format!("{} script", listen));
+        }
+    }
+}
+
+///
+/// The raw url, the url is a string or an object with the `raw` one.
+///
+fn get_url(url: &Yaml) -> String {
+    match url {
+        Yaml::String(s) => s.clone(),
+        _ => url["raw"].as_str().unwrap_or("").to_string(),
+    }
+}
+
+fn get_raw_body(
+    body: &Yaml,
+    variables: &Variables,
+    source: &str,
+    report: &mut ImportReport,
+) -> Option<(&'static str, Yaml)> {
+    let raw = body["raw"].as_str().unwrap_or("");
+    if raw.trim().is_empty() {
+        return None;
+    }
+    let language = body["options"]["raw"]["language"]
+        .as_str()
+        .unwrap_or("json");
+    let parsed = match language {
+        "json" => variables.parse_json_body(raw, source, report),
+        _ => None,
+    };
+    match parsed {
+        Some(b) => Some(("body", b)),
+        _ => {
+            report.add_unsupported(source, "raw body that isn't a JSON object");
+            None
+        }
+    }
+}
+
+fn get_form_body(
+    params: &Yaml,
+    variables: &Variables,
+    source: &str,
+    report: &mut ImportReport,
+) -> Option<(&'static str, Yaml)> {
+    for p in params.as_vec().into_iter().flatten() {
+        if p["type"].as_str() == Some("file") {
+            report.add_unsupported(source, "file form field");
+        }
+    }
+    let text_params: Vec<Yaml> = params
+        .as_vec()
+        .into_iter()
+        .flatten()
+        .filter(|p| p["type"].as_str() != Some("file"))
+        .cloned()
+        .collect();
+    let pairs = get_pairs(&Yaml::Array(text_params), variables, source, report);
+    match pairs.is_empty() {
+        true => None,
+        false => Some((
+            "form",
+            Yaml::Hash(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (get_yaml_string(k), get_yaml_string(v)))
+                    .collect(),
+            ),
+        )),
+    }
+}
+
+fn get_body(
+    body: &Yaml,
+    variables: &Variables,
+    source: &str,
+    report: &mut ImportReport,
+) -> Option<(&'static str, Yaml)> {
+    match body["mode"].as_str()? {
+        "raw" => get_raw_body(body, variables, source, report),
+        "urlencoded" => get_form_body(&body["urlencoded"], variables, source, report),
+        "formdata" => get_form_body(&body["formdata"], variables, source, report),
+        "graphql" => {
+            let mut graphql = BTreeMap::new();
+            let query = body["graphql"]["query"].as_str().unwrap_or("");
+            graphql.insert(
+                get_yaml_string("query"),
+                get_yaml_string(&variables.convert(query, source, report)),
+            );
+            let graphql_vars = body["graphql"]["variables"].as_str().unwrap_or("");
+            if let Some(v) = variables.parse_json_body(graphql_vars, source, report) {
+                graphql.insert(get_yaml_string("variables"), v);
+            }
+            Some(("body", Yaml::Hash(graphql)))
+        }
+        m => {
+            report.add_unsupported(source, &format!("{} body", m));
+            None
+        }
+    }
+}
+
+fn get_request(item: &Yaml, variables: &Variables, report: &mut ImportReport) -> SavedRequest {
+    let name = item["name"].as_str().unwrap_or("request").to_string();
+    let request = &item["request"];
+    add_events(item, &name, report);
+    // A request can be only its url
+    let url = match request {
+        Yaml::String(_) => get_url(request),
+        _ => get_url(&request["url"]),
+    };
+    let query = match &request["url"]["query"] {
+        Yaml::Array(_) => get_pairs(&request["url"]["query"], variables, &name, report),
+        _ => collection::get_query_pairs(&variables.convert(&url, &name, report)),
+    };
+    SavedRequest {
+        method: request["method"].as_str().unwrap_or("GET").to_string(),
+        url: variables.convert(&url, &name, report),
+        headers: get_pairs(&request["header"], variables, &name, report),
+        query,
+        body: get_body(&request["body"], variables, &name, report),
+        auth: get_auth(&request["auth"], variables, &name, report),
+        name,
+    }
+}
+
+fn get_description(item: &Yaml) -> Option<String> {
+    let description = match &item["description"] {
+        Yaml::String(s) => s.as_str(),
+        d => d["content"].as_str()?,
+    };
+    description.lines().next().map(|l| l.trim().to_string())
+}
+
+fn get_folder(name: &str, item: &Yaml, variables: &Variables, report: &mut ImportReport) -> Folder {
+    let mut folder = Folder {
+        name: name.to_string(),
+        about: get_description(item).filter(|d| !d.is_empty()),
+        auth: get_auth(&item["auth"], variables, name, report),
+        ..Default::default()
+    };
+    add_events(item, name, report);
+    if !item["variable"].is_badvalue() && !item["name"].is_badvalue() {
+        report.add_unsupported(name, "folder variables");
+    }
+    for child in item["item"].as_vec().into_iter().flatten() {
+        let child_name = child["name"].as_str().unwrap_or("folder");
+        match child["item"].is_badvalue() {
+            true => folder.requests.push(get_request(child, variables, report)),
+            false => folder
+                .folders
+                .push(get_folder(child_name, child, variables, report)),
+        }
+    }
+    folder
+}
+
+fn get_variables(postman_collection: &Yaml) -> Variables {
+    let mut variables = Variables::default();
+    for v in postman_collection["variable"]
+        .as_vec()
+        .into_iter()
+        .flatten()
+    {
+        let key = v["key"].as_str().or(v["id"].as_str());
+        let value = get_scalar_string(&v["value"]).filter(|v| !v.is_empty());
+        if let (Some(k), Some(v)) = (key, value) {
+            variables.values.insert(k.to_string(), v);
+        }
+    }
+    variables
+}
+
+///
+/// An extension config from a Postman collection (v2.0 or v2.1), with a subcommand per request
+/// and a group per folder.
+///
+pub fn get_extension_config(
+    postman_collection: &Yaml,
+    name: &str,
+    report: &mut ImportReport,
+) -> error::Result<Yaml> {
+    let info = &postman_collection["info"];
+    if info.is_badvalue() || postman_collection["item"].is_badvalue() {
+        return Err(JoatError::Config(String::from(
+            "Not a Postman collection, the info or item key is missing",
+        )));
+    }
+    let variables = get_variables(postman_collection);
+    let collection_name = info["name"].as_str().unwrap_or(name);
+    let mut root = get_folder(collection_name, postman_collection, &variables, report);
+    root.about = get_description(info)
+        .filter(|d| !d.is_empty())
+        .or(Some(collection_name.to_string()));
+    Ok(collection::get_extension_config(
+        &root, &variables, name, report,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oauth;
+    use std::collections::HashMap;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_get_extension_config() {
+        // Arrange
+        let content = r#"{
+            "info": {"name": "Issues", "schema": "https://schema.getpostman.com/json/collection/v2.1.0/collection.json"},
+            "auth": {"type": "bearer", "bearer": [{"key": "token", "value": "{{token}}"}]},
+            "variable": [{"key": "baseUrl", "value": "https://api.test"}],
+            "item": [{
+                "name": "Issues",
+                "item": [{
+                    "name": "Create issue",
+                    "request": {
+                        "method": "POST",
+                        "url": {"raw": "{{baseUrl}}/issues/:project?draft=true", "query": [{"key": "draft", "value": "true"}]},
+                        "body": {"mode": "raw", "raw": "{\"title\": \"{{title}}\", \"count\": {{count}}}"}
+                    },
+                    "event": [{"listen": "test", "script": {"exec": ["pm.test()"]}}]
+                }]
+            }]
+        }"#;
+        let postman_collection = YamlLoader::load_from_str(content).unwrap().remove(0);
+        let mut report = ImportReport::default();
+
+        // Act
+        let config = get_extension_config(&postman_collection, "issues", &mut report).unwrap();
+
+        // Assert
+        assert_eq!(config["base_endpoint"].as_str(), Some("{{vars.base_url}}"));
+        assert_eq!(
+            config["vars"]["base_url"].as_str(),
+            Some("https://api.test")
+        );
+        assert_eq!(config["auth"]["token"].as_str(), Some("{{env.TOKEN}}"));
+        let create = &config["subcommands"][0]["issues"]["subcommands"][0]["create_issue"];
+        assert_eq!(create["method"].as_str(), Some("post"));
+        assert_eq!(create["path"].as_str(), Some("/issues/{{args.PROJECT}}"));
+        assert_eq!(create["query_params"]["draft"].as_str(), Some("true"));
+        assert_eq!(create["body"]["title"].as_str(), Some("{{env.TITLE}}"));
+        assert_eq!(create["body"]["count"].as_str(), Some("{{env.COUNT}}"));
+        assert_eq!(
+            report.unsupported,
+            &[String::from("Create issue: test script")]
+        );
+    }
+
+    #[test]
+    fn test_get_extension_config_oauth2() {
+        // Arrange
+        let content = r#"{
+            "info": {"name": "Issues"},
+            "auth": {"type": "oauth2", "oauth2": [
+                {"key": "grant_type", "value": "authorization_code"},
+                {"key": "clientId", "value": "abc"},
+                {"key": "authUrl", "value": "https://auth.test/authorize"},
+                {"key": "accessTokenUrl", "value": "https://auth.test/token"}
+            ]},
+            "item": [{"name": "List", "request": {"method": "GET", "url": "https://api.test/issues"}}]
+        }"#;
+        let postman_collection = YamlLoader::load_from_str(content).unwrap().remove(0);
+        let mut report = ImportReport::default();
+
+        // Act
+        let config = get_extension_config(&postman_collection, "issues", &mut report).unwrap();
+
+        // Assert
+        let oauth_config =
+            oauth::get_oauth_config("issues", &config["oauth"], &HashMap::new()).unwrap();
+        assert_eq!(oauth_config.client_id, "abc");
+        assert_eq!(
+            config["oauth"]["header_key"].as_str(),
+            Some("Authorization")
+        );
+        assert_eq!(config["oauth"]["header_prefix"].as_str(), Some("Bearer"));
+    }
+}
@@ -0,0 +1,152 @@
+use clap::ArgMatches;
+use std::env;
+use std::sync::Mutex;
+use yaml_rust::Yaml;
+
+use crate::error::{self, JoatError};
+use crate::{oauth, yaml};
+
+const PROFILE_ENV: &str = "JOAT_PROFILE";
+// Maps merged with the top level ones, every other profile key replaces the top level value
+const MERGED_KEYS: [&str; 3] = ["vars", "headers", "query_params"];
+
+// Token and cache paths depend on the profile, main sets it once the args are parsed
+static ACTIVE_PROFILE: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn get_active_profile() -> Option<String> {
+    ACTIVE_PROFILE.lock().unwrap().clone()
+}
+
+///
+/// Path of a file holding per profile state, like tokens and caches, in the app home folder.
+///
+/// Without an active profile the paths are the same as before profiles existed.
+///
+pub fn get_profile_file_path(app_name: &str, suffix: &str) -> error::Result<String> {
+    let home_path = oauth::get_app_home_path(app_name)?;
+    Ok(match get_active_profile() {
+        Some(p) => format!("{}.{}.{}.{}", home_path, app_name, p, suffix),
+        None => format!("{}.{}.{}", home_path, app_name, suffix),
+    })
+}
+
+///
+/// --profile is global, it's only set in the matches of the level it's passed at and below, so
+/// the deepest one wins.
+///
+fn get_arg_profile(matches: &ArgMatches) -> Option<String> {
+    let mut profile = matches.value_of("profile").map(String::from);
+    let mut level = matches;
+    while let (_, Some(child)) = level.subcommand() {
+        if let Some(p) = child.value_of("profile") {
+            profile = Some(p.to_string());
+        }
+        level = child;
+    }
+    profile
+}
+
+fn get_profile_name(config: &Yaml, matches: &ArgMatches) -> Option<String> {
+    if let Some(p) = get_arg_profile(matches) {
+        return Some(p);
+    }
+    // The variable is shared by every extension, ignore it for the ones without profiles
+    if let (Ok(p), false) = (env::var(PROFILE_ENV), config["profiles"].is_badvalue()) {
+        return Some(p);
+    }
+    config["default_profile"].as_str().map(|p| p.to_string())
+}
+
+fn apply_profile(config: &Yaml, profile: &Yaml) -> error::Result<Yaml> {
+    let mut config_hash = config
+        .clone()
+        .into_hash()
+        .ok_or(JoatError::Config(String::from(
+            "The config should be a map",
+        )))?;
+    let profile_hash = match profile.clone().into_hash() {
+        Some(h) => h,
+        None => return Ok(config.clone()),
+    };
+    for (key, value) in profile_hash {
+        let key_str = yaml::get_key_str(&key);
+        let new_value = match MERGED_KEYS.contains(&key_str.as_str()) {
+            true => yaml::combine_hash_yaml(&value, &config[key_str.as_str()]),
+            false => value,
+        };
+        config_hash.insert(key, new_value);
+    }
+    Ok(Yaml::Hash(config_hash))
+}
+
+///
+/// Resolves the profile from `--profile`, `JOAT_PROFILE` or `default_profile` and applies it to
+/// the config.
+///
+pub fn select_profile(config: &Yaml, matches: &ArgMatches) -> error::Result<Yaml> {
+    let profile_name = match get_profile_name(config, matches) {
+        Some(p) => p,
+        None => return Ok(config.clone()),
+    };
+    let profile = &config["profiles"][profile_name.as_str()];
+    if profile.is_badvalue() {
+        let available: Vec<String> = config["profiles"]
+            .as_hash()
+            .into_iter()
+            .flat_map(|p| p.keys())
+            .map(yaml::get_key_str)
+            .collect();
+        return Err(JoatError::Config(format!(
+            "Unknown profile {}, available profiles: {}",
+            profile_name,
+            available.join(", ")
+        )));
+    }
+    *ACTIVE_PROFILE.lock().unwrap() = Some(profile_name);
+    apply_profile(config, profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{App, Arg, SubCommand};
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_get_arg_profile_nested_group() {
+        // Arrange
+        let app = App::new("gitlab")
+            .arg(
+                Arg::with_name("profile")
+                    .long("profile")
+                    .takes_value(true)
+                    .global(true),
+            )
+            .subcommand(SubCommand::with_name("issue").subcommand(SubCommand::with_name("show")));
+
+        // Act
+        let matches = app
+            .get_matches_from_safe(vec!["gitlab", "issue", "show", "--profile", "staging"])
+            .unwrap();
+
+        // Assert
+        assert_eq!(get_arg_profile(&matches), Some(String::from("staging")));
+    }
+
+    #[test]
+    fn test_apply_profile() {
+        // Arrange
+        let config = &YamlLoader::load_from_str(
+            "base_endpoint: https://prod\nvars:\n  a: '1'\n  b: '2'\nprofiles:\n  staging:\n    base_endpoint: https://staging\n    vars:\n      b: '3'\n",
+        )
+        .unwrap()[0];
+
+        // Act
+        let applied = apply_profile(config, &config["profiles"]["staging"]).unwrap();
+
+        // Assert
+        assert_eq!(applied["base_endpoint"].as_str(), Some("https://staging"));
+        assert_eq!(applied["vars"]["a"].as_str(), Some("1"));
+        assert_eq!(applied["vars"]["b"].as_str(), Some("3"));
+    }
+}
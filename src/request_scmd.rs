@@ -1,18 +1,47 @@
 use log::debug;
 use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
 use serde_json::value::Value;
 use serde_json::Map;
 use std::time::Duration;
 use yaml_rust::Yaml;
 
-use crate::{http, oauth, template, yaml, Context};
+use crate::error::{self, JoatError};
+use crate::{auth, http, oauth, redact, template, yaml, Context};
 
-fn get_parsed_yaml_key(key: &str, yaml: &Yaml, error_str: &str, context: &Context) -> String {
-    template::get_compiled_template_str_with_context(
-        &yaml[key].clone().into_string().expect(error_str),
+fn get_parsed_yaml_key(
+    key: &str,
+    yaml: &Yaml,
+    error_str: &str,
+    context: &Context,
+) -> error::Result<String> {
+    let raw_value = yaml[key]
+        .clone()
+        .into_string()
+        .ok_or(JoatError::Config(error_str.to_string()))?;
+    Ok(template::get_compiled_template_str_with_context(
+        &raw_value, context,
+    )?)
+}
+
+///
+/// The oauth header value, the token with the optional `header_prefix` (e.g. `Bearer`).
+///
+fn get_oauth_header_value(
+    oauth_yaml: &Yaml,
+    token: &str,
+    context: &Context,
+) -> error::Result<String> {
+    if oauth_yaml["header_prefix"].is_badvalue() {
+        return Ok(token.to_string());
+    }
+    let prefix = get_parsed_yaml_key(
+        "header_prefix",
+        oauth_yaml,
+        "oauth header_prefix should be a string",
         context,
-    )
-    .expect(format!("Could not parse template for yaml key: {}", key).as_str())
+    )?;
+    Ok(format!("{} {}", prefix, token))
 }
 
 fn print_response_json(result: &Value, pretty: bool) {
@@ -33,15 +62,16 @@ fn print_response_template(
     mut context: Context,
     response_body: Value,
     headers_context: Value,
-) {
-    let template_parser = template::Template::new(app_name);
+) -> error::Result<()> {
+    let template_parser = template::Template::new(app_name)?;
 
     context.insert(String::from("response"), response_body);
     context.insert(String::from("response_headers"), headers_context);
     print!(
         "{}",
-        template_parser.get_compiled_template_with_context(template, context)
+        template_parser.get_compiled_template_with_context(template, context)?
     );
+    Ok(())
 }
 
 fn get_headers_map(headers: &HeaderMap) -> Value {
@@ -54,77 +84,104 @@ fn get_headers_map(headers: &HeaderMap) -> Value {
     Value::Object(map)
 }
 
-fn get_base_endpoint(yaml: &Yaml, subcmd_yaml: &Yaml) -> String {
-    let err = "Endpoint should be a string";
-    match subcmd_yaml["base_endpoint"].is_badvalue() {
-        true => yaml["base_endpoint"].as_str().expect(err).to_string(),
-        false => subcmd_yaml["base_endpoint"]
-            .as_str()
-            .expect(err)
-            .to_string(),
-    }
+fn get_base_endpoint(yaml: &Yaml, subcmd_yaml: &Yaml) -> error::Result<String> {
+    let base_endpoint = match subcmd_yaml["base_endpoint"].is_badvalue() {
+        true => &yaml["base_endpoint"],
+        false => &subcmd_yaml["base_endpoint"],
+    };
+    base_endpoint
+        .as_str()
+        .map(|e| e.to_string())
+        .ok_or(JoatError::Config(String::from(
+            "base_endpoint should be a string, set it at the top level or in the subcommand",
+        )))
 }
 
-fn get_path(subcmd_yaml: &Yaml) -> String {
+fn get_path(subcmd_yaml: &Yaml) -> error::Result<String> {
     subcmd_yaml["path"]
         .as_str()
-        .expect("Path should be a string")
-        .to_string()
+        .map(|p| p.to_string())
+        .ok_or(JoatError::Config(String::from("path should be a string")))
 }
 
-pub fn execute_request(app_name: &String, yaml: &Yaml, subcmd_yaml: &Yaml, context: Context) {
+///
+/// `auth: none` (or `type: none`) on a subcommand or its group, e.g. a public endpoint of an API
+/// using oauth: no token is fetched nor sent.
+///
+fn is_auth_disabled(subcmd_yaml: &Yaml) -> bool {
+    let auth_yaml = &subcmd_yaml["auth"];
+    auth_yaml.as_str() == Some("none") || auth_yaml["type"].as_str() == Some("none")
+}
+
+pub fn execute_request(
+    app_name: &String,
+    yaml: &Yaml,
+    subcmd_yaml: &Yaml,
+    mut context: Context,
+) -> error::Result<()> {
     let subcmd_hash = subcmd_yaml
         .clone()
         .into_hash()
-        .expect("Could not hash subcmd yaml");
+        .ok_or(JoatError::Config(String::from(
+            "Subcommand should be a map",
+        )))?;
     let http_method: String;
     if subcmd_hash.contains_key(&Yaml::from_str("method")) {
-        let method_template = subcmd_yaml["method"].clone().into_string().unwrap();
-        http_method = template::get_compiled_template_str_with_context(&method_template, &context)
-            .expect("Could not parse request method");
+        http_method =
+            get_parsed_yaml_key("method", subcmd_yaml, "method should be a string", &context)?;
     } else {
         http_method = String::from("get")
     }
 
-    let mut headers = yaml::get_hash_from_yaml(&yaml["headers"], &context, false);
-    let scmd_headers = yaml::get_hash_from_yaml(&subcmd_yaml["headers"], &context, false);
+    // Get the token first so templates can use the auth context (e.g. id token claims)
+    let oauth_yaml = &yaml["oauth"];
+    let mut oauth_token = None;
+    if !oauth_yaml.is_badvalue() && !is_auth_disabled(subcmd_yaml) {
+        let oauth_config = oauth::get_oauth_config(app_name, &oauth_yaml, &context)?;
+        oauth_token = Some((
+            oauth::get_oauth_token(app_name, &oauth_config)?,
+            oauth_config,
+        ));
+        context.insert(String::from("auth"), oauth::get_auth_context(app_name)?);
+    }
+
+    let mut headers = yaml::get_hash_from_yaml(&yaml["headers"], &context, false)?;
+    let scmd_headers = yaml::get_hash_from_yaml(&subcmd_yaml["headers"], &context, false)?;
     for (key, value) in scmd_headers {
         headers.insert(key, value);
     }
 
-    let body = yaml::get_hash_from_yaml(&subcmd_yaml["body"], &context, true);
-    let form = yaml::get_hash_from_yaml(&subcmd_yaml["form"], &context, true);
+    let body = yaml::get_hash_from_yaml(&subcmd_yaml["body"], &context, true)?;
+    let form = yaml::get_hash_from_yaml(&subcmd_yaml["form"], &context, true)?;
 
     let query_params_yaml =
         yaml::combine_hash_yaml(&subcmd_yaml["query_params"], &yaml["query_params"]);
-    let query_params = yaml::get_hash_from_yaml(&query_params_yaml, &context, false);
+    let query_params = yaml::get_hash_from_yaml(&query_params_yaml, &context, false)?;
 
-    let base_endpoint = get_base_endpoint(&yaml, &subcmd_yaml);
-    let path = get_path(&subcmd_yaml);
-    let endpoint = http::get_endpoint(&base_endpoint, &path, &context, &query_params);
+    let base_endpoint = get_base_endpoint(&yaml, &subcmd_yaml)?;
+    let path = get_path(&subcmd_yaml)?;
+    let raw_endpoint = http::get_endpoint(&base_endpoint, &path, &context, &query_params)?;
 
-    let oauth_yaml = &yaml["oauth"];
-    if !oauth_yaml.is_badvalue() {
-        let client_id =
-            get_parsed_yaml_key("client_id", &oauth_yaml, "Missing client_id", &context);
-        let client_secret = get_parsed_yaml_key(
-            "client_secret",
+    let auth = auth::get_auth(app_name, &yaml, &subcmd_yaml, &raw_endpoint, &context)?;
+    auth::add_auth_headers(&auth, &mut headers);
+    let endpoint = auth::add_auth_query_param(&auth, raw_endpoint);
+
+    let mut oauth_header = None;
+    if let Some((token, oauth_config)) = oauth_token {
+        let header_name = get_parsed_yaml_key(
+            "header_key",
             &oauth_yaml,
-            "Missing client_secret",
+            "Missing oauth header_key",
             &context,
-        );
-        let auth_url = get_parsed_yaml_key("auth_url", &oauth_yaml, "Missing auth_url", &context);
-        let token_url =
-            get_parsed_yaml_key("token_url", &oauth_yaml, "Missing token_url", &context);
-        let oauth_token =
-            oauth::get_oauth_token(app_name, client_id, client_secret, auth_url, token_url);
-
-        let header_name =
-            get_parsed_yaml_key("header_key", &oauth_yaml, "Missing header_key", &context);
-        headers.insert(header_name, Value::String(oauth_token));
+        )?;
+        redact::add_secret(&token);
+        let header_value = get_oauth_header_value(&oauth_yaml, &token, &context)?;
+        headers.insert(header_name.clone(), Value::String(header_value));
+        oauth_header = Some((header_name, oauth_config));
     }
-    debug!("Request Body {:?}", body);
-    debug!("Request Form {:?}", form);
+    redact::add_sensitive_values(&headers);
+    debug!("Request Body {}", redact::redact(&format!("{:?}", body)));
+    debug!("Request Form {}", redact::redact(&format!("{:?}", form)));
     let timeout_duration_value = yaml::get_value_from_yaml(&subcmd_yaml["timeout"], &context);
     let timeout_duration = match timeout_duration_value {
         Some(v) => match v.as_u64() {
@@ -140,13 +197,45 @@ pub fn execute_request(app_name: &String, yaml: &Yaml, subcmd_yaml: &Yaml, conte
         &body,
         &form,
         timeout_duration,
-    );
+        &auth,
+    )?;
+    if let Some((header_name, oauth_config)) = &oauth_header {
+        if response.status() == StatusCode::UNAUTHORIZED {
+            debug!("Oauth token rejected, renewing it");
+            let oauth_token = oauth::renew_oauth_token(app_name, oauth_config)?;
+            redact::add_secret(&oauth_token);
+            let header_value = get_oauth_header_value(&oauth_yaml, &oauth_token, &context)?;
+            headers.insert(header_name.clone(), Value::String(header_value));
+            response = http::request(
+                &http_method,
+                &endpoint,
+                &headers,
+                &body,
+                &form,
+                timeout_duration,
+                &auth,
+            )?;
+        }
+    }
+    if let Some(digest_headers) =
+        auth::get_digest_retry_headers(&auth, &http_method, &endpoint, &response, &headers)?
+    {
+        response = http::request(
+            &http_method,
+            &endpoint,
+            &digest_headers,
+            &body,
+            &form,
+            timeout_duration,
+            &auth,
+        )?;
+    }
     let response_body: Value = match response.json() {
         Ok(r) => r,
         Err(_e) => {
             let response_str = response
                 .text()
-                .expect("Could not convert response to json or text");
+                .map_err(|e| JoatError::Network(format!("Could not read the response: {}", e)))?;
             Value::String(response_str)
         }
     };
@@ -156,13 +245,13 @@ pub fn execute_request(app_name: &String, yaml: &Yaml, subcmd_yaml: &Yaml, conte
 
     // Quiet
     if context_args.contains_key("quiet") {
-        return;
+        return Ok(());
     }
 
     // Raw output
     if context_args.contains_key("raw_response") {
         print_response_json(&response_body, false);
-        return;
+        return Ok(());
     }
 
     let headers_map = get_headers_map(response.headers());
@@ -176,7 +265,7 @@ pub fn execute_request(app_name: &String, yaml: &Yaml, subcmd_yaml: &Yaml, conte
                 .as_str() // avoids quotes on the string
                 .expect("Could not convert template str")
                 .to_string();
-            print_response_template(template_str, app_name, context, response_body, headers_map);
+            print_response_template(template_str, app_name, context, response_body, headers_map)?;
         }
     } else if subcmd_hash.contains_key(&Yaml::from_str("response_template")) {
         let response_template = subcmd_yaml["response_template"]
@@ -189,9 +278,29 @@ pub fn execute_request(app_name: &String, yaml: &Yaml, subcmd_yaml: &Yaml, conte
             context,
             response_body,
             headers_map,
-        );
+        )?;
     } else {
         print_response_json(&response_body, true);
-        return;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    #[test]
+    fn test_is_auth_disabled() {
+        // Arrange
+        let load = |s: &str| YamlLoader::load_from_str(s).unwrap().remove(0);
+
+        // Act, Assert
+        assert!(is_auth_disabled(&load("path: /status\nauth: none\n")));
+        assert!(is_auth_disabled(&load(
+            "path: /status\nauth: {type: none}\n"
+        )));
+        assert!(!is_auth_disabled(&load("path: /status\nauth: basic\n")));
+        assert!(!is_auth_disabled(&load("path: /status\n")));
     }
 }
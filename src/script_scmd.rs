@@ -1,10 +1,11 @@
 use log::info;
 use std::env;
-use std::process::{Command, Stdio};
+use std::process::{Command, ExitStatus, Stdio};
 use terminal_size::{terminal_size, Height, Width};
 use yaml_rust::Yaml;
 
-use crate::{template, Context};
+use crate::error::{self, JoatError};
+use crate::{redact, template, Context};
 
 const RECUSRION_COUNT_VAR_NAME: &str = "JOAT_RECURSION_COUNT";
 const COLUMNS_ENV_VAR_NAME: &str = "COLUMNS";
@@ -21,15 +22,18 @@ fn get_terminal_width() -> u16 {
     return 80;
 }
 
-fn check_recursion_count(yaml: &Yaml) -> i64 {
+fn check_recursion_count(yaml: &Yaml) -> error::Result<i64> {
     let max_recursion_count;
     if yaml["max_recursion_count"].is_badvalue() {
         max_recursion_count = 100;
     } else {
-        max_recursion_count = yaml["max_recursion_count"]
-            .clone()
-            .into_i64()
-            .expect("Max recursion should be an integer");
+        max_recursion_count =
+            yaml["max_recursion_count"]
+                .clone()
+                .into_i64()
+                .ok_or(JoatError::Config(String::from(
+                    "max_recursion_count should be an integer",
+                )))?;
     }
     let recursion_count: i64 = match env::var(RECUSRION_COUNT_VAR_NAME) {
         Ok(count) => count.parse().expect(&format!(
@@ -39,23 +43,23 @@ fn check_recursion_count(yaml: &Yaml) -> i64 {
         Err(_e) => 0,
     };
     if recursion_count > max_recursion_count {
-        println!("Max recursion count ({:?}) reached", max_recursion_count);
-        println!("Check for infinite loops in your yaml or increase max_recursion_count config");
-        ::std::process::exit(1);
+        return Err(JoatError::Script(format!(
+            "Max recursion count ({:?}) reached, check for infinite loops in your yaml or increase max_recursion_count config",
+            max_recursion_count
+        )));
     }
-    return recursion_count + 1;
+    return Ok(recursion_count + 1);
 }
 
-pub fn execute_script(context: Context, subcmd_yaml: &Yaml, yaml: &Yaml) {
+pub fn execute_script(context: Context, subcmd_yaml: &Yaml, yaml: &Yaml) -> error::Result<()> {
     let script_string = subcmd_yaml["script"]
         .clone()
         .into_string()
-        .expect("Could not convert script to string");
-    let script = template::get_compiled_template_str_with_context(&script_string, &context)
-        .expect(format!("Could not parse script template {:?}", script_string).as_str());
-    info!("Executing script\n {}", script);
+        .ok_or(JoatError::Config(String::from("script should be a string")))?;
+    let script = template::get_compiled_template_str_with_context(&script_string, &context)?;
+    info!("Executing script\n {}", redact::redact(&script));
     let columns = get_terminal_width();
-    let recursion_count = check_recursion_count(yaml);
+    let recursion_count = check_recursion_count(yaml)?;
     let mut command = Command::new("bash");
     command
         .arg("-c")
@@ -64,17 +68,31 @@ pub fn execute_script(context: Context, subcmd_yaml: &Yaml, yaml: &Yaml) {
         .env(RECUSRION_COUNT_VAR_NAME, recursion_count.to_string());
 
     let context_args = context["args"].as_object().unwrap();
+    let spawn_error =
+        |e: std::io::Error| JoatError::Script(format!("Failed to execute script: {}", e));
     if context_args.contains_key("quiet") {
-        let output = command.output().expect("Failed to execute script");
-        ::std::process::exit(output.status.code().expect("Unknown exit code"));
+        let output = command.output().map_err(spawn_error)?;
+        exit_with_script_status(output.status);
     }
 
     let mut cmd = command
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
         .spawn()
-        .expect("Failed to execute script");
+        .map_err(spawn_error)?;
 
-    let status = cmd.wait().expect("Failed to wait end of script");
-    ::std::process::exit(status.code().expect("Unknown exit code"));
+    let status = cmd.wait().map_err(spawn_error)?;
+    exit_with_script_status(status);
+}
+
+///
+/// The script exit code is the command exit code, scripts killed by a signal have none.
+///
+fn exit_with_script_status(status: ExitStatus) -> ! {
+    match status.code() {
+        Some(c) => ::std::process::exit(c),
+        None => error::exit(JoatError::Script(String::from(
+            "Script was terminated by a signal",
+        ))),
+    }
 }
@@ -0,0 +1,341 @@
+extern crate ring;
+extern crate rpassword;
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::value::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::error::{self, JoatError};
+use crate::{oauth, redact};
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const KDF_ITERATIONS: u32 = 100_000;
+const KEY_FILE_ENV: &str = "JOAT_SECRETS_KEY_FILE";
+
+///
+/// On disk format of the secret store, the whole name to value map is encrypted.
+///
+#[derive(Deserialize, Serialize)]
+struct EncryptedStore {
+    kdf_iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+struct UnlockedStore {
+    key: [u8; KEY_LEN],
+    salt: Vec<u8>,
+    secrets: BTreeMap<String, String>,
+}
+
+// Template functions can't receive the app name, main sets it once at startup
+static APP_NAME: Mutex<Option<String>> = Mutex::new(None);
+static UNLOCKED_STORE: Mutex<Option<UnlockedStore>> = Mutex::new(None);
+
+pub fn init(app_name: &str) {
+    *APP_NAME.lock().unwrap() = Some(app_name.to_string());
+}
+
+fn get_app_name() -> String {
+    APP_NAME
+        .lock()
+        .unwrap()
+        .clone()
+        .expect("Secret store used before init")
+}
+
+fn get_store_file_path(app_name: &str) -> error::Result<String> {
+    Ok(format!(
+        "{}.{}.secrets",
+        oauth::get_app_home_path(app_name)?,
+        app_name
+    ))
+}
+
+fn get_key_file_path(app_name: &str) -> error::Result<Option<String>> {
+    if let Ok(p) = env::var(KEY_FILE_ENV) {
+        return Ok(Some(p));
+    }
+    let default_path = format!(
+        "{}.{}.secrets.key",
+        oauth::get_app_home_path(app_name)?,
+        app_name
+    );
+    Ok(match Path::new(&default_path).exists() {
+        true => Some(default_path),
+        false => None,
+    })
+}
+
+///
+/// Writes a file only the current user can read, used for anything holding credentials.
+///
+pub fn write_private_file(path: &str, content: &str) -> error::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // Created private, the content is never readable by others, not even briefly
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| JoatError::Io(format!("Unable to write {}: {}", path, e)))?;
+    // The mode only applies to new files, older versions created them readable by others
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .map_err(|e| JoatError::Io(format!("Could not set permissions of {}: {}", path, e)))?;
+    }
+    file.write_all(content.as_bytes())
+        .map_err(|e| JoatError::Io(format!("Unable to write {}: {}", path, e)))
+}
+
+fn read_password(prompt: &str) -> error::Result<String> {
+    rpassword::read_password_from_tty(Some(prompt))
+        .map_err(|e| JoatError::Io(format!("Could not read from tty: {}", e)))
+}
+
+fn get_passphrase(app_name: &str, confirm: bool) -> error::Result<String> {
+    if let Some(key_file) = get_key_file_path(app_name)? {
+        let content = fs::read_to_string(&key_file).map_err(|e| {
+            JoatError::Io(format!(
+                "Could not read secrets key file {}: {}",
+                key_file, e
+            ))
+        })?;
+        return Ok(content.trim().to_string());
+    }
+    let passphrase = read_password("Secrets passphrase: ")?;
+    if confirm && read_password("Confirm passphrase: ")? != passphrase {
+        return Err(JoatError::Auth(String::from("Passphrases don't match")));
+    }
+    Ok(passphrase)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; KEY_LEN] {
+    let mut key = [0; KEY_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(iterations).expect("Invalid kdf iterations"),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    key
+}
+
+fn get_aead_key(key: &[u8; KEY_LEN]) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).expect("Invalid secrets key"))
+}
+
+fn encrypt(key: &[u8; KEY_LEN], salt: &[u8], secrets: &BTreeMap<String, String>) -> EncryptedStore {
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .expect("Could not generate nonce");
+    let mut in_out = serde_json::to_vec(secrets).expect("Could not serialize secrets");
+    get_aead_key(key)
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut in_out,
+        )
+        .expect("Could not encrypt secrets");
+    EncryptedStore {
+        kdf_iterations: KDF_ITERATIONS,
+        salt: base64::encode(salt),
+        nonce: base64::encode(&nonce),
+        ciphertext: base64::encode(&in_out),
+    }
+}
+
+fn decrypt(key: &[u8; KEY_LEN], store: &EncryptedStore) -> Option<BTreeMap<String, String>> {
+    let nonce_bytes = base64::decode(&store.nonce).ok()?;
+    let nonce = Nonce::try_assume_unique_for_key(&nonce_bytes).ok()?;
+    let mut in_out = base64::decode(&store.ciphertext).ok()?;
+    let plaintext = get_aead_key(key)
+        .open_in_place(nonce, Aad::empty(), &mut in_out)
+        .ok()?;
+    serde_json::from_slice(plaintext).ok()
+}
+
+fn unlock(app_name: &str, create: bool) -> error::Result<Option<UnlockedStore>> {
+    let store_path = get_store_file_path(app_name)?;
+    if !Path::new(&store_path).exists() {
+        if !create {
+            return Ok(None);
+        }
+        let mut salt = vec![0; SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_e| JoatError::Internal(String::from("Could not generate salt")))?;
+        let key = derive_key(&get_passphrase(app_name, true)?, &salt, KDF_ITERATIONS);
+        return Ok(Some(UnlockedStore {
+            key,
+            salt,
+            secrets: BTreeMap::new(),
+        }));
+    }
+    let content = fs::read_to_string(&store_path)
+        .map_err(|e| JoatError::Io(format!("Could not read {}: {}", store_path, e)))?;
+    let invalid_store = || JoatError::Config(format!("Invalid secret store file {}", store_path));
+    let store: EncryptedStore = serde_json::from_str(&content).map_err(|_e| invalid_store())?;
+    let salt = base64::decode(&store.salt).map_err(|_e| invalid_store())?;
+    if store.kdf_iterations == 0 {
+        return Err(invalid_store());
+    }
+    let key = derive_key(
+        &get_passphrase(app_name, false)?,
+        &salt,
+        store.kdf_iterations,
+    );
+    match decrypt(&key, &store) {
+        Some(secrets) => Ok(Some(UnlockedStore { key, salt, secrets })),
+        None => Err(JoatError::Auth(format!(
+            "Could not unlock {}, wrong passphrase or key file?",
+            store_path
+        ))),
+    }
+}
+
+///
+/// Runs f with the unlocked store, prompting for the passphrase only once per execution.
+///
+fn with_store<T>(
+    create: bool,
+    f: impl FnOnce(&mut UnlockedStore) -> error::Result<T>,
+) -> error::Result<Option<T>> {
+    let mut unlocked = UNLOCKED_STORE.lock().unwrap();
+    if unlocked.is_none() {
+        *unlocked = unlock(&get_app_name(), create)?;
+    }
+    unlocked.as_mut().map(f).transpose()
+}
+
+fn save(store: &UnlockedStore) -> error::Result<()> {
+    let app_name = get_app_name();
+    let encrypted = encrypt(&store.key, &store.salt, &store.secrets);
+    let content = serde_json::to_string(&encrypted)
+        .map_err(|e| JoatError::Internal(format!("Could not serialize secret store: {}", e)))?;
+    let app_home_path = oauth::get_app_home_path(&app_name)?;
+    fs::create_dir_all(&app_home_path)
+        .map_err(|e| JoatError::Io(format!("Could not create {}: {}", app_home_path, e)))?;
+    write_private_file(&get_store_file_path(&app_name)?, &content)
+}
+
+pub fn get_secret(name: &str) -> error::Result<Option<String>> {
+    with_store(false, |s| Ok(s.secrets.get(name).cloned())).map(|s| s.flatten())
+}
+
+pub fn set_secret(name: &str, value: &str) -> error::Result<()> {
+    with_store(true, |s| {
+        s.secrets.insert(name.to_string(), value.to_string());
+        save(s)
+    })?;
+    Ok(())
+}
+
+pub fn remove_secret(name: &str) -> error::Result<bool> {
+    let removed = with_store(false, |s| {
+        let removed = s.secrets.remove(name).is_some();
+        if removed {
+            save(s)?;
+        }
+        Ok(removed)
+    })?;
+    Ok(removed.unwrap_or(false))
+}
+
+pub fn list_secrets() -> error::Result<Vec<String>> {
+    let names = with_store(false, |s| Ok(s.secrets.keys().cloned().collect()))?;
+    Ok(names.unwrap_or_default())
+}
+
+///
+/// The `secret(name="...")` template function, a positional name isn't supported by tera.
+///
+pub fn secret_template_function(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let name = match args.get("name").and_then(|n| n.as_str()) {
+        Some(n) => n,
+        None => return Err("secret() needs a name argument, e.g. secret(name=\"token\")".into()),
+    };
+    match get_secret(name).map_err(|e| e.message().to_string())? {
+        Some(s) => {
+            redact::add_secret(&s);
+            Ok(Value::String(s))
+        }
+        None => Err(format!(
+            "Secret {} not found, add it with: secrets set {}",
+            name, name
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_private_file() {
+        // Arrange
+        use std::os::unix::fs::PermissionsExt;
+        let path = env::temp_dir().join(format!("joat-private-{}", std::process::id()));
+        let path_str = path.to_string_lossy().to_string();
+        fs::write(&path, "old content").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        // Act
+        write_private_file(&path_str, "token").unwrap();
+
+        // Assert
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "token");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        // Arrange
+        let salt = b"0123456789abcdef";
+        let key = derive_key("passphrase", salt, 1000);
+        let mut secrets = BTreeMap::new();
+        secrets.insert(String::from("gitlab_token"), String::from("abc"));
+
+        // Act
+        let store = encrypt(&key, salt, &secrets);
+        let decrypted = decrypt(&key, &store).unwrap();
+
+        // Assert
+        assert_eq!(decrypted, secrets);
+        assert!(!store.ciphertext.contains("abc"));
+    }
+
+    #[test]
+    fn test_decrypt_with_wrong_key() {
+        // Arrange
+        let salt = b"0123456789abcdef";
+        let key = derive_key("passphrase", salt, 1000);
+        let wrong_key = derive_key("other", salt, 1000);
+        let store = encrypt(&key, salt, &BTreeMap::new());
+
+        // Act, Assert
+        assert_eq!(decrypt(&wrong_key, &store), None);
+    }
+}
@@ -0,0 +1,376 @@
+extern crate chrono;
+extern crate hmac;
+extern crate sha2;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use log::debug;
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE, HOST};
+use reqwest::Request;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use url::percent_encoding::percent_decode;
+use url::Url;
+
+use crate::error::{self, JoatError};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const DATE_HEADER: &str = "x-amz-date";
+const CONTENT_SHA256_HEADER: &str = "x-amz-content-sha256";
+const SECURITY_TOKEN_HEADER: &str = "x-amz-security-token";
+
+#[derive(Clone, Debug)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+fn get_credentials_from_env() -> Option<AwsCredentials> {
+    let access_key_id = env::var("AWS_ACCESS_KEY_ID").ok()?;
+    let secret_access_key = env::var("AWS_SECRET_ACCESS_KEY").ok()?;
+    Some(AwsCredentials {
+        access_key_id,
+        secret_access_key,
+        session_token: env::var("AWS_SESSION_TOKEN").ok(),
+    })
+}
+
+fn get_credentials_file_path() -> error::Result<String> {
+    match env::var("AWS_SHARED_CREDENTIALS_FILE") {
+        Ok(p) => Ok(p),
+        Err(_) => {
+            let home_dir_path =
+                dirs::home_dir().ok_or(JoatError::Io(String::from("Could not find home dir")))?;
+            Ok(format!(
+                "{}/.aws/credentials",
+                home_dir_path.to_string_lossy()
+            ))
+        }
+    }
+}
+
+fn parse_credentials_file(content: &str, profile: &str) -> Option<AwsCredentials> {
+    let mut current_profile = String::new();
+    let mut values = HashMap::new();
+    for raw_line in content.lines() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            current_profile = line[1..line.len() - 1].trim().to_string();
+            continue;
+        }
+        if current_profile != profile {
+            continue;
+        }
+        if let Some(i) = line.find('=') {
+            let key = line[..i].trim().to_string();
+            let value = line[i + 1..].trim().to_string();
+            values.insert(key, value);
+        }
+    }
+    Some(AwsCredentials {
+        access_key_id: values.remove("aws_access_key_id")?,
+        secret_access_key: values.remove("aws_secret_access_key")?,
+        session_token: values.remove("aws_session_token"),
+    })
+}
+
+///
+/// Looks up AWS credentials the same way the AWS CLI does.
+///
+/// Environment variables come first, then the shared credentials file using the given profile,
+/// `AWS_PROFILE` or `default`.
+///
+pub fn get_credentials(profile: Option<String>) -> error::Result<AwsCredentials> {
+    if let Some(c) = get_credentials_from_env() {
+        return Ok(c);
+    }
+    let profile_name = profile
+        .or(env::var("AWS_PROFILE").ok())
+        .unwrap_or(String::from("default"));
+    let credentials_path = get_credentials_file_path()?;
+    let content = fs::read_to_string(&credentials_path).map_err(|_e| {
+        JoatError::Auth(format!(
+            "No AWS credentials in env and could not read {}",
+            credentials_path
+        ))
+    })?;
+    parse_credentials_file(&content, &profile_name).ok_or(JoatError::Auth(format!(
+        "Could not find AWS credentials for profile {} in {}",
+        profile_name, credentials_path
+    )))
+}
+
+pub fn get_region(region: Option<String>) -> error::Result<String> {
+    match region {
+        Some(r) => Ok(r),
+        None => env::var("AWS_REGION")
+            .or(env::var("AWS_DEFAULT_REGION"))
+            .map_err(|_e| {
+                JoatError::Config(String::from(
+                    "Missing AWS region, set region in auth or AWS_REGION",
+                ))
+            }),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts keys of any size");
+    mac.input(data.as_bytes());
+    mac.result().code().to_vec()
+}
+
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::new();
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn get_canonical_uri(url: &Url, service: &str) -> String {
+    let path = url.path();
+    if path.is_empty() {
+        return String::from("/");
+    }
+    path.split('/')
+        .map(|segment| {
+            let decoded = percent_decode(segment.as_bytes()).decode_utf8_lossy();
+            let encoded = uri_encode(&decoded, true);
+            // Every service but S3 expects the path to be encoded twice
+            match service {
+                "s3" => encoded,
+                _ => uri_encode(&encoded, true),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn get_canonical_query(url: &Url) -> String {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(k, v)| (uri_encode(&k, true), uri_encode(&v, true)))
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+fn get_host(url: &Url) -> error::Result<String> {
+    let host = url
+        .host_str()
+        .ok_or(JoatError::Config(format!("Endpoint {} without host", url)))?;
+    Ok(match url.port() {
+        Some(p) => format!("{}:{}", host, p),
+        None => host.to_string(),
+    })
+}
+
+fn get_signing_key(secret_access_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let k_date = hmac_sha256(format!("AWS4{}", secret_access_key).as_bytes(), date);
+    let k_region = hmac_sha256(&k_date, region);
+    let k_service = hmac_sha256(&k_region, service);
+    hmac_sha256(&k_service, "aws4_request")
+}
+
+///
+/// Computes the SigV4 headers for a request.
+///
+/// The headers argument holds the ones the request will already carry that should be signed
+/// (lowercase names). Returns the headers to add to the request, `authorization` included.
+///
+fn get_signature_headers(
+    method: &str,
+    url: &Url,
+    headers: &Vec<(String, String)>,
+    payload: &[u8],
+    credentials: &AwsCredentials,
+    service: &str,
+    region: &str,
+    now: DateTime<Utc>,
+) -> error::Result<Vec<(String, String)>> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = sha256_hex(payload);
+
+    let mut added_headers = vec![(DATE_HEADER.to_string(), amz_date.clone())];
+    if service == "s3" {
+        added_headers.push((CONTENT_SHA256_HEADER.to_string(), payload_hash.clone()));
+    }
+    if let Some(token) = &credentials.session_token {
+        added_headers.push((SECURITY_TOKEN_HEADER.to_string(), token.clone()));
+    }
+
+    let mut signed: Vec<(String, String)> = vec![("host".to_string(), get_host(url)?)];
+    signed.extend(headers.iter().cloned());
+    signed.extend(added_headers.iter().cloned());
+    signed.sort();
+
+    let canonical_headers: String = signed
+        .iter()
+        .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+        .collect();
+    let signed_headers = signed
+        .iter()
+        .map(|(k, _)| k.as_str())
+        .collect::<Vec<&str>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method.to_uppercase(),
+        get_canonical_uri(url, service),
+        get_canonical_query(url),
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+    debug!("SigV4 canonical request\n{}", canonical_request);
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM,
+        amz_date,
+        scope,
+        sha256_hex(canonical_request.as_bytes())
+    );
+    let signing_key = get_signing_key(&credentials.secret_access_key, &date, region, service);
+    let signature = hex(&hmac_sha256(&signing_key, &string_to_sign));
+
+    let authorization = format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        ALGORITHM, credentials.access_key_id, scope, signed_headers, signature
+    );
+    added_headers.push((AUTHORIZATION.as_str().to_string(), authorization));
+    Ok(added_headers)
+}
+
+pub fn sign_request(
+    request: &mut Request,
+    payload: &[u8],
+    credentials: &AwsCredentials,
+    service: &str,
+    region: &str,
+) -> error::Result<()> {
+    let invalid_header = |e: String| JoatError::Config(format!("Invalid header to sign: {}", e));
+    let mut headers = Vec::new();
+    if let Some(content_type) = request.headers().get(CONTENT_TYPE) {
+        let value = content_type
+            .to_str()
+            .map_err(|e| invalid_header(e.to_string()))?;
+        headers.push((CONTENT_TYPE.as_str().to_string(), value.to_string()));
+    }
+    let signature_headers = get_signature_headers(
+        request.method().as_str(),
+        request.url(),
+        &headers,
+        payload,
+        credentials,
+        service,
+        region,
+        Utc::now(),
+    )?;
+    let host = get_host(request.url())?;
+    let request_headers = request.headers_mut();
+    request_headers.insert(
+        HOST,
+        HeaderValue::from_str(&host).map_err(|e| invalid_header(e.to_string()))?,
+    );
+    for (name, value) in signature_headers {
+        request_headers.insert(
+            HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid_header(e.to_string()))?,
+            HeaderValue::from_str(&value).map_err(|e| invalid_header(e.to_string()))?,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn example_credentials() -> AwsCredentials {
+        AwsCredentials {
+            access_key_id: String::from("AKIDEXAMPLE"),
+            secret_access_key: String::from("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+            session_token: None,
+        }
+    }
+
+    #[test]
+    fn test_get_signature_headers_get_vanilla() {
+        // Arrange, from the AWS SigV4 test suite
+        let url = Url::parse("https://example.amazonaws.com/").unwrap();
+        let now = Utc.ymd(2015, 8, 30).and_hms(12, 36, 0);
+
+        // Act
+        let headers = get_signature_headers(
+            "GET",
+            &url,
+            &Vec::new(),
+            b"",
+            &example_credentials(),
+            "service",
+            "us-east-1",
+            now,
+        )
+        .unwrap();
+
+        // Assert
+        assert_eq!(
+            headers[0],
+            (String::from("x-amz-date"), String::from("20150830T123600Z"))
+        );
+        assert_eq!(
+            headers[1].1,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn test_get_canonical_query_sorts_and_encodes() {
+        // Arrange
+        let url = Url::parse("https://example.com/?b=2&a=x%20y&a=1").unwrap();
+
+        // Act, Assert
+        assert_eq!("a=1&a=x%20y&b=2", get_canonical_query(&url));
+    }
+
+    #[test]
+    fn test_parse_credentials_file() {
+        // Arrange
+        let content = "[default]\naws_access_key_id = A\naws_secret_access_key = B\n\n[minio]\naws_access_key_id=C\naws_secret_access_key=D\naws_session_token=E\n";
+
+        // Act
+        let credentials = parse_credentials_file(content, "minio").unwrap();
+
+        // Assert
+        assert_eq!(credentials.access_key_id, "C");
+        assert_eq!(credentials.secret_access_key, "D");
+        assert_eq!(credentials.session_token, Some(String::from("E")));
+    }
+}
@@ -1,13 +1,20 @@
 extern crate globwalk;
 
-use crate::Context;
+use crate::error::{self, JoatError};
+use crate::layers::{self, LayerKind};
+use crate::{redact, secrets, Context};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::env;
-use std::path::Path;
+use std::path::PathBuf;
 use tera::{Context as TeraContext, Error, Tera};
 
+fn register_functions(tera: &mut Tera) {
+    tera.register_function("secret", secrets::secret_template_function);
+    tera.register_filter("redact", redact::redact_filter);
+}
+
 fn get_tera_context(context: &Context) -> TeraContext {
     let mut tera_context = TeraContext::new();
     for (k, v) in context.iter() {
@@ -22,7 +29,10 @@ pub fn get_compiled_template_str_with_context(
 ) -> Result<String, Error> {
     let context = get_tera_context(raw_context);
 
-    let result = Tera::one_off(&template, context, false)?;
+    let mut tera = Tera::default();
+    tera.add_raw_template("one_off", &template)?;
+    register_functions(&mut tera);
+    let result = tera.render("one_off", context)?;
     return Ok(result);
 }
 
@@ -31,8 +41,9 @@ pub struct Template {
 }
 
 impl Template {
-    pub fn new(app_name: &str) -> Template {
-        let home_dir_path = dirs::home_dir().expect("Could not get home dir");
+    pub fn new(app_name: &str) -> error::Result<Template> {
+        let home_dir_path =
+            dirs::home_dir().ok_or(JoatError::Io(String::from("Could not find home dir")))?;
 
         // Add joat default templates
         let home_dir_str = home_dir_path
@@ -41,33 +52,43 @@ impl Template {
             .into_string()
             .unwrap();
         let joat_path_str = String::from(format!("{}/.joat.joat/templates/**", home_dir_str));
-        let mut tera = Tera::parse(joat_path_str.as_str()).expect("Could not start Tera");
+        let mut tera = Tera::parse(joat_path_str.as_str())?;
 
-        // Add templates from config folders
-        let current_path = env::current_dir().expect("Could not find current dir");
-        let mut ancestors = current_path.ancestors();
-        while let Some(path) = ancestors.next() {
-            let current_dir = path.to_str().expect("Could not convert path to string");
-            let config_base_path_str = format!("{}/.{}.joat/templates/", current_dir, app_name);
-            let config_path = Path::new(&config_base_path_str);
+        // Add templates from config folders, the loaded config layers first (the highest
+        // priority one wins) and then the ancestors of the current dir
+        let mut template_dirs: Vec<PathBuf> = layers::get_loaded_layers()
+            .iter()
+            .rev()
+            .filter(|l| l.kind != LayerKind::BuiltIn)
+            .map(|l| l.base_path.join("templates"))
+            .collect();
+        let current_path = env::current_dir()
+            .map_err(|e| JoatError::Io(format!("Could not find current dir: {}", e)))?;
+        for path in current_path.ancestors() {
+            let template_dir = path.join(format!(".{}.joat", app_name)).join("templates");
+            if !template_dirs.contains(&template_dir) {
+                template_dirs.push(template_dir);
+            }
+        }
+        for config_path in template_dirs {
             if config_path.exists() && config_path.is_dir() {
-                let templates_glob = format!("{}**/*.j2", config_base_path_str);
-                let tera_templates = Tera::parse(&templates_glob)
-                    .expect("Could not start tera with local templates");
-                tera.extend(&tera_templates).unwrap();
+                let templates_glob = format!("{}/**/*.j2", config_path.to_string_lossy());
+                let tera_templates = Tera::parse(&templates_glob)?;
+                tera.extend(&tera_templates)?;
             }
         }
 
-        tera.build_inheritance_chains().unwrap();
+        tera.build_inheritance_chains()?;
+        register_functions(&mut tera);
 
-        return Template { tera };
+        return Ok(Template { tera });
     }
 
     pub fn get_compiled_template_with_context<T>(
         self,
         template: String,
         context_hashes: HashMap<String, T>,
-    ) -> String
+    ) -> error::Result<String>
     where
         T: DeserializeOwned,
         T: Serialize,
@@ -77,14 +98,12 @@ impl Template {
             context.insert(&key, &value);
         }
 
-        let result = match self.tera.render(&template, context) {
-            Ok(s) => s,
-            Err(e) => {
-                println!("Could not render template {:?}", template);
-                println!("Error: {}", e);
-                ::std::process::exit(1);
-            }
-        };
-        return result;
+        self.tera.render(&template, context).map_err(|e| {
+            JoatError::Template(format!(
+                "Could not render template {:?}: {}",
+                template,
+                JoatError::from(e).message()
+            ))
+        })
     }
 }
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{self, JoatError};
+
+const TRUST_STORE_FILE: &str = "trusted.json";
+
+///
+/// Config files the user approved with `joat trust`, with the hash of the approved content.
+///
+#[derive(Default, Deserialize, Serialize)]
+struct TrustStore {
+    files: BTreeMap<String, String>,
+}
+
+pub enum TrustStatus {
+    Trusted,
+    Untrusted,
+    Changed,
+}
+
+fn get_trust_store_path() -> error::Result<PathBuf> {
+    let home_dir =
+        dirs::home_dir().ok_or(JoatError::Io(String::from("Could not find home dir")))?;
+    Ok(home_dir.join(".joat.joat").join(TRUST_STORE_FILE))
+}
+
+fn get_trust_store() -> error::Result<TrustStore> {
+    let store_path = get_trust_store_path()?;
+    match fs::read_to_string(&store_path) {
+        Ok(c) => serde_json::from_str(&c).map_err(|e| {
+            JoatError::Config(format!(
+                "Invalid trust store {:?}: {}, fix or delete it and trust the files again",
+                store_path, e
+            ))
+        }),
+        Err(_e) => Ok(TrustStore::default()),
+    }
+}
+
+fn get_content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn get_store_key(config_path: &Path) -> String {
+    let canonical_path = fs::canonicalize(config_path).unwrap_or(config_path.to_path_buf());
+    canonical_path.to_string_lossy().to_string()
+}
+
+fn get_status(store: &TrustStore, key: &str, content: &str) -> TrustStatus {
+    match store.files.get(key) {
+        Some(h) if *h == get_content_hash(content) => TrustStatus::Trusted,
+        Some(_) => TrustStatus::Changed,
+        None => TrustStatus::Untrusted,
+    }
+}
+
+///
+/// Only the project layers are checked, see layers::ConfigLayer::needs_trust.
+///
+pub fn get_trust_status(config_path: &Path, content: &str) -> error::Result<TrustStatus> {
+    Ok(get_status(
+        &get_trust_store()?,
+        &get_store_key(config_path),
+        content,
+    ))
+}
+
+pub fn trust_file(config_path: &Path) -> error::Result<()> {
+    let content = fs::read_to_string(config_path)
+        .map_err(|e| JoatError::Io(format!("Could not read {:?}: {}", config_path, e)))?;
+    let mut store = get_trust_store()?;
+    store
+        .files
+        .insert(get_store_key(config_path), get_content_hash(&content));
+    let store_content = serde_json::to_string_pretty(&store)
+        .map_err(|e| JoatError::Internal(format!("Could not serialize trust store: {}", e)))?;
+    let store_path = get_trust_store_path()?;
+    if let Some(store_dir) = store_path.parent() {
+        fs::create_dir_all(store_dir)
+            .map_err(|e| JoatError::Io(format!("Could not create {:?}: {}", store_dir, e)))?;
+    }
+    fs::write(&store_path, store_content)
+        .map_err(|e| JoatError::Io(format!("Unable to write trust store: {}", e)))?;
+    println!("Trusted {}", get_store_key(config_path));
+    Ok(())
+}
+
+///
+/// Config files joat would load from a directory: `.<app>.joat/<app>.yml` for any app.
+///
+pub fn get_config_files_in_dir(dir: &Path) -> Vec<PathBuf> {
+    let mut config_files = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_e) => return config_files,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !file_name.starts_with('.') || !file_name.ends_with(".joat") {
+            continue;
+        }
+        let app_name = &file_name[1..file_name.len() - ".joat".len()];
+        let config_path = entry.path().join(format!("{}.yml", app_name));
+        if config_path.exists() {
+            config_files.push(config_path);
+        }
+    }
+    config_files.sort();
+    config_files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_status() {
+        // Arrange
+        let mut store = TrustStore::default();
+        store.files.insert(
+            String::from("/p/.a.joat/a.yml"),
+            get_content_hash("name: a"),
+        );
+
+        // Act, Assert
+        match get_status(&store, "/p/.a.joat/a.yml", "name: a") {
+            TrustStatus::Trusted => (),
+            _ => panic!("Expected trusted"),
+        }
+        match get_status(&store, "/p/.a.joat/a.yml", "name: b") {
+            TrustStatus::Changed => (),
+            _ => panic!("Expected changed"),
+        }
+        match get_status(&store, "/q/.a.joat/a.yml", "name: a") {
+            TrustStatus::Untrusted => (),
+            _ => panic!("Expected untrusted"),
+        }
+    }
+}
@@ -1,49 +1,40 @@
+use crate::error::{self, JoatError};
+use crate::explain;
+use crate::layers::{self, ConfigLayer};
+use crate::lint;
+use crate::merge;
 use crate::template;
+use crate::trust::{self, TrustStatus};
+use globwalk::GlobWalkerBuilder;
 use log::debug;
 use serde_json::map::Map;
 use serde_json::value::Value;
 use serde_json::Number;
 use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
 use std::{env, fs};
 use yaml_rust::{Yaml, YamlLoader};
 
 use crate::Context;
 
-fn combine_yaml_scmd_arrays(overrider: Yaml, overriden: Yaml) -> Yaml {
-    let mut result = Vec::new();
-    let mut result_map = BTreeMap::new();
-    let overrider_vec = overrider
-        .into_vec()
-        .expect("Subcommands should be an array");
-    let overriden_vec = overriden
-        .into_vec()
-        .expect("Subcommands should be an array");
-    for value in overrider_vec {
-        let scmd_hash = value
-            .clone()
-            .into_hash()
-            .expect(&format!("Invalid subcommand {:?}", value));
-        let scmd_name = scmd_hash
-            .keys()
-            .nth(0)
-            .expect(&format!("Invalid subcommand name {:?}", scmd_hash));
-        result_map.insert(scmd_name.to_owned(), true);
-        result.push(value);
-    }
-    for v in overriden_vec {
-        let scmd_hash = v.as_hash().expect(&format!("Invalid subcommand {:?}", v));
-        let scmd_name = scmd_hash
-            .keys()
-            .nth(0)
-            .expect(&format!("Invalid subcommand name {:?}", scmd_hash));
-
-        if !result_map.contains_key(scmd_name) {
-            result.push(v);
-        }
-    }
-    return Yaml::Array(result);
+const INCLUDE_KEY: &str = "include";
+// Marks the auth, secrets and config groups added by joat, not the ones extensions define
+const BUILTIN_GROUP_KEY: &str = "builtin_group";
+const GLOB_CHARS: [char; 4] = ['*', '?', '[', '{'];
+// Args (name, long) read from the raw command line, see check_reserved_arg_names
+const RESERVED_ARGS: [(&str, &str); 2] = [("config", "config"), ("error_format", "error-format")];
+// Options subcommand groups pass down to their subcommands, see inherit_group_options
+const INHERITED_MAP_KEYS: [&str; 3] = ["vars", "headers", "query_params"];
+const INHERITED_KEYS: [&str; 5] = [
+    "base_endpoint",
+    "auth",
+    "netrc",
+    "timeout",
+    "scmd_config_base_path",
+];
+
+fn get_config_error(message: &str, yaml: &Yaml) -> JoatError {
+    JoatError::Config(format!("{} {:?}", message, yaml))
 }
 
 fn merge_btreemaps(
@@ -62,20 +53,8 @@ fn merge_btreemaps(
     return result;
 }
 
-fn merge_scmd_hash(overrider: &BTreeMap<Yaml, Yaml>, overriden: &BTreeMap<Yaml, Yaml>) -> Yaml {
-    let sub_key = Yaml::String(String::from("subcommands"));
-    let scmds = combine_yaml_scmd_arrays(overrider[&sub_key].clone(), overriden[&sub_key].clone());
-
-    let mut new_config = merge_btreemaps(overrider, overriden);
-    new_config.insert(sub_key, scmds);
-    return Yaml::Hash(new_config);
-}
-
 fn combine_scmd_yaml(overrider: &Yaml, overriden: &Yaml) -> Yaml {
-    match (overrider, overriden) {
-        (Yaml::Hash(r), Yaml::Hash(n)) => merge_scmd_hash(&r, &n),
-        _ => return overrider.clone(),
-    }
+    merge::merge_layers(overrider, overriden)
 }
 
 pub fn combine_hash_yaml(overrider: &Yaml, overriden: &Yaml) -> Yaml {
@@ -88,73 +67,416 @@ pub fn combine_hash_yaml(overrider: &Yaml, overriden: &Yaml) -> Yaml {
     return Yaml::Hash(combined);
 }
 
-fn add_subcommands_path(config: Yaml, path: &String) -> Yaml {
-    let mut config_bmap = get_imut_yaml_hash(config);
-    let scmd_yaml = config_bmap
-        .get_mut(&get_yaml_string("subcommands"))
-        .expect("No subcommands in config, wrong yml format");
-    let scmds = get_yaml_array(scmd_yaml);
+///
+/// Stamps the base path of the layer on the subcommands it defines a script or a path for, a
+/// layer only patching a subcommand (e.g. its timeout) keeps the base path of the defining one.
+///
+fn add_scmds_path(scmds_yaml: &mut Yaml, path: &str) -> error::Result<()> {
+    for scmd_yaml in get_yaml_array(merge::get_value_mut(scmds_yaml))?.iter_mut() {
+        for (_scmd_name, scmd_options_yaml) in get_yaml_hash(scmd_yaml)?.iter_mut() {
+            let scmd_options = get_yaml_hash(merge::get_value_mut(scmd_options_yaml))?;
+            if let Some(child_scmds) = scmd_options.get_mut(&get_yaml_string("subcommands")) {
+                add_scmds_path(child_scmds, path)?;
+            }
+            let defines_request = scmd_options.contains_key(&get_yaml_string("script"))
+                || scmd_options.contains_key(&get_yaml_string("path"));
+            if defines_request {
+                scmd_options.insert(
+                    get_yaml_string("scmd_config_base_path"),
+                    get_yaml_string(path),
+                );
+            }
+        }
+    }
+    Ok(())
+}
 
-    for scmd_yaml in scmds.iter_mut() {
-        let scmd = get_yaml_hash(scmd_yaml);
+fn add_subcommands_path(config: Yaml, path: &str) -> error::Result<Yaml> {
+    let mut config_bmap = get_imut_yaml_hash(config)?;
+    // Layers only setting options (e.g. a var or a header) have no subcommands
+    if let Some(scmd_yaml) = config_bmap.get_mut(&get_yaml_string("subcommands")) {
+        add_scmds_path(scmd_yaml, path)?;
+    }
+    Ok(Yaml::Hash(config_bmap))
+}
 
-        for (_scmd_name, scmd_options_yaml) in scmd.iter_mut() {
-            let scmd_options = get_yaml_hash(scmd_options_yaml);
-            let scmd_config_base_path = get_yaml_string("scmd_config_base_path");
-            let path_value = get_yaml_string(&path);
-            scmd_options.insert(scmd_config_base_path, path_value);
-        }
+fn load_config_file(
+    config_file_path: &str,
+    needs_trust: bool,
+    is_include: bool,
+) -> error::Result<Option<Yaml>> {
+    let local_config = fs::read_to_string(config_file_path)
+        .map_err(|e| JoatError::Io(format!("Could not read {}: {}", config_file_path, e)))?;
+    load_config_str(config_file_path, &local_config, needs_trust, is_include)
+}
+
+fn load_config_str(
+    config_file_path: &str,
+    local_config: &str,
+    needs_trust: bool,
+    is_include: bool,
+) -> error::Result<Option<Yaml>> {
+    // Scripts run arbitrary commands, don't load configs from a cloned repo until approved
+    let trust_status = match needs_trust {
+        true => trust::get_trust_status(Path::new(config_file_path), local_config)?,
+        false => TrustStatus::Trusted,
+    };
+    let trust_warning = match trust_status {
+        TrustStatus::Trusted => None,
+        TrustStatus::Untrusted => Some("is not trusted"),
+        TrustStatus::Changed => Some("changed since it was trusted"),
+    };
+    if let Some(w) = trust_warning {
+        eprintln!(
+            "WARN: Ignoring {} that {}, review it and run: joat trust {}",
+            config_file_path, w, config_file_path
+        );
+        return Ok(None);
     }
+    lint::check_config(config_file_path, local_config, is_include)?;
+    let local_yaml = merge::load_str(local_config)
+        .map_err(|e| JoatError::Config(format!("{}: {}", config_file_path, e)))?;
+    Ok(Some(local_yaml))
+}
 
-    Yaml::Hash(config_bmap.clone())
+///
+/// The value of an `include` item of a subcommands list, BadValue for regular subcommands.
+///
+/// Only a path or a list of paths is a directive, a subcommand can be named include too.
+///
+pub fn get_include_directive(scmd: &Yaml) -> &Yaml {
+    let include = match scmd.as_hash() {
+        Some(h) if h.len() == 1 => &scmd[INCLUDE_KEY],
+        _ => return &Yaml::BadValue,
+    };
+    match include {
+        Yaml::String(_) => include,
+        Yaml::Array(a) if a.iter().all(|p| p.as_str().is_some()) => include,
+        _ => &Yaml::BadValue,
+    }
 }
 
-fn get_yaml_from(config_file_path: String, base_path: String) -> Yaml {
-    let local_config =
-        fs::read_to_string(config_file_path.clone()).expect("Could not find configuration file");
-    let local_yaml =
-        &YamlLoader::load_from_str(&local_config).expect("failed to load YAML file")[0];
-    add_subcommands_path(local_yaml.clone(), &base_path)
+fn get_include_patterns(include: &Yaml) -> error::Result<Vec<String>> {
+    match include {
+        Yaml::String(s) => Ok(vec![s.clone()]),
+        Yaml::Array(patterns) => patterns
+            .iter()
+            .map(|p| {
+                p.as_str().map(String::from).ok_or(get_config_error(
+                    "Include patterns should be strings, found",
+                    p,
+                ))
+            })
+            .collect(),
+        _ => Err(get_config_error(
+            "include should be a path or a list of paths, found",
+            include,
+        )),
+    }
 }
 
-fn get_config_from(app_name: &String, base_dir: &String) -> Option<Yaml> {
-    let config_path = String::from(format!("{}/.{}.joat/", base_dir, app_name));
-    let local_path = String::from(format!("{}{}.yml", config_path, app_name));
-    if Path::new(&local_path).exists() {
-        let config = get_yaml_from(local_path, config_path);
-        return Some(config);
+///
+/// Splits an include pattern in the directory to walk and the glob to match in it, so
+/// `resources/*.yml` only walks `resources`.
+///
+fn split_include_pattern(pattern: &str) -> (PathBuf, Option<String>) {
+    let mut dir = PathBuf::new();
+    let mut glob_parts = Vec::new();
+    for component in Path::new(pattern).components() {
+        let part = component.as_os_str().to_string_lossy().to_string();
+        if glob_parts.is_empty() && !part.contains(&GLOB_CHARS[..]) {
+            dir.push(component);
+        } else {
+            glob_parts.push(part);
+        }
+    }
+    match glob_parts.is_empty() {
+        true => (dir, None),
+        false => (dir, Some(glob_parts.join("/"))),
     }
-    // To ease development
-    let alternative_path = String::from(format!("{}/{}.yml", base_dir, app_name));
-    if Path::new(&alternative_path).exists() {
-        let config = get_yaml_from(alternative_path, base_dir.to_string());
-        return Some(config);
+}
+
+///
+/// Files matched by an `include` directive, patterns are relative to the config base path and
+/// the files of a glob are sorted by path.
+///
+fn get_included_paths(base_path: &Path, include: &Yaml) -> error::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for pattern in get_include_patterns(include)? {
+        let (dir, glob) = split_include_pattern(&pattern);
+        let dir = base_path.join(dir);
+        let glob = match glob {
+            Some(g) => g,
+            None if dir.is_file() => {
+                paths.push(dir);
+                continue;
+            }
+            None => {
+                return Err(JoatError::Config(format!(
+                    "Included file {} not found",
+                    dir.to_string_lossy()
+                )))
+            }
+        };
+        let mut walker = GlobWalkerBuilder::new(&dir, format!("/{}", glob));
+        if !glob.contains("**") {
+            walker = walker.max_depth(glob.split('/').count());
+        }
+        let walker = walker.build().map_err(|e| {
+            JoatError::Config(format!("Invalid include pattern {}: {}", pattern, e))
+        })?;
+        let mut matches: Vec<PathBuf> = walker
+            .filter_map(|e| e.ok())
+            .map(|e| e.path().to_path_buf())
+            .filter(|p| p.is_file())
+            .collect();
+        matches.sort();
+        paths.append(&mut matches);
     }
-    return None;
+    Ok(paths)
 }
 
-fn get_config_vec_from_path(app_name: &String, path: &PathBuf) -> Vec<Yaml> {
-    let mut ancestors = path.ancestors();
-    let mut config_files = Vec::new();
-    while let Some(path) = ancestors.next() {
-        let current_dir = path.to_str().expect("Could not convert path to string");
-        debug!("Searching config file in {:?}", current_dir);
-        match get_config_from(app_name, &current_dir.to_string()) {
-            Some(c) => config_files.push(c),
+fn get_included_yamls(
+    include: &Yaml,
+    base_path: &Path,
+    needs_trust: bool,
+    stack: &mut Vec<PathBuf>,
+) -> error::Result<Vec<Yaml>> {
+    let mut yamls = Vec::new();
+    for path in get_included_paths(base_path, include)? {
+        let canonical_path = fs::canonicalize(&path).unwrap_or(path.clone());
+        if stack.contains(&canonical_path) {
+            return Err(JoatError::Config(format!(
+                "{} is included recursively",
+                path.to_string_lossy()
+            )));
+        }
+        let included = match load_config_file(&path.to_string_lossy(), needs_trust, true)? {
+            Some(y) => y,
             None => continue,
+        };
+        stack.push(canonical_path);
+        let included = expand_includes(included, base_path, needs_trust, stack)?;
+        explain::record_included(&included, &path);
+        yamls.push(included);
+        stack.pop();
+    }
+    Ok(yamls)
+}
+
+fn get_included_scmds(included: Yaml) -> error::Result<Vec<Yaml>> {
+    match included {
+        Yaml::Array(scmds) => Ok(scmds),
+        Yaml::Hash(mut h) if h.len() == 1 => h
+            .remove(&get_yaml_string("subcommands"))
+            .and_then(|s| s.into_vec())
+            .ok_or(JoatError::Config(String::from(
+                "Files included in subcommands should only have subcommands",
+            ))),
+        Yaml::Null => Ok(Vec::new()),
+        _ => Err(JoatError::Config(String::from(
+            "Files included in subcommands should only have subcommands",
+        ))),
+    }
+}
+
+fn expand_scmd_includes(
+    scmds: Vec<Yaml>,
+    base_path: &Path,
+    needs_trust: bool,
+    stack: &mut Vec<PathBuf>,
+) -> error::Result<Vec<Yaml>> {
+    let mut result = Vec::new();
+    for scmd in scmds {
+        let include = get_include_directive(&scmd);
+        if include.is_badvalue() {
+            result.push(expand_nested_scmd_includes(
+                scmd,
+                base_path,
+                needs_trust,
+                stack,
+            )?);
+            continue;
         }
+        for included in get_included_yamls(include, base_path, needs_trust, stack)? {
+            result.append(&mut get_included_scmds(included)?);
+        }
+    }
+    Ok(result)
+}
+
+fn expand_scmds_yaml_includes(
+    scmds_yaml: &mut Yaml,
+    base_path: &Path,
+    needs_trust: bool,
+    stack: &mut Vec<PathBuf>,
+) -> error::Result<()> {
+    if let Yaml::Array(scmds) = merge::get_value_mut(scmds_yaml) {
+        let expanded_scmds = expand_scmd_includes(scmds.clone(), base_path, needs_trust, stack)?;
+        *scmds = expanded_scmds;
     }
-    config_files
+    Ok(())
 }
 
-fn get_config_vec(app_name: &String) -> Vec<Yaml> {
-    let current_path = env::current_dir().expect("Could not find current dir");
-    let config_files = get_config_vec_from_path(app_name, &current_path);
-    if config_files.is_empty() {
-        let home_dir = dirs::home_dir().expect("No home folder");
-        return get_config_vec_from_path(app_name, &home_dir);
+fn expand_nested_scmd_includes(
+    mut scmd: Yaml,
+    base_path: &Path,
+    needs_trust: bool,
+    stack: &mut Vec<PathBuf>,
+) -> error::Result<Yaml> {
+    let sub_key = get_yaml_string("subcommands");
+    if let Ok(scmd_bmap) = get_yaml_hash(&mut scmd) {
+        for (_name, options) in scmd_bmap.iter_mut() {
+            if let Yaml::Hash(options_bmap) = merge::get_value_mut(options) {
+                if let Some(scmds_yaml) = options_bmap.get_mut(&sub_key) {
+                    expand_scmds_yaml_includes(scmds_yaml, base_path, needs_trust, stack)?;
+                }
+            }
+        }
     }
-    config_files
+    Ok(scmd)
+}
+
+fn merge_included_config(config: Yaml, included: Yaml) -> error::Result<Yaml> {
+    let sub_key = get_yaml_string("subcommands");
+    let mut config_bmap = get_imut_yaml_hash(config)?;
+    let included_bmap = match included {
+        Yaml::Array(scmds) => {
+            let mut bmap = BTreeMap::new();
+            bmap.insert(sub_key.clone(), Yaml::Array(scmds));
+            bmap
+        }
+        Yaml::Null => return Ok(Yaml::Hash(config_bmap)),
+        included => get_imut_yaml_hash(included)?,
+    };
+    let mut scmds = match config_bmap.remove(&sub_key) {
+        Some(s) => s.into_vec().ok_or(JoatError::Config(String::from(
+            "Subcommands should be a list",
+        )))?,
+        None => Vec::new(),
+    };
+    if let Some(s) = included_bmap.get(&sub_key) {
+        let mut included_scmds = s.clone().into_vec().ok_or(JoatError::Config(String::from(
+            "Subcommands should be a list",
+        )))?;
+        scmds.append(&mut included_scmds);
+    }
+    let mut merged = merge_btreemaps(&config_bmap, &included_bmap);
+    merged.insert(sub_key, Yaml::Array(scmds));
+    Ok(Yaml::Hash(merged))
+}
+
+///
+/// Replaces the `include` directives with the files they match. Top level includes are merged
+/// into the config (keys of the including file win, subcommands are appended) and includes in
+/// a subcommands list are replaced by the included subcommands.
+///
+fn expand_includes(
+    config: Yaml,
+    base_path: &Path,
+    needs_trust: bool,
+    stack: &mut Vec<PathBuf>,
+) -> error::Result<Yaml> {
+    let mut config_bmap = match config {
+        Yaml::Hash(h) => h,
+        Yaml::Array(scmds) => {
+            return Ok(Yaml::Array(expand_scmd_includes(
+                scmds,
+                base_path,
+                needs_trust,
+                stack,
+            )?))
+        }
+        config => return Ok(config),
+    };
+    let include = config_bmap.remove(&get_yaml_string(INCLUDE_KEY));
+    if let Some(scmds_yaml) = config_bmap.get_mut(&get_yaml_string("subcommands")) {
+        expand_scmds_yaml_includes(scmds_yaml, base_path, needs_trust, stack)?;
+    }
+    let mut config = Yaml::Hash(config_bmap);
+    if let Some(include) = include {
+        for included in get_included_yamls(&include, base_path, needs_trust, stack)? {
+            config = merge_included_config(config, included)?;
+        }
+    }
+    Ok(config)
+}
+
+fn get_yaml_from(layer: &ConfigLayer) -> error::Result<Option<Yaml>> {
+    let config_file_path = layer.path.to_string_lossy().to_string();
+    let local_yaml = match layer.get_built_in_content() {
+        Some(c) => load_config_str(&config_file_path, c, false, false)?,
+        None => load_config_file(&config_file_path, layer.needs_trust(), false)?,
+    };
+    let local_yaml = match local_yaml {
+        Some(y) => y,
+        None => return Ok(None),
+    };
+    let mut stack = vec![fs::canonicalize(&layer.path).unwrap_or(layer.path.clone())];
+    let local_yaml = expand_includes(
+        local_yaml,
+        &layer.base_path,
+        layer.needs_trust(),
+        &mut stack,
+    )?;
+    explain::record_layer(&local_yaml, &layer.path);
+    // Kept with a trailing slash, templates use it as a prefix
+    let base_path = format!("{}/", layer.base_path.to_string_lossy());
+    add_subcommands_path(local_yaml, &base_path).map(Some)
+}
+
+fn collect_scmd_include_directives<'a>(scmds: &'a Yaml, includes: &mut Vec<&'a Yaml>) {
+    for scmd in scmds.as_vec().map(|v| v.iter()).into_iter().flatten() {
+        let include = get_include_directive(scmd);
+        if !include.is_badvalue() {
+            includes.push(include);
+        }
+        for (_name, options) in scmd.as_hash().map(|h| h.iter()).into_iter().flatten() {
+            collect_scmd_include_directives(&options["subcommands"], includes);
+        }
+    }
+}
+
+fn collect_include_directives<'a>(config: &'a Yaml, includes: &mut Vec<&'a Yaml>) {
+    if config.as_vec().is_some() {
+        return collect_scmd_include_directives(config, includes);
+    }
+    if !config[INCLUDE_KEY].is_badvalue() {
+        includes.push(&config[INCLUDE_KEY]);
+    }
+    collect_scmd_include_directives(&config["subcommands"], includes);
+}
+
+///
+/// Files a config file includes, recursively, for `joat lint` and `joat trust`.
+///
+pub fn get_included_files(config_file_path: &Path) -> error::Result<Vec<PathBuf>> {
+    let base_path = config_file_path.parent().unwrap_or(Path::new("."));
+    let mut included_files: Vec<PathBuf> = Vec::new();
+    let mut pending = vec![config_file_path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let yaml = match fs::read_to_string(&path)
+            .ok()
+            .and_then(|c| YamlLoader::load_from_str(&c).ok())
+            .and_then(|docs| docs.into_iter().next())
+        {
+            Some(y) => y,
+            None => continue,
+        };
+        let mut includes = Vec::new();
+        collect_include_directives(&yaml, &mut includes);
+        for include in includes {
+            for included_path in get_included_paths(base_path, include)? {
+                let is_new =
+                    included_path != config_file_path && !included_files.contains(&included_path);
+                if is_new {
+                    included_files.push(included_path.clone());
+                    pending.push(included_path);
+                }
+            }
+        }
+    }
+    Ok(included_files)
 }
 
 fn get_yaml_string(rust_str: &str) -> Yaml {
@@ -205,38 +527,32 @@ fn get_arg_yaml(name: &str, options: BTreeMap<Yaml, Yaml>) -> Yaml {
     Yaml::Hash(args)
 }
 
-fn get_yaml_hash(yaml: &mut Yaml) -> &mut BTreeMap<Yaml, Yaml> {
+fn get_yaml_hash(yaml: &mut Yaml) -> error::Result<&mut BTreeMap<Yaml, Yaml>> {
     match *yaml {
-        Yaml::Hash(ref mut h) => h,
-        _ => {
-            panic!("Failed to convert {:?} to mutable Yaml::Hash", yaml);
-        }
+        Yaml::Hash(ref mut h) => Ok(h),
+        _ => Err(get_config_error("Expected a map, found", yaml)),
     }
 }
 
-fn get_imut_yaml_hash(yaml: Yaml) -> BTreeMap<Yaml, Yaml> {
+fn get_imut_yaml_hash(yaml: Yaml) -> error::Result<BTreeMap<Yaml, Yaml>> {
     match yaml {
-        Yaml::Hash(h) => h,
-        _ => {
-            panic!("Failed to convert {:?} to immutable Yaml::Hash", yaml);
-        }
+        Yaml::Hash(h) => Ok(h),
+        _ => Err(get_config_error("Expected a map, found", &yaml)),
     }
 }
 
-fn get_yaml_array(yaml: &mut Yaml) -> &mut Vec<Yaml> {
+fn get_yaml_array(yaml: &mut Yaml) -> error::Result<&mut Vec<Yaml>> {
     match yaml {
-        Yaml::Array(a) => a,
-        _ => {
-            panic!("Failed to convert {:?} to mutable Yaml::Array", yaml);
-        }
+        Yaml::Array(a) => Ok(a),
+        _ => Err(get_config_error("Expected a list, found", yaml)),
     }
 }
 
-fn check_existing_options(args: Vec<Yaml>, option: &BTreeMap<Yaml, Yaml>) {
+fn check_existing_options(args: Vec<Yaml>, option: &BTreeMap<Yaml, Yaml>) -> error::Result<()> {
     for arg in args {
-        let arg_hash = get_imut_yaml_hash(arg);
+        let arg_hash = get_imut_yaml_hash(arg)?;
         for (_arg_name, arg_options_yaml) in arg_hash {
-            let arg_options = get_imut_yaml_hash(arg_options_yaml);
+            let arg_options = get_imut_yaml_hash(arg_options_yaml)?;
 
             let short = get_yaml_string("short");
             match option.get(&short) {
@@ -244,8 +560,10 @@ fn check_existing_options(args: Vec<Yaml>, option: &BTreeMap<Yaml, Yaml>) {
                     match arg_options.get(&short) {
                         Some(short_value) => {
                             if short_value == o {
-                                println!("'short: {:?}' is reserved, check your yml", o);
-                                ::std::process::exit(1);
+                                return Err(JoatError::Config(format!(
+                                    "'short: {}' is reserved, check your yml",
+                                    get_key_str(o)
+                                )));
                             }
                         }
                         None => (),
@@ -260,8 +578,10 @@ fn check_existing_options(args: Vec<Yaml>, option: &BTreeMap<Yaml, Yaml>) {
                     match arg_options.get(&long) {
                         Some(long_value) => {
                             if long_value == o {
-                                println!("'long: {:?}' is reserved, check your yml", o);
-                                ::std::process::exit(1);
+                                return Err(JoatError::Config(format!(
+                                    "'long: {}' is reserved, check your yml",
+                                    get_key_str(o)
+                                )));
                             }
                         }
                         None => (),
@@ -271,6 +591,7 @@ fn check_existing_options(args: Vec<Yaml>, option: &BTreeMap<Yaml, Yaml>) {
             };
         }
     }
+    Ok(())
 }
 
 fn add_auto_complete_cmd() -> Yaml {
@@ -303,128 +624,516 @@ fn add_auto_complete_cmd() -> Yaml {
     Yaml::Hash(auto_complete_cmd)
 }
 
-fn add_default_options(config: Yaml) -> Yaml {
-    let mut config_bmap = get_imut_yaml_hash(config);
-    let scmd_yaml = config_bmap
-        .get_mut(&get_yaml_string("subcommands"))
-        .expect("No subcommands in config, wrong yml format");
-    let scmds = get_yaml_array(scmd_yaml);
+fn get_builtin_scmd_yaml(name: &str, about: &str) -> Yaml {
+    get_builtin_scmd_with_args_yaml(name, about, Vec::new())
+}
+
+fn get_builtin_positional_arg(name: &str, help: &str, required: bool, index: i64) -> Yaml {
+    let mut arg = BTreeMap::new();
+    let mut arg_options = BTreeMap::new();
+    arg_options.insert(get_yaml_string("help"), get_yaml_string(help));
+    arg_options.insert(get_yaml_string("required"), Yaml::Boolean(required));
+    arg_options.insert(get_yaml_string("index"), Yaml::Integer(index));
+    arg.insert(get_yaml_string(name), Yaml::Hash(arg_options));
+    Yaml::Hash(arg)
+}
+
+fn get_builtin_scmd_with_args_yaml(name: &str, about: &str, args: Vec<Yaml>) -> Yaml {
+    let mut scmd = BTreeMap::new();
+    let mut scmd_options = BTreeMap::new();
+    scmd_options.insert(get_yaml_string("about"), get_yaml_string(about));
+    if !args.is_empty() {
+        scmd_options.insert(get_yaml_string("args"), Yaml::Array(args));
+    }
+    scmd.insert(get_yaml_string(name), Yaml::Hash(scmd_options));
+    Yaml::Hash(scmd)
+}
+
+fn add_auth_cmd() -> Yaml {
+    let mut auth_cmd = BTreeMap::new();
+    let mut auth_cmd_options = BTreeMap::new();
+    let about_description = get_yaml_string("Manage the oauth login");
+
+    let scmds = vec![
+        get_builtin_scmd_yaml("login", "Log in, replacing the current token"),
+        get_builtin_scmd_yaml("logout", "Remove the stored token"),
+        get_builtin_scmd_yaml("status", "Show the token expiry and scopes"),
+        get_builtin_scmd_yaml("token", "Print the current access token"),
+    ];
+    let settings = vec![get_yaml_string("SubcommandRequiredElseHelp")];
+
+    auth_cmd_options.insert(get_yaml_string("about"), about_description);
+    auth_cmd_options.insert(get_yaml_string("settings"), Yaml::Array(settings));
+    auth_cmd_options.insert(get_yaml_string("subcommands"), Yaml::Array(scmds));
+    auth_cmd_options.insert(get_yaml_string(BUILTIN_GROUP_KEY), Yaml::Boolean(true));
+    auth_cmd.insert(get_yaml_string("auth"), Yaml::Hash(auth_cmd_options));
+    Yaml::Hash(auth_cmd)
+}
+
+fn add_secrets_cmd() -> Yaml {
+    let mut secrets_cmd = BTreeMap::new();
+    let mut secrets_cmd_options = BTreeMap::new();
+    let about_description = get_yaml_string("Manage the encrypted secret store");
+
+    let name_arg = || get_builtin_positional_arg("NAME", "Name of the secret", true, 1);
+    let value_arg =
+        get_builtin_positional_arg("VALUE", "Secret value, prompted when missing", false, 2);
+    let scmds = vec![
+        get_builtin_scmd_with_args_yaml("set", "Store a secret", vec![name_arg(), value_arg]),
+        get_builtin_scmd_with_args_yaml("get", "Print a secret", vec![name_arg()]),
+        get_builtin_scmd_yaml("list", "List the secret names"),
+        get_builtin_scmd_with_args_yaml("rm", "Remove a secret", vec![name_arg()]),
+    ];
+    let settings = vec![get_yaml_string("SubcommandRequiredElseHelp")];
+
+    secrets_cmd_options.insert(get_yaml_string("about"), about_description);
+    secrets_cmd_options.insert(get_yaml_string("settings"), Yaml::Array(settings));
+    secrets_cmd_options.insert(get_yaml_string("subcommands"), Yaml::Array(scmds));
+    secrets_cmd_options.insert(get_yaml_string(BUILTIN_GROUP_KEY), Yaml::Boolean(true));
+    secrets_cmd.insert(get_yaml_string("secrets"), Yaml::Hash(secrets_cmd_options));
+    Yaml::Hash(secrets_cmd)
+}
+
+fn add_config_cmd() -> Yaml {
+    let mut config_cmd = BTreeMap::new();
+    let mut config_cmd_options = BTreeMap::new();
+    let about_description = get_yaml_string("Inspect the loaded config");
+
+    let mut scmd_arg = BTreeMap::new();
+    scmd_arg.insert(
+        get_yaml_string("help"),
+        get_yaml_string("Subcommand to explain, e.g. `issue show` for a subcommand of a group"),
+    );
+    scmd_arg.insert(get_yaml_string("multiple"), Yaml::Boolean(true));
+    scmd_arg.insert(get_yaml_string("index"), Yaml::Integer(1));
+    let mut resolved_arg = BTreeMap::new();
+    resolved_arg.insert(get_yaml_string("long"), get_yaml_string("resolved"));
+    resolved_arg.insert(
+        get_yaml_string("help"),
+        get_yaml_string("Render the templates with the current context"),
+    );
+    let scmds = vec![
+        get_builtin_scmd_yaml(
+            "layers",
+            "List the loaded config files, from the lowest to the highest priority",
+        ),
+        get_builtin_scmd_with_args_yaml(
+            "explain",
+            "Print the merged config with the file each value comes from",
+            vec![
+                get_arg_yaml("SUBCOMMAND", scmd_arg),
+                get_arg_yaml("resolved", resolved_arg),
+            ],
+        ),
+    ];
+    let settings = vec![get_yaml_string("SubcommandRequiredElseHelp")];
+
+    config_cmd_options.insert(get_yaml_string("about"), about_description);
+    config_cmd_options.insert(get_yaml_string("settings"), Yaml::Array(settings));
+    config_cmd_options.insert(get_yaml_string("subcommands"), Yaml::Array(scmds));
+    config_cmd_options.insert(get_yaml_string(BUILTIN_GROUP_KEY), Yaml::Boolean(true));
+    config_cmd.insert(get_yaml_string("config"), Yaml::Hash(config_cmd_options));
+    Yaml::Hash(config_cmd)
+}
+
+fn get_config_arg_option() -> BTreeMap<Yaml, Yaml> {
+    let mut config_option = BTreeMap::new();
+    config_option.insert(get_yaml_string("long"), get_yaml_string("config"));
+    config_option.insert(get_yaml_string("value_name"), get_yaml_string("FILE"));
+    config_option.insert(
+        get_yaml_string("help"),
+        get_yaml_string("Merge a config file on top of the discovered ones"),
+    );
+    // Not global, it's only read before the first subcommand name
+    config_option.insert(get_yaml_string("takes_value"), Yaml::Boolean(true));
+    config_option
+}
+
+fn get_profile_arg_option() -> BTreeMap<Yaml, Yaml> {
+    let mut profile_option = BTreeMap::new();
+    profile_option.insert(get_yaml_string("long"), get_yaml_string("profile"));
+    profile_option.insert(get_yaml_string("value_name"), get_yaml_string("PROFILE"));
+    profile_option.insert(
+        get_yaml_string("help"),
+        get_yaml_string("Use one of the configured profiles"),
+    );
+    profile_option.insert(get_yaml_string("takes_value"), Yaml::Boolean(true));
+    profile_option.insert(get_yaml_string("global"), Yaml::Boolean(true));
+    profile_option
+}
+
+fn get_error_format_arg_option() -> BTreeMap<Yaml, Yaml> {
+    let mut error_format_option = BTreeMap::new();
+    error_format_option.insert(get_yaml_string("long"), get_yaml_string("error-format"));
+    error_format_option.insert(get_yaml_string("value_name"), get_yaml_string("FORMAT"));
+    error_format_option.insert(
+        get_yaml_string("help"),
+        get_yaml_string("Print errors as text or json"),
+    );
+    error_format_option.insert(
+        get_yaml_string("possible_values"),
+        Yaml::Array(vec![get_yaml_string("text"), get_yaml_string("json")]),
+    );
+    error_format_option.insert(get_yaml_string("takes_value"), Yaml::Boolean(true));
+    error_format_option.insert(get_yaml_string("global"), Yaml::Boolean(true));
+    error_format_option
+}
+
+fn add_global_arg(
+    config_bmap: &mut BTreeMap<Yaml, Yaml>,
+    name: &str,
+    option: BTreeMap<Yaml, Yaml>,
+) -> error::Result<()> {
+    let args_yaml = config_bmap
+        .entry(get_yaml_string("args"))
+        .or_insert(Yaml::Array(Vec::new()));
+    let args = get_yaml_array(args_yaml)?;
+    check_existing_options(args.clone(), &option)?;
+    args.push(get_arg_yaml(name, option));
+    Ok(())
+}
+
+fn add_reserved_options(scmd_options: &mut BTreeMap<Yaml, Yaml>) -> error::Result<()> {
+    let args_yaml = get_yaml_string("args");
+    if !scmd_options.contains_key(&args_yaml) {
+        // If no arguments present add args key
+        let args_vec: Vec<Yaml> = Vec::new();
+        let args = Yaml::Array(args_vec);
+        let iargs_yaml = get_yaml_string("args");
+        scmd_options.insert(iargs_yaml, args);
+    }
+    let scmd_options_clone = scmd_options.clone();
+    let args_opt = scmd_options.get_mut(&args_yaml).unwrap();
+    let args = get_yaml_array(args_opt)?;
+
+    let script_yaml = get_yaml_string("script");
+    if !scmd_options_clone.contains_key(&script_yaml) {
+        // Only non script subcommands get the template option
+        let template_option = get_template_arg_option();
+        check_existing_options(args.clone(), &template_option)?;
+        args.push(get_arg_yaml("template", template_option));
+    }
+
+    let quiet_option = get_quiet_arg_option();
+    check_existing_options(args.clone(), &quiet_option)?;
+    args.push(get_arg_yaml("quiet", quiet_option));
+
+    let raw_option = get_raw_arg_option();
+    check_existing_options(args.clone(), &raw_option)?;
+    args.push(get_arg_yaml("raw_response", raw_option));
+    Ok(())
+}
+
+fn get_arg_name(arg: &Yaml) -> Option<&Yaml> {
+    arg.as_hash().and_then(|h| h.keys().next())
+}
+
+///
+/// Args read from the raw command line before clap parses it, an extension arg with the same
+/// name or long option at any level would be taken by joat.
+///
+fn check_reserved_arg_names(args_yaml: &Yaml) -> error::Result<()> {
+    for arg in args_yaml.as_vec().map_or(&[][..], |a| &a[..]) {
+        let name = get_arg_name(arg)
+            .and_then(|n| n.as_str())
+            .unwrap_or_default();
+        let long = arg[name]["long"].as_str().unwrap_or_default();
+        if let Some((reserved_name, reserved_long)) =
+            RESERVED_ARGS.iter().find(|(n, l)| *n == name || *l == long)
+        {
+            return Err(JoatError::Config(format!(
+                "The arg `{}` is reserved (`--{}` is read by joat), rename it in your yml",
+                reserved_name, reserved_long
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn is_arg_defined(args: &[Yaml], arg: &Yaml) -> bool {
+    let name = match get_arg_name(arg) {
+        Some(n) => n,
+        None => return false,
+    };
+    let options = &arg[name.as_str().unwrap_or_default()];
+    args.iter().any(|a| match get_arg_name(a) {
+        Some(n) => {
+            let a_options = &a[n.as_str().unwrap_or_default()];
+            let same_short =
+                !options["short"].is_badvalue() && options["short"] == a_options["short"];
+            let same_long = !options["long"].is_badvalue() && options["long"] == a_options["long"];
+            n == name || same_short || same_long
+        }
+        None => false,
+    })
+}
 
+///
+/// Passes the options of a subcommand group down to one of its subcommands. Maps are merged,
+/// other keys are only set when the subcommand doesn't have them, and the group flags and
+/// options are added to its args (positional args stay in the group).
+///
+fn inherit_group_options(
+    group_options: &BTreeMap<Yaml, Yaml>,
+    scmd_options: &mut BTreeMap<Yaml, Yaml>,
+) -> error::Result<()> {
+    for key in INHERITED_MAP_KEYS.iter() {
+        let key = get_yaml_string(key);
+        if let Some(group_value) = group_options.get(&key) {
+            let scmd_value = scmd_options.get(&key).unwrap_or(&Yaml::BadValue);
+            let merged = combine_hash_yaml(scmd_value, group_value);
+            scmd_options.insert(key, merged);
+        }
+    }
+    for key in INHERITED_KEYS.iter() {
+        let key = get_yaml_string(key);
+        match group_options.get(&key) {
+            Some(v) if !scmd_options.contains_key(&key) => {
+                scmd_options.insert(key, v.clone());
+            }
+            _ => (),
+        }
+    }
+    let args_key = get_yaml_string("args");
+    let group_args = match group_options.get(&args_key).and_then(|a| a.as_vec()) {
+        Some(a) => a.clone(),
+        None => return Ok(()),
+    };
+    let args_yaml = scmd_options
+        .entry(args_key)
+        .or_insert(Yaml::Array(Vec::new()));
+    let args = get_yaml_array(args_yaml)?;
+    let mut inherited_args = Vec::new();
+    for arg in group_args {
+        let is_positional = match get_arg_name(&arg).and_then(|n| n.as_str()) {
+            Some(n) => !arg[n]["index"].is_badvalue(),
+            None => true,
+        };
+        if !is_positional && !is_arg_defined(args, &arg) {
+            inherited_args.push(arg);
+        }
+    }
+    inherited_args.append(args);
+    *args = inherited_args;
+    Ok(())
+}
+
+fn add_subcommand_required_setting(scmd_options: &mut BTreeMap<Yaml, Yaml>) -> error::Result<()> {
+    let setting = get_yaml_string("SubcommandRequiredElseHelp");
+    let settings_yaml = scmd_options
+        .entry(get_yaml_string("settings"))
+        .or_insert(Yaml::Array(Vec::new()));
+    let settings = get_yaml_array(settings_yaml)?;
+    if !settings.contains(&setting) {
+        settings.push(setting);
+    }
+    Ok(())
+}
+
+///
+/// Adds joat's options to the subcommands, walking down the subcommand groups.
+///
+/// Groups without a `path` or `script` only dispatch to their subcommands, they require one and
+/// don't get the options.
+///
+fn add_scmds_default_options(
+    scmds: &mut [Yaml],
+    group_options: Option<&BTreeMap<Yaml, Yaml>>,
+) -> error::Result<()> {
+    let sub_key = get_yaml_string("subcommands");
     for scmd_yaml in scmds.iter_mut() {
-        let scmd = get_yaml_hash(scmd_yaml);
+        let scmd = get_yaml_hash(scmd_yaml)?;
 
         for (_scmd_name, scmd_options_yaml) in scmd.iter_mut() {
-            let scmd_options = get_yaml_hash(scmd_options_yaml);
-            let args_yaml = get_yaml_string("args");
-            if !scmd_options.contains_key(&args_yaml) {
-                // If no arguments present add args key
-                let args_vec: Vec<Yaml> = Vec::new();
-                let args = Yaml::Array(args_vec);
-                let iargs_yaml = get_yaml_string("args");
-                scmd_options.insert(iargs_yaml, args);
+            check_reserved_arg_names(&scmd_options_yaml["args"])?;
+            let scmd_options = get_yaml_hash(scmd_options_yaml)?;
+            if let Some(g) = group_options {
+                inherit_group_options(g, scmd_options)?;
             }
-            let scmd_options_clone = scmd_options.clone();
-            let args_opt = scmd_options.get_mut(&args_yaml).unwrap();
-            let args = get_yaml_array(args_opt);
-
-            let script_yaml = get_yaml_string("script");
-            if !scmd_options_clone.contains_key(&script_yaml) {
-                // Only non script subcommands get the template option
-                let template_option = get_template_arg_option();
-                check_existing_options(args.clone(), &template_option);
-                args.push(get_arg_yaml("template", template_option));
+            if scmd_options.contains_key(&sub_key) {
+                let options = scmd_options.clone();
+                let child_scmds = get_yaml_array(scmd_options.get_mut(&sub_key).unwrap())?;
+                add_scmds_default_options(child_scmds, Some(&options))?;
+                let is_runnable = options.contains_key(&get_yaml_string("path"))
+                    || options.contains_key(&get_yaml_string("script"));
+                if !is_runnable {
+                    add_subcommand_required_setting(scmd_options)?;
+                    continue;
+                }
             }
+            add_reserved_options(scmd_options)?;
+        }
+    }
+    Ok(())
+}
 
-            let quiet_option = get_quiet_arg_option();
-            check_existing_options(args.clone(), &quiet_option);
-            args.push(get_arg_yaml("quiet", quiet_option));
+///
+/// Whether the subcommand is one of the groups added by add_default_options, which dispatch
+/// their subcommands themselves.
+///
+pub fn is_builtin_group(scmd: &Yaml) -> bool {
+    scmd[BUILTIN_GROUP_KEY].as_bool() == Some(true)
+}
 
-            let raw_option = get_raw_arg_option();
-            check_existing_options(args.clone(), &raw_option);
-            args.push(get_arg_yaml("raw_response", raw_option));
-        }
+fn has_subcommand(scmds: &Vec<Yaml>, name: &str) -> bool {
+    scmds.iter().any(|s| !s[name].is_badvalue())
+}
+
+fn add_default_options(config: Yaml) -> error::Result<Yaml> {
+    // A profile can bring its own oauth config
+    let has_oauth = !config["oauth"].is_badvalue()
+        || config["profiles"]
+            .as_hash()
+            .map_or(false, |p| p.values().any(|v| !v["oauth"].is_badvalue()));
+    let has_profiles = !config["profiles"].is_badvalue();
+    check_reserved_arg_names(&config["args"])?;
+    let mut config_bmap = get_imut_yaml_hash(config)?;
+    if has_profiles {
+        add_global_arg(&mut config_bmap, "profile", get_profile_arg_option())?;
     }
+    // Read before clap parses the args, see error::init, declared so clap accepts it
+    add_global_arg(
+        &mut config_bmap,
+        "error_format",
+        get_error_format_arg_option(),
+    )?;
+    // Read before clap parses the args too, before the subcommand, see layers::get_explicit_config
+    add_global_arg(&mut config_bmap, "config", get_config_arg_option())?;
+    // Layers can leave them out, the merged config needs them
+    let scmd_yaml =
+        config_bmap
+            .get_mut(&get_yaml_string("subcommands"))
+            .ok_or(JoatError::Config(String::from(
+                "No subcommands in the merged config, none of the config layers defines them",
+            )))?;
+    let scmds = get_yaml_array(scmd_yaml)?;
+    add_scmds_default_options(scmds, None)?;
     let auto_complete_cmd = add_auto_complete_cmd();
     scmds.push(auto_complete_cmd);
+    // Extensions defining their own auth, secrets or config subcommand keep it
+    if has_oauth && !has_subcommand(scmds, "auth") {
+        scmds.push(add_auth_cmd());
+    }
+    if !has_subcommand(scmds, "secrets") {
+        scmds.push(add_secrets_cmd());
+    }
+    if !has_subcommand(scmds, "config") {
+        scmds.push(add_config_cmd());
+    }
 
-    Yaml::Hash(config_bmap.clone())
+    Ok(Yaml::Hash(config_bmap.clone()))
 }
 
-fn override_version(app_name: &String, config: Yaml) -> Yaml {
+fn override_version(app_name: &String, config: Yaml) -> error::Result<Yaml> {
     let version;
+    let app_version = config["version"]
+        .as_str()
+        .ok_or(JoatError::Config(String::from("Version not defined")))?;
     if app_name == env!("CARGO_PKG_NAME") {
-        version = String::from(config["version"].as_str().expect("Version not defined"));
+        version = String::from(app_version);
     } else {
         let joat_version = env!("CARGO_PKG_VERSION");
-        let app_version = config["version"].as_str().expect("Version not defined");
         version = format!("{} (joat {})", app_version, joat_version);
     }
-    let mut config_btree = config.into_hash().expect("Config yaml is not a hash");
+    let mut config_btree = get_imut_yaml_hash(config)?;
     let version_yaml = get_yaml_string("version");
     let version_value_yaml = get_yaml_string(&version);
     config_btree.insert(version_yaml, version_value_yaml);
-    Yaml::Hash(config_btree)
+    Ok(Yaml::Hash(config_btree))
 }
 
-fn create_default_config() {
-    let yaml_string = String::from(include_str!("../joat.yml"));
-    let home_dir_path = match dirs::home_dir() {
-        Some(h) => h,
-        _ => panic!("No home dir"),
-    };
-    let home_dir_str = home_dir_path.into_os_string().into_string().unwrap();
-    let joat_config_path = format!("{}/.joat.joat", home_dir_str);
-    match fs::create_dir_all(&joat_config_path) {
-        Ok(_v) => (),
-        Err(e) => panic!("Could not create config folder {:?}", e),
-    };
-
-    let filename = format!("{}/joat.yml", joat_config_path);
-    fs::write(filename, &yaml_string).expect("Unable to write file");
+///
+/// Merges the config layers of the app, see layers::get_config_layers.
+///
+pub fn get_yaml_config(app_name: &String, explicit_config: Option<String>) -> error::Result<Yaml> {
+    let config_layers = layers::get_config_layers(app_name, explicit_config)?;
+    let mut combined_config: Option<Yaml> = None;
+    let mut loaded_layers = Vec::new();
+    for layer in config_layers {
+        debug!("Loading {} config {:?}", layer.kind, layer.path);
+        let current_config = match get_yaml_from(&layer)? {
+            Some(c) => c,
+            None => continue,
+        };
+        // Layers come from the lowest priority, the current one overrides the combined ones
+        combined_config = match combined_config {
+            Some(c) => Some(combine_scmd_yaml(&current_config, &c)),
+            None => Some(current_config),
+        };
+        loaded_layers.push(layer);
+    }
+    layers::set_loaded_layers(loaded_layers);
+
+    let partial_config = combined_config.ok_or(JoatError::Config(format!(
+        "Could not find a {} config file in /etc/joat/{}, $XDG_CONFIG_HOME/{}, ~/.{}.joat, the current dir or its ancestors",
+        app_name, app_name, app_name, app_name
+    )))?;
+    let config = merge::resolve(partial_config);
+    check_request_subcommands(&config)?;
+    let config = override_version(app_name, config)?;
+    add_default_options(config)
 }
 
-pub fn get_yaml_config(app_name: &String) -> Yaml {
-    let config_vec = get_config_vec(app_name);
-    let mut combined_config: Option<Yaml> = None;
-    debug!("Config vec {:?}", config_vec);
-    for current_config in config_vec {
-        match combined_config {
-            Some(c) => {
-                combined_config = Some(combine_scmd_yaml(&c, &current_config));
-            }
-            None => {
-                combined_config = Some(current_config);
+fn collect_pathless_subcommands(scmds: &Yaml, path: &str, errors: &mut Vec<String>) {
+    for scmd in scmds.as_vec().map_or(&[][..], |v| &v[..]) {
+        for (name, options) in scmd.as_hash().into_iter().flatten() {
+            let scmd_path = format!("{}.{}", path, get_key_str(name));
+            if !options["subcommands"].is_badvalue() {
+                let child_path = format!("{}.subcommands", scmd_path);
+                collect_pathless_subcommands(&options["subcommands"], &child_path, errors);
+            } else if options["path"].is_badvalue() && options["script"].is_badvalue() {
+                errors.push(format!(
+                    "{}: error: Request subcommand `{}` has no path or script in any config layer",
+                    explain::get_recorded_source(&scmd_path),
+                    scmd_path
+                ));
             }
         }
     }
+}
 
-    let partial_config = match combined_config {
-        Some(c) => c,
-        None => {
-            if app_name == "joat" {
-                create_default_config();
-                return get_yaml_config(&app_name);
-            }
-            panic!("Could not find config file");
-        }
-    };
-    let config = override_version(app_name, partial_config);
-    add_default_options(config)
+///
+/// A layer can patch a subcommand without its `path` or `script` (lint only warns), the merged
+/// config needs one of them for every request subcommand.
+///
+fn check_request_subcommands(config: &Yaml) -> error::Result<()> {
+    // The joat subcommands run built-in code, see main::execute
+    if config["name"].as_str() == Some("joat") {
+        return Ok(());
+    }
+    let mut errors = Vec::new();
+    collect_pathless_subcommands(&config["subcommands"], "subcommands", &mut errors);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(JoatError::Config(format!(
+        "{}\nAdd a `path:` (appended to base_endpoint) or a `script:`",
+        errors.join("\n")
+    )))
 }
 
-pub fn get_string_from_yaml(yaml: &Yaml) -> String {
-    match yaml.clone().into_string() {
-        Some(s) => s,
-        None => {
-            panic!("Failed to convert {:?} into string, exiting.", yaml);
-        }
+pub fn get_string_from_yaml(yaml: &Yaml) -> error::Result<String> {
+    yaml.clone()
+        .into_string()
+        .ok_or(get_config_error("Expected a string, found", yaml))
+}
+
+///
+/// A key or a name for messages and paths, other scalars (e.g. a `200:` key) are kept as written.
+///
+pub fn get_key_str(yaml: &Yaml) -> String {
+    match yaml {
+        Yaml::String(s) | Yaml::Real(s) => s.clone(),
+        Yaml::Integer(i) => i.to_string(),
+        Yaml::Boolean(b) => b.to_string(),
+        _ => format!("{:?}", yaml),
     }
 }
 
 fn get_value_from_yaml_hash(btree_map: &BTreeMap<Yaml, Yaml>, context: &Context) -> Value {
     let mut value_map = Map::new();
     for (key, value) in btree_map.iter() {
-        let key_str = get_string_from_yaml(key);
+        let key_str = get_key_str(key);
         let v_value = match get_value_from_yaml(value, context) {
             Some(v) => v,
             None => continue,
@@ -452,9 +1161,7 @@ fn get_value_from_yaml_string(yaml_str: &String, context: &Context) -> Option<Va
         Err(_e) => return None,
     };
     if templated_str == "true" || templated_str == "false" {
-        let bool_value =
-            FromStr::from_str(&templated_str).expect("Could not convert boolean value in body");
-        return Some(Value::Bool(bool_value));
+        return Some(Value::Bool(templated_str == "true"));
     }
     let value = match serde_json::from_str::<Number>(&templated_str) {
         Ok(n) => Value::Number(n),
@@ -474,17 +1181,21 @@ pub fn get_value_from_yaml(yaml: &Yaml, context: &Context) -> Option<Value> {
     }
 }
 
-pub fn get_hash_from_yaml(yaml: &Yaml, context: &Context, deep: bool) -> HashMap<String, Value> {
+pub fn get_hash_from_yaml(
+    yaml: &Yaml,
+    context: &Context,
+    deep: bool,
+) -> error::Result<HashMap<String, Value>> {
     if yaml.is_badvalue() {
-        return HashMap::new();
+        return Ok(HashMap::new());
     }
     let yaml_btree = yaml
         .clone()
         .into_hash()
-        .expect("Yaml should be a map value");
+        .ok_or(get_config_error("Expected a map, found", yaml))?;
     let mut yaml_hash = HashMap::new();
     for (key, value) in yaml_btree.iter() {
-        let str_key = get_string_from_yaml(key);
+        let str_key = get_string_from_yaml(key)?;
         if deep {
             let v_value = match get_value_from_yaml(value, context) {
                 Some(v) => v,
@@ -494,7 +1205,12 @@ pub fn get_hash_from_yaml(yaml: &Yaml, context: &Context, deep: bool) -> HashMap
         } else {
             let raw_string = match value {
                 Yaml::String(v) => v,
-                _ => panic!("Only string values are allowed for {}", str_key),
+                _ => {
+                    return Err(JoatError::Config(format!(
+                        "Only string values are allowed for {}",
+                        str_key
+                    )))
+                }
             };
             let str_option = template::get_compiled_template_str_with_context(&raw_string, context);
             let str_value = match str_option {
@@ -504,17 +1220,15 @@ pub fn get_hash_from_yaml(yaml: &Yaml, context: &Context, deep: bool) -> HashMap
             yaml_hash.insert(str_key, Value::String(str_value));
         }
     }
-    return yaml_hash;
+    return Ok(yaml_hash);
 }
 
-pub fn get_subcommand_from_yaml(cmd_name: &str, yaml: &Yaml) -> Yaml {
+pub fn get_subcommand_from_yaml(cmd_name: &str, yaml: &Yaml) -> error::Result<Yaml> {
     let subcommands = &yaml["subcommands"];
-    let subcommands_vec = match subcommands.clone().into_vec() {
-        Some(t) => t,
-        None => {
-            panic!("Failed to retrieve subcommands, exiting.");
-        }
-    };
+    let subcommands_vec = subcommands
+        .clone()
+        .into_vec()
+        .ok_or(JoatError::Config(String::from("No subcommands in config")))?;
     let cmd_name_yaml = Yaml::from_str(cmd_name);
     let scmd_option = subcommands_vec
         .iter()
@@ -522,13 +1236,11 @@ pub fn get_subcommand_from_yaml(cmd_name: &str, yaml: &Yaml) -> Yaml {
             Some(sl) => sl.contains_key(&cmd_name_yaml),
             None => false,
         });
-    let scmd_hash = match scmd_option {
-        Some(s) => s,
-        None => {
-            panic!("Failed to retrieve subcommands hash, exiting.");
-        }
-    };
-    return scmd_hash[cmd_name].clone();
+    let scmd_hash = scmd_option.ok_or(JoatError::Config(format!(
+        "Subcommand {} not found in config",
+        cmd_name
+    )))?;
+    return Ok(scmd_hash[cmd_name].clone());
 }
 
 #[cfg(test)]
@@ -581,17 +1293,20 @@ mod tests {
         let yaml_str = Yaml::String(sample_str.to_string());
 
         // Act, Assert
-        assert_eq!(sample_str, get_string_from_yaml(&yaml_str));
+        assert_eq!(sample_str, get_string_from_yaml(&yaml_str).unwrap());
     }
 
     #[test]
-    #[should_panic]
     fn test_get_string_from_yaml_non_str_yaml() {
         // Arrange
         let yaml_number = Yaml::Integer(1);
 
-        // Act, Assert
-        get_string_from_yaml(&yaml_number);
+        // Act
+        let result = get_string_from_yaml(&yaml_number);
+
+        // Assert
+        assert!(result.is_err());
+        assert_eq!(get_key_str(&yaml_number), "1");
     }
 
     #[test]
@@ -601,7 +1316,7 @@ mod tests {
         let scmd_about = "This is a sample scmd";
 
         // Act
-        let subcommand = get_subcommand_from_yaml("scmd2", &yaml);
+        let subcommand = get_subcommand_from_yaml("scmd2", &yaml).unwrap();
 
         // Assert
         let scmd_btree = subcommand.into_hash().expect("Could not cast to btree");
@@ -624,4 +1339,199 @@ mod tests {
         assert_eq!(result["name"], get_yaml_string(final_value));
         assert_ne!(result["name"], get_yaml_string(other_value));
     }
+
+    #[test]
+    fn test_split_include_pattern() {
+        // Act, Assert
+        assert_eq!(
+            split_include_pattern("resources/issues.yml"),
+            (PathBuf::from("resources/issues.yml"), None)
+        );
+        assert_eq!(
+            split_include_pattern("resources/*/api.yml"),
+            (PathBuf::from("resources"), Some(String::from("*/api.yml")))
+        );
+    }
+
+    #[test]
+    fn test_get_include_directive() {
+        // Arrange
+        let scmds = &YamlLoader::load_from_str(
+            "- include: resources/*.yml\n- include: [a.yml, b.yml]\n- include:\n    path: /include\n",
+        )
+        .unwrap()[0];
+
+        // Act, Assert
+        assert_eq!(
+            get_include_directive(&scmds[0]).as_str(),
+            Some("resources/*.yml")
+        );
+        assert!(get_include_directive(&scmds[1]).as_vec().is_some());
+        assert!(get_include_directive(&scmds[2]).is_badvalue());
+    }
+
+    #[test]
+    fn test_add_subcommands_path_keeps_defining_layer() {
+        // Arrange
+        let load = |s: &str| YamlLoader::load_from_str(s).unwrap().remove(0);
+        let home = load("subcommands:\n    - deploy: {script: ./deploy.sh}\n    - issue:\n        subcommands:\n            - show: {path: /issue}\n");
+        let project = load("subcommands:\n    - deploy: {timeout: 5}\n    - issue:\n        subcommands:\n            - show: {response_template: show.j2}\n            - new: {path: /new}\n");
+        let home = add_subcommands_path(home, "/home/").unwrap();
+        let project = add_subcommands_path(project, "/project/").unwrap();
+
+        // Act
+        let config = merge::resolve(combine_scmd_yaml(&project, &home));
+
+        // Assert
+        let scmds = &config["subcommands"];
+        let issue_scmds = &scmds[1]["issue"]["subcommands"];
+        assert_eq!(
+            scmds[0]["deploy"]["scmd_config_base_path"].as_str(),
+            Some("/home/")
+        );
+        assert_eq!(scmds[0]["deploy"]["timeout"].as_i64(), Some(5));
+        assert_eq!(
+            issue_scmds[0]["show"]["scmd_config_base_path"].as_str(),
+            Some("/home/")
+        );
+        assert_eq!(
+            issue_scmds[1]["new"]["scmd_config_base_path"].as_str(),
+            Some("/project/")
+        );
+    }
+
+    #[test]
+    fn test_check_request_subcommands() {
+        // Arrange
+        let load = |s: &str| YamlLoader::load_from_str(s).unwrap().remove(0);
+        let home = load("subcommands:\n    - ci:\n        subcommands:\n            - pipelines: {path: /pipelines}\n            - jobs: {}\n");
+        let project = load("subcommands:\n    - ci:\n        subcommands:\n            - pipelines: {timeout: 5}\n");
+        explain::record_layer(&home, Path::new("/home/u/.gl.joat/gl.yml"));
+        explain::record_layer(&project, Path::new("/work/.gl.joat/gl.yml"));
+
+        // Act
+        let result = check_request_subcommands(&merge::resolve(combine_scmd_yaml(&project, &home)));
+
+        // Assert
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("/home/u/.gl.joat/gl.yml: error: Request subcommand `subcommands.ci.subcommands.jobs` has no path"));
+        assert!(!message.contains("pipelines"));
+    }
+
+    #[test]
+    fn test_merge_included_config() {
+        // Arrange
+        let config = YamlLoader::load_from_str("name: demo\nsubcommands:\n    - a: {}\n")
+            .unwrap()
+            .remove(0);
+        let included =
+            YamlLoader::load_from_str("name: other\nvars: {x: 1}\nsubcommands:\n    - b: {}\n")
+                .unwrap()
+                .remove(0);
+
+        // Act
+        let merged = merge_included_config(config, included).unwrap();
+
+        // Assert
+        let scmd_names: Vec<String> = merged["subcommands"]
+            .as_vec()
+            .unwrap()
+            .iter()
+            .map(|s| get_key_str(s.as_hash().unwrap().keys().next().unwrap()))
+            .collect();
+        assert_eq!(merged["name"].as_str(), Some("demo"));
+        assert_eq!(merged["vars"]["x"].as_i64(), Some(1));
+        assert_eq!(scmd_names, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_add_default_options_keeps_extension_groups() {
+        // Arrange
+        let config = YamlLoader::load_from_str(
+            "subcommands:\n    - config:\n        subcommands:\n            - show: {path: /config}\n",
+        )
+        .unwrap()
+        .remove(0);
+
+        // Act
+        let result = add_default_options(config).unwrap();
+
+        // Assert
+        let scmds = result["subcommands"].as_vec().unwrap();
+        let get_scmd = |name: &str| scmds.iter().find(|s| !s[name].is_badvalue()).unwrap();
+        assert!(!is_builtin_group(&get_scmd("config")["config"]));
+        assert!(is_builtin_group(&get_scmd("secrets")["secrets"]));
+    }
+
+    #[test]
+    fn test_add_default_options_rejects_reserved_args() {
+        // Arrange
+        let load = |s: &str| YamlLoader::load_from_str(s).unwrap().remove(0);
+        let nested = load(
+            "subcommands:\n    - app:\n        subcommands:\n            - deploy:\n                path: /d\n                args:\n                    - config: {long: config, takes_value: true}\n",
+        );
+        let top_level = load(
+            "args:\n    - format: {long: error-format}\nsubcommands:\n    - show: {path: /s}\n",
+        );
+
+        // Act
+        let nested_result = add_default_options(nested);
+        let top_level_result = add_default_options(top_level);
+
+        // Assert
+        assert!(nested_result
+            .unwrap_err()
+            .to_string()
+            .contains("`config` is reserved"));
+        assert!(top_level_result
+            .unwrap_err()
+            .to_string()
+            .contains("`error_format` is reserved"));
+    }
+
+    #[test]
+    fn test_add_scmds_default_options_inherits_group_options() {
+        // Arrange
+        let config = YamlLoader::load_from_str(
+            "subcommands:
+    - issue:
+        vars: {kind: issue, scope: group}
+        headers: {X-Group: g}
+        args:
+            - project: {short: p, takes_value: true}
+            - GROUP: {index: 1}
+        subcommands:
+            - show:
+                vars: {scope: show}
+                path: /issues
+",
+        )
+        .unwrap()
+        .remove(0);
+        let mut scmds = config["subcommands"].as_vec().unwrap().clone();
+
+        // Act
+        add_scmds_default_options(&mut scmds, None).unwrap();
+
+        // Assert
+        let group = &scmds[0]["issue"];
+        let show = &group["subcommands"].as_vec().unwrap()[0]["show"];
+        let arg_names: Vec<String> = show["args"]
+            .as_vec()
+            .unwrap()
+            .iter()
+            .map(|a| get_key_str(get_arg_name(a).unwrap()))
+            .collect();
+        assert_eq!(show["vars"]["kind"].as_str(), Some("issue"));
+        assert_eq!(show["vars"]["scope"].as_str(), Some("show"));
+        assert_eq!(show["headers"]["X-Group"].as_str(), Some("g"));
+        assert_eq!(
+            arg_names,
+            vec!["project", "template", "quiet", "raw_response"]
+        );
+        assert_eq!(
+            group["settings"].as_vec().unwrap()[0].as_str(),
+            Some("SubcommandRequiredElseHelp")
+        );
+    }
 }